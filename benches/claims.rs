@@ -0,0 +1,51 @@
+// Compares the map behind Claims and Header with a BTreeMap, for a typical
+// dozen claims. Run with `cargo bench --bench claims`.
+extern crate jws;
+extern crate serde_json;
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use jws::Claims;
+use serde_json::Value;
+
+const ROUNDS: u32 = 200000;
+
+fn main() {
+    let keys: Vec<String> = (0..12).map(|i| format!("claim_{:02}", i)).collect();
+
+    let mut btree_build = Duration::new(0, 0);
+    let mut btree_lookup = Duration::new(0, 0);
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        let mut map = BTreeMap::new();
+        for key in keys.iter() {
+            map.insert(key.clone(), Value::U64(1));
+        }
+        btree_build += start.elapsed();
+        let start = Instant::now();
+        for key in keys.iter() {
+            assert!(map.contains_key(key));
+        }
+        btree_lookup += start.elapsed();
+    }
+
+    let mut claims_build = Duration::new(0, 0);
+    let mut claims_lookup = Duration::new(0, 0);
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        let mut claims = Claims::new();
+        for key in keys.iter() {
+            claims.set(key, 1);
+        }
+        claims_build += start.elapsed();
+        let start = Instant::now();
+        for key in keys.iter() {
+            assert!(claims.has(key));
+        }
+        claims_lookup += start.elapsed();
+    }
+
+    println!("{} rounds of {} claims", ROUNDS, keys.len());
+    println!("BTreeMap build {:?}, lookup {:?}", btree_build, btree_lookup);
+    println!("Claims   build {:?}, lookup {:?}", claims_build, claims_lookup);
+}
@@ -0,0 +1,38 @@
+// Compares RS256 verification from raw key bytes, which parses the public key
+// on every call, with a DecodingKey built once and reused.
+// Run with `cargo bench --bench verify`.
+extern crate jws;
+extern crate openssl;
+
+use std::time::Instant;
+use jws::{Claims, DecodingKey, Header, JWS, RsaKey, Validation, ALGORITHM};
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+
+const ROUNDS: u32 = 5000;
+
+fn main() {
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let public = key.public_key_to_pem().unwrap();
+    let mut header = Header::new();
+    header.alg = ALGORITHM::RS256;
+    let token = JWS::from_claims(header, Claims::new()).encode(&key.private_key_to_pem_pkcs8().unwrap(), ALGORITHM::RS256).unwrap();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        JWS::decode_jwt(token.clone(), &public, ALGORITHM::RS256).unwrap();
+    }
+    let raw = start.elapsed();
+
+    let decoding = DecodingKey::from_key(&RsaKey::new(&public).unwrap(), ALGORITHM::RS256).unwrap();
+    let validation = Validation::new();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        JWS::decode_jwt_with_key(token.clone(), &decoding, &validation).unwrap();
+    }
+    let staged = start.elapsed();
+
+    println!("{} RS256 verifications", ROUNDS);
+    println!("raw key bytes    {:?} ({:?} each)", raw, raw / ROUNDS);
+    println!("staged key       {:?} ({:?} each)", staged, staged / ROUNDS);
+}
@@ -0,0 +1,121 @@
+// Writes a directory of tokens, keys and expected outcomes for cross-language
+// conformance tests: `cargo run --example conformance_fixtures -- <out-dir>`.
+extern crate jws;
+extern crate openssl;
+extern crate serde_json;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use jws::{Claims, Header, JWS, ALGORITHM};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::rsa::Rsa;
+use serde_json::Value;
+
+fn write(dir: &Path, name: &str, contents: &[u8]) -> String {
+    let mut file = fs::File::create(dir.join(name)).expect("cannot create fixture file");
+    file.write_all(contents).expect("cannot write fixture file");
+    name.to_owned()
+}
+
+fn outcome(token: &str, key: &[u8], alg: ALGORITHM) -> String {
+    match JWS::decode_jwt(token.to_owned(), key, alg) {
+        Ok(_) => "valid".to_owned(),
+        Err(err) => err.reject_reason().as_str().to_owned()
+    }
+}
+
+fn tamper(token: &str) -> String {
+    let parts: Vec<&str> = token.split('.').collect();
+    let mut claims = Claims::new();
+    claims.sub = Some("mallory".to_owned());
+    let forged = JWS::from_claims(Header::new(), claims).encode(b"a 32-byte key only used to forge", ALGORITHM::HS256).unwrap();
+    format!("{}.{}.{}", parts[0], forged.split('.').nth(1).unwrap(), parts[2])
+}
+
+fn fixture(name: &str, alg: &ALGORITHM, private_file: &str, public_file: &str, token: String, expected: String) -> Value {
+    let mut entry = BTreeMap::new();
+    entry.insert("name".to_owned(), Value::String(name.to_owned()));
+    entry.insert("alg".to_owned(), Value::String(alg.name().to_owned()));
+    entry.insert("private_key".to_owned(), Value::String(private_file.to_owned()));
+    entry.insert("public_key".to_owned(), Value::String(public_file.to_owned()));
+    entry.insert("token".to_owned(), Value::String(token));
+    entry.insert("expected".to_owned(), Value::String(expected));
+    Value::Object(entry)
+}
+
+fn pem_pair(key: &PKey<Private>) -> (Vec<u8>, Vec<u8>) {
+    (key.private_key_to_pem_pkcs8().unwrap(), key.public_key_to_pem().unwrap())
+}
+
+fn main() {
+    let out = env::args().nth(1).unwrap_or_else(|| "conformance".to_owned());
+    let dir = Path::new(&out);
+    fs::create_dir_all(dir).expect("cannot create output directory");
+
+    let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let ec = |nid| PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(nid).unwrap()).unwrap()).unwrap();
+    let k1 = ec(Nid::SECP256K1);
+    let ed25519 = PKey::generate_ed25519().unwrap();
+    let ed448 = PKey::generate_ed448().unwrap();
+    assert_eq!(ed448.id(), Id::ED448);
+
+    let mut keys: Vec<(&str, Vec<ALGORITHM>, Vec<u8>, Vec<u8>)> = vec![
+        ("oct", vec![ALGORITHM::HS256, ALGORITHM::HS384, ALGORITHM::HS512],
+         b"conformance-fixture-secret-0123456789abcdef0123456789abcdef01234".to_vec(), Vec::new()),
+    ];
+    let (private, public) = pem_pair(&rsa);
+    keys.push(("rsa", vec![ALGORITHM::RS256, ALGORITHM::RS384, ALGORITHM::RS512,
+                           ALGORITHM::PS256, ALGORITHM::PS384, ALGORITHM::PS512], private, public));
+    for &(name, nid, ref alg) in [("p256", Nid::X9_62_PRIME256V1, ALGORITHM::ES256),
+                                  ("p384", Nid::SECP384R1, ALGORITHM::ES384),
+                                  ("p521", Nid::SECP521R1, ALGORITHM::ES512)].iter() {
+        let (private, public) = pem_pair(&ec(nid));
+        keys.push((name, vec![alg.clone()], private, public));
+    }
+    let (private, public) = pem_pair(&k1);
+    keys.push(("secp256k1", vec![ALGORITHM::ES256K], private, public));
+    let (private, public) = pem_pair(&ed25519);
+    keys.push(("ed25519", vec![ALGORITHM::EdDSA], private, public));
+    let (private, public) = pem_pair(&ed448);
+    keys.push(("ed448", vec![ALGORITHM::EdDSA], private, public));
+
+    let mut fixtures = Vec::new();
+    for (key_name, algs, private, public) in keys {
+        let symmetric = public.is_empty();
+        let private_file = write(dir, &format!("{}.key", key_name), &private);
+        let public_file = if symmetric { private_file.clone() } else { write(dir, &format!("{}.pub", key_name), &public) };
+        let verify_key = if symmetric { private.clone() } else { public.clone() };
+
+        for alg in algs {
+            let prefix = format!("{}-{}", key_name, alg.name());
+            let mut header = Header::new();
+            header.alg = alg.clone();
+            let mut claims = Claims::new();
+            claims.iss = Some("https://issuer.example".to_owned());
+            claims.sub = Some("alice".to_owned());
+            claims.set("unicode", "\u{1F511} ü 日本");
+            let token = JWS::from_claims(header, claims).encode(&private, alg.clone()).unwrap();
+
+            let cases = vec![
+                ("valid", token.clone()),
+                ("tampered-payload", tamper(&token)),
+                ("empty-signature", format!("{}.", token.rsplitn(2, '.').nth(1).unwrap())),
+                ("truncated", token.splitn(3, '.').take(2).collect::<Vec<&str>>().join(".")),
+            ];
+            for (case, token) in cases {
+                let expected = outcome(&token, &verify_key, alg.clone());
+                fixtures.push(fixture(&format!("{}-{}", prefix, case), &alg, &private_file, &public_file, token, expected));
+            }
+        }
+    }
+
+    let manifest = serde_json::to_string_pretty(&Value::Array(fixtures)).unwrap();
+    write(dir, "manifest.json", manifest.as_bytes());
+    println!("wrote {}", dir.join("manifest.json").display());
+}
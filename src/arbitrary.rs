@@ -0,0 +1,108 @@
+#![allow(dead_code)]
+
+use proptest::prelude::*;
+use proptest::collection::{btree_map, vec};
+use proptest::option;
+use serde_json::Value;
+
+use claims::Claims;
+use jws_header::{Header, ALGORITHM};
+
+pub fn arb_key() -> BoxedStrategy<String> {
+    "\\PC{1,16}".boxed()
+}
+
+pub fn arb_value() -> BoxedStrategy<Value> {
+    let leaf = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::Bool),
+        (::std::i64::MIN..0i64).prop_map(Value::I64),
+        any::<u64>().prop_map(Value::U64),
+        any::<String>().prop_map(Value::String),
+    ];
+    leaf.prop_recursive(8, 64, 8, |inner| prop_oneof![
+        vec(inner.clone(), 0..8).prop_map(Value::Array),
+        btree_map(arb_key(), inner, 0..8).prop_map(Value::Object),
+    ]).boxed()
+}
+
+pub fn arb_algorithm() -> BoxedStrategy<ALGORITHM> {
+    prop_oneof![
+        Just(ALGORITHM::HS256),
+        Just(ALGORITHM::HS384),
+        Just(ALGORITHM::HS512),
+        Just(ALGORITHM::RS256),
+        Just(ALGORITHM::RS384),
+        Just(ALGORITHM::RS512),
+        Just(ALGORITHM::PS256),
+        Just(ALGORITHM::PS384),
+        Just(ALGORITHM::PS512),
+        Just(ALGORITHM::EdDSA),
+        Just(ALGORITHM::ES256),
+        Just(ALGORITHM::ES384),
+        Just(ALGORITHM::ES512),
+        Just(ALGORITHM::ES256K),
+    ].boxed()
+}
+
+pub fn arb_hmac_algorithm() -> BoxedStrategy<ALGORITHM> {
+    prop_oneof![
+        Just(ALGORITHM::HS256),
+        Just(ALGORITHM::HS384),
+        Just(ALGORITHM::HS512),
+    ].boxed()
+}
+
+pub fn arb_header() -> BoxedStrategy<Header> {
+    (arb_algorithm(),
+     option::of(any::<String>()),
+     option::of(any::<String>()),
+     option::of(any::<String>()),
+     option::of(any::<String>()),
+     option::of(any::<String>()),
+     option::of(vec(any::<u8>(), 0..32)),
+     option::of(vec(any::<u8>(), 0..32)),
+     btree_map(arb_key(), arb_value(), 0..8))
+        .prop_map(|(alg, typ, jku, kid, x5u, x5t, apu, apv, values)| {
+            let mut header = Header::new();
+            header.alg = alg;
+            header.typ = typ;
+            header.jku = jku;
+            header.kid = kid;
+            header.x5u = x5u;
+            header.x5t = x5t;
+            header.apu = apu;
+            header.apv = apv;
+            for (key, value) in values {
+                header.set(&key, value);
+            }
+            header
+        })
+        .boxed()
+}
+
+pub fn arb_claims() -> BoxedStrategy<Claims> {
+    (option::of(any::<String>()),
+     option::of(any::<String>()),
+     option::of(any::<String>()),
+     option::of(any::<u64>()),
+     option::of(any::<u64>()),
+     option::of(any::<u64>()),
+     option::of(any::<String>()),
+     btree_map(arb_key(), arb_value(), 0..16))
+        .prop_map(|(iss, sub, aud, exp, nbf, iat, jti, values)| {
+            let mut claims = Claims::new();
+            claims.iss = iss;
+            claims.sub = sub;
+            claims.aud = aud.map(Into::into);
+            claims.exp = exp;
+            claims.nbf = nbf;
+            claims.iat = iat;
+            claims.jti = jti;
+            for (key, value) in values {
+                claims.set(&key, value);
+            }
+            claims
+        })
+        .boxed()
+}
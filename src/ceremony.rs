@@ -0,0 +1,92 @@
+#![allow(dead_code)]
+
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::{PKey, Public};
+use claims::Claims;
+use clock::Clock;
+use jws::{JWS, base64_url_encode_bytes};
+use jws_header::{Header, ALGORITHM};
+use error::{Error, Result};
+use signing;
+
+fn private_key(key: &[u8]) -> Result<PKey<::openssl::pkey::Private>> {
+    let loaded = if key.len() == 32 || key.len() == 57 {
+        signing::eddsa_key(key)
+    } else {
+        signing::private_key(key)
+    };
+    loaded.map_err(|err| Error::private_key_parse(key, err))
+}
+
+fn public_key(key: &[u8]) -> Result<PKey<Public>> {
+    if let Ok(public) = PKey::public_key_from_pem(key) {
+        return Ok(public);
+    }
+    signing::certificate_key(key).map_err(|err| Error::public_key_parse(key, err))
+}
+
+fn public_der_of(private: &[u8]) -> Result<Vec<u8>> {
+    Ok(try!(try!(private_key(private)).public_key_to_der()))
+}
+
+pub fn fingerprint(public_der: &[u8]) -> Result<String> {
+    let digest = try!(hash(MessageDigest::sha256(), public_der));
+    let hex: Vec<String> = digest.iter().map(|byte| format!("{:02X}", byte)).collect();
+    Ok(hex.join(":"))
+}
+
+pub fn export_public_pem(private: &[u8]) -> Result<String> {
+    let key = try!(private_key(private));
+    let der = try!(key.public_key_to_der());
+    let pem = try!(key.public_key_to_pem());
+    Ok(format!("# SHA-256 fingerprint: {}\n{}", try!(fingerprint(&der)), String::from_utf8_lossy(&pem)))
+}
+
+pub fn keys_correspond(private: &[u8], public_or_cert: &[u8]) -> Result<bool> {
+    let private = try!(private_key(private));
+    let public = try!(public_key(public_or_cert));
+    Ok(private.public_eq(&public))
+}
+
+pub fn key_announcement<C: Clock>(old_key: &[u8], alg: ALGORITHM, new_key: &[u8], clock: &C) -> Result<String> {
+    let new_der = match public_key(new_key) {
+        Ok(public) => try!(public.public_key_to_der()),
+        Err(_) => try!(public_der_of(new_key))
+    };
+    let old_der = try!(public_der_of(old_key));
+    let mut claims = Claims::new();
+    claims.iat = Some(clock.now());
+    claims.sub = Some(base64_url_encode_bytes(&try!(hash(MessageDigest::sha256(), &new_der))));
+    claims.set("announced_by", base64_url_encode_bytes(&try!(hash(MessageDigest::sha256(), &old_der))));
+    let mut header = Header::new();
+    header.alg = alg.clone();
+    JWS::from_claims(header, claims).encode(old_key, alg)
+}
+
+#[test]
+fn public_halves_are_exported_with_a_fingerprint_banner() {
+    let exported = export_public_pem(&[1u8; 32]).unwrap();
+    let mut lines = exported.lines();
+    let banner = lines.next().unwrap();
+    assert!(banner.starts_with("# SHA-256 fingerprint: "));
+    assert_eq!(banner.split(": ").nth(1).unwrap().split(':').count(), 32);
+    assert_eq!(lines.next(), Some("-----BEGIN PUBLIC KEY-----"));
+
+    let pem = exported.split_once('\n').map(|(_, rest)| rest).unwrap();
+    assert!(keys_correspond(&[1u8; 32], pem.as_bytes()).unwrap());
+    assert!(!keys_correspond(&[2u8; 32], pem.as_bytes()).unwrap());
+}
+
+#[test]
+fn key_announcements_are_signed_by_the_old_key() {
+    use clock::FixedClock;
+
+    let old_key = [1u8; 32];
+    let new_key = [2u8; 32];
+    let announcement = key_announcement(&old_key, ALGORITHM::EdDSA, &new_key, &FixedClock(1000)).unwrap();
+    let old_public = signing::eddsa_key(&old_key).unwrap().raw_public_key().unwrap();
+    let (_, claims) = JWS::decode_jwt(announcement, &old_public, ALGORITHM::EdDSA).unwrap();
+    let new_der = signing::eddsa_key(&new_key).unwrap().public_key_to_der().unwrap();
+    assert_eq!(claims.sub, Some(base64_url_encode_bytes(&hash(MessageDigest::sha256(), &new_der).unwrap())));
+    assert_eq!(claims.iat, Some(1000));
+}
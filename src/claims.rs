@@ -5,24 +5,153 @@ use serde;
 use serde_json;
 use serde::Serialize;
 use serde_json::{Value, to_value, from_value};
+use clock::{Clock, SystemClock};
 use std::result;
-use error::Result;
+use value_map::ValueMap;
+use trace::TraceContext;
+use error::{Error, Result};
 
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Claims {
     pub iss: Option<String>,
     pub sub: Option<String>,
-    pub aud: Option<String>,
+    pub aud: Option<Audience>,
     pub exp: Option<u64>,
     pub nbf: Option<u64>,
     pub iat: Option<u64>,
     pub jti: Option<String>,
-    claims: BTreeMap<String, Value>,
+    claims: ValueMap,
+}
+
+// RFC 7519 allows aud to be a single string or an array; the form a token
+// arrived in is kept, so re-encoding it gives the same JSON.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Audience {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl Audience {
+    pub fn contains(&self, audience: &str) -> bool {
+        self.iter().any(|aud| aud == audience)
+    }
+
+    pub fn iter<'a>(&'a self) -> ::std::slice::Iter<'a, String> {
+        match *self {
+            Audience::Single(ref aud) => ::std::slice::from_ref(aud).iter(),
+            Audience::Multiple(ref auds) => auds.iter(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a> From<&'a str> for Audience {
+    fn from(aud: &'a str) -> Audience {
+        Audience::Single(aud.to_owned())
+    }
+}
+
+impl From<String> for Audience {
+    fn from(aud: String) -> Audience {
+        Audience::Single(aud)
+    }
+}
+
+impl From<Vec<String>> for Audience {
+    fn from(auds: Vec<String>) -> Audience {
+        Audience::Multiple(auds)
+    }
+}
+
+impl Serialize for Audience {
+    fn serialize<S>(&self, serializer: &mut S) -> result::Result<(), S::Error>
+        where S: serde::Serializer
+    {
+        match *self {
+            Audience::Single(ref aud) => aud.serialize(serializer),
+            Audience::Multiple(ref auds) => auds.serialize(serializer),
+        }
+    }
+}
+
+impl serde::Deserialize for Audience {
+    fn deserialize<D>(deserializer: &mut D) -> result::Result<Audience, D::Error>
+        where D: serde::Deserializer
+    {
+        match try!(Value::deserialize(deserializer)) {
+            Value::String(aud) => Ok(Audience::Single(aud)),
+            Value::Array(auds) => auds.into_iter()
+                .map(|aud| match aud {
+                    Value::String(aud) => Ok(aud),
+                    _ => Err(serde::de::Error::custom("aud must be a string or an array of strings"))
+                })
+                .collect::<result::Result<Vec<String>, D::Error>>()
+                .map(Audience::Multiple),
+            _ => Err(serde::de::Error::custom("aud must be a string or an array of strings"))
+        }
+    }
 }
 
 const RESERVED_CLAIMS: [&'static str; 7] = ["iss", "sub", "aud", "exp", "nbf", "iat", "jti"];
 
+#[derive(Debug, PartialEq, Clone)]
+pub enum ClaimsWarning {
+    Expired,
+    ExpiresTooLate { max_lifetime: u64 },
+    MissingIssuer,
+    MissingAudience,
+    IssuedInFuture,
+    CustomClaimsTooLarge { size: usize, limit: usize },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ClaimError {
+    Missing,
+    WrongType { found: &'static str },
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match *value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::I64(_) | Value::U64(_) | Value::F64(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LintOptions {
+    pub max_lifetime: u64,
+    pub max_custom_size: usize,
+}
+
+impl LintOptions {
+    pub fn new() -> LintOptions {
+        LintOptions {
+            max_lifetime: 30 * 24 * 60 * 60,
+            max_custom_size: 4 * 1024,
+        }
+    }
+}
+
+fn namespaced_key(ns_uri: &str, name: &str) -> String {
+    if ns_uri.ends_with('/') {
+        format!("{}{}", ns_uri, name)
+    } else {
+        format!("{}/{}", ns_uri, name)
+    }
+}
+
 impl Serialize for Claims {
     fn serialize<S>(&self, serializer: &mut S) -> result::Result<(), S::Error>
         where S: serde::Serializer
@@ -43,7 +172,7 @@ impl Serialize for Claims {
         }
         if let Some(ref aud) = self.aud {
             try!(serializer.serialize_map_key(&mut state, "aud"));
-            try!(serializer.serialize_map_value(&mut state, aud.as_str()));
+            try!(serializer.serialize_map_value(&mut state, aud));
         }
         if let Some(ref exp) = self.exp {
             try!(serializer.serialize_map_key(&mut state, "exp"));
@@ -114,7 +243,7 @@ impl serde::de::Visitor for ClaimsVisitor {
         let mut nbf = None;
         let mut iat = None;
         let mut jti = None;
-        let mut claims = BTreeMap::new();
+        let mut claims = ValueMap::with_insertion_order();
 
         while let Some(key) = try!(visitor.visit_key()) {
             match key {
@@ -166,7 +295,20 @@ impl Claims {
             nbf: None,
             iat: None,
             jti: None,
-            claims: BTreeMap::new(),
+            claims: ValueMap::new(),
+        }
+    }
+
+    pub fn with_insertion_order() -> Claims {
+        Claims {
+            iss: None,
+            sub: None,
+            aud: None,
+            exp: None,
+            nbf: None,
+            iat: None,
+            jti: None,
+            claims: ValueMap::with_insertion_order(),
         }
     }
 
@@ -176,10 +318,149 @@ impl Claims {
         }
     }
 
+    pub fn has(&self, key: &str) -> bool {
+        match key {
+            "iss" => self.iss.is_some(),
+            "sub" => self.sub.is_some(),
+            "aud" => self.aud.is_some(),
+            "exp" => self.exp.is_some(),
+            "nbf" => self.nbf.is_some(),
+            "iat" => self.iat.is_some(),
+            "jti" => self.jti.is_some(),
+            _ => self.claims.get(key).is_some()
+        }
+    }
+
     pub fn get<T: serde::de::Deserialize>(&self, key: &str) -> Option<T> {
        self.claims.get(key).and_then(|v| from_value(v.clone()).ok())
     }
 
+    pub fn try_get<T: serde::de::Deserialize>(&self, key: &str) -> result::Result<T, ClaimError> {
+        match self.claims.get(key) {
+            Some(value) => from_value(value.clone()).map_err(|_| ClaimError::WrongType { found: json_type(value) }),
+            None => Err(ClaimError::Missing)
+        }
+    }
+
+    pub fn set_namespaced<T: Serialize>(&mut self, ns_uri: &str, name: &str, value: T) {
+        self.set(&namespaced_key(ns_uri, name), value);
+    }
+
+    pub fn get_namespaced<T: serde::de::Deserialize>(&self, ns_uri: &str, name: &str) -> Option<T> {
+        self.get(&namespaced_key(ns_uri, name))
+    }
+
+    pub fn extract_namespaced<T: serde::de::Deserialize>(&self, ns_uri: &str) -> Option<T> {
+        let prefix = namespaced_key(ns_uri, "");
+        let values: BTreeMap<String, Value> = self.claims.iter()
+            .filter(|&(key, _)| key.starts_with(prefix.as_str()))
+            .map(|(key, value)| (key[prefix.len()..].to_owned(), value.clone()))
+            .collect();
+        from_value(Value::Object(values)).ok()
+    }
+
+    pub fn set_trace_context(&mut self, context: &TraceContext) {
+        self.set("traceparent", context.traceparent());
+        match context.tracestate {
+            Some(ref tracestate) => self.set("tracestate", tracestate.as_str()),
+            None => { self.claims.remove("tracestate"); }
+        }
+    }
+
+    pub fn trace_context(&self) -> Option<TraceContext> {
+        let traceparent: Option<String> = self.get("traceparent");
+        let tracestate: Option<String> = self.get("tracestate");
+        traceparent.and_then(|traceparent| TraceContext::parse(&traceparent, tracestate.as_ref().map(|s| s.as_str())))
+    }
+
+    pub fn extract<T: serde::de::Deserialize>(&self) -> Result<T> {
+        Ok(try!(from_value(Value::Object(self.claims.to_object()))))
+    }
+
+    pub fn inject<T: Serialize>(&mut self, value: &T) -> Result<()> {
+        match to_value(value) {
+            Value::Object(values) => {
+                for (key, value) in values.into_iter()
+                    .filter(|&(ref key, _)| !RESERVED_CLAIMS.contains(&key.as_str())) {
+                    self.claims.insert(key, value);
+                }
+                Ok(())
+            },
+            _ => {
+                let err: serde_json::Error = serde::de::Error::invalid_type(serde::de::Type::Map);
+                Err(err.into())
+            }
+        }
+    }
+
+    pub fn lint(&self) -> Vec<ClaimsWarning> {
+        self.lint_with_clock(&SystemClock)
+    }
+
+    pub fn lint_with_clock<C: Clock>(&self, clock: &C) -> Vec<ClaimsWarning> {
+        self.lint_at(clock.now(), &LintOptions::new())
+    }
+
+    pub fn lint_at(&self, now: u64, options: &LintOptions) -> Vec<ClaimsWarning> {
+        let mut warnings = Vec::new();
+        if let Some(exp) = self.exp {
+            if exp <= now {
+                warnings.push(ClaimsWarning::Expired);
+            } else if exp - now > options.max_lifetime {
+                warnings.push(ClaimsWarning::ExpiresTooLate { max_lifetime: options.max_lifetime });
+            }
+        }
+        if self.iss.is_none() {
+            warnings.push(ClaimsWarning::MissingIssuer);
+        }
+        if self.aud.is_none() {
+            warnings.push(ClaimsWarning::MissingAudience);
+        }
+        if let Some(iat) = self.iat {
+            if iat > now {
+                warnings.push(ClaimsWarning::IssuedInFuture);
+            }
+        }
+        let size = serde_json::to_vec(&self.claims.to_object()).map(|json| json.len()).unwrap_or(0);
+        if size > options.max_custom_size {
+            warnings.push(ClaimsWarning::CustomClaimsTooLarge { size: size, limit: options.max_custom_size });
+        }
+        warnings
+    }
+
+    // Kept apart from nbf so a skewed issuer clock can be told from a token that
+    // is not yet meant to be used.
+    pub fn check_issued_at(&self, now: u64, leeway: u64) -> Result<()> {
+        match self.iat {
+            Some(iat) if iat > now.saturating_add(leeway) => Err(Error::IssuedInFuture { skew: iat - now }),
+            _ => Ok(())
+        }
+    }
+
+    // Yields claims in the order they are serialized: custom claims, then
+    // registered ones.
+    pub fn iter_all(&self) -> ::std::vec::IntoIter<(String, Value)> {
+        let mut all: Vec<(String, Value)> = self.claims.iter()
+            .filter(|&(key, _)| !RESERVED_CLAIMS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        {
+            let mut push = |name: &str, value: Option<Value>| {
+                if let Some(value) = value {
+                    all.push((name.to_owned(), value));
+                }
+            };
+            push("iss", self.iss.as_ref().map(to_value));
+            push("sub", self.sub.as_ref().map(to_value));
+            push("aud", self.aud.as_ref().map(to_value));
+            push("exp", self.exp.as_ref().map(to_value));
+            push("nbf", self.nbf.as_ref().map(to_value));
+            push("iat", self.iat.as_ref().map(to_value));
+            push("jti", self.jti.as_ref().map(to_value));
+        }
+        all.into_iter()
+    }
+
     pub fn to_json(&self) -> Result<String> {
         Ok(try!(serde_json::to_string(self)))
     }
@@ -203,6 +484,17 @@ fn you_can_set_and_retrieve_custom_claims() {
     assert_eq!(result.unwrap(), 245);
 }
 
+#[test]
+fn has_covers_registered_and_custom_claims() {
+    let mut c = Claims::new();
+    assert!(!c.has("iss"));
+    c.iss = Some("Dyn".to_owned());
+    c.set("DOG", 245);
+    assert!(c.has("iss"));
+    assert!(c.has("DOG"));
+    assert!(!c.has("CAT"));
+}
+
 #[test]
 fn retrieving_a_custom_claim_that_is_not_set_returns_none() {
     let c = Claims::new();
@@ -210,12 +502,112 @@ fn retrieving_a_custom_claim_that_is_not_set_returns_none() {
     assert!(result.is_none());
 }
 
+#[test]
+fn namespaced_claims_are_stored_under_the_namespace_uri() {
+    let mut c = Claims::new();
+    c.set_namespaced("https://example.com/claims", "role", "admin");
+    c.set_namespaced("https://example.com/claims/", "tenant", 42);
+    let role: String = c.get("https://example.com/claims/role").unwrap();
+    assert_eq!(role, "admin");
+    let tenant: u64 = c.get_namespaced("https://example.com/claims", "tenant").unwrap();
+    assert_eq!(tenant, 42);
+}
+
+#[test]
+fn all_claims_under_a_namespace_can_be_extracted_together() {
+    let mut c = Claims::new();
+    c.set_namespaced("https://example.com/claims", "role", "admin");
+    c.set_namespaced("https://example.com/claims", "level", 3);
+    c.set("https://other.com/claims/role", "guest");
+    let ns: BTreeMap<String, Value> = c.extract_namespaced("https://example.com/claims").unwrap();
+    assert_eq!(ns.len(), 2);
+    assert_eq!(ns.get("role"), Some(&Value::String("admin".to_owned())));
+    assert_eq!(ns.get("level"), Some(&Value::U64(3)));
+}
+
+#[test]
+fn custom_claims_can_be_extracted_and_injected_in_bulk() {
+    let mut c = Claims::new();
+    c.iss = Some("Dyn".to_owned());
+    let mut custom = BTreeMap::new();
+    custom.insert("orgid".to_owned(), 1701);
+    custom.insert("idmid".to_owned(), 1);
+    custom.insert("iss".to_owned(), 5);
+    c.inject(&custom).unwrap();
+
+    let orgid: u64 = c.get("orgid").unwrap();
+    assert_eq!(orgid, 1701);
+    assert_eq!(c.iss, Some("Dyn".to_owned()));
+
+    let extracted: BTreeMap<String, u64> = c.extract().unwrap();
+    assert_eq!(extracted.len(), 2);
+    assert_eq!(extracted.get("idmid"), Some(&1));
+}
+
+#[test]
+fn injecting_a_non_object_is_an_error() {
+    let mut c = Claims::new();
+    assert!(c.inject(&42).is_err());
+}
+
+#[test]
+fn custom_claims_can_keep_their_insertion_order() {
+    let mut c = Claims::with_insertion_order();
+    c.set("zeta", 1);
+    c.set("alpha", 2);
+    assert_eq!(c.to_json().unwrap(), r#"{"zeta":1,"alpha":2}"#);
+
+    let decoded: Claims = serde_json::from_str(r#"{"zeta":1,"alpha":2,"iss":"Dyn"}"#).unwrap();
+    assert_eq!(decoded.to_json().unwrap(), r#"{"zeta":1,"alpha":2,"iss":"Dyn"}"#);
+}
+
+#[test]
+fn well_formed_claims_have_no_lint_warnings() {
+    let mut c = Claims::new();
+    c.iss = Some("https://issuer.example.com".to_owned());
+    c.aud = Some("api".into());
+    c.iat = Some(1000);
+    c.exp = Some(4600);
+    assert!(c.lint_at(1000, &LintOptions::new()).is_empty());
+    assert!(c.lint_with_clock(&::clock::FixedClock(1000)).is_empty());
+    assert_eq!(c.lint_with_clock(&::clock::FixedClock(5000)), vec![ClaimsWarning::Expired]);
+}
+
+#[test]
+fn lint_reports_common_issuer_mistakes() {
+    let mut c = Claims::new();
+    c.iat = Some(2000);
+    c.exp = Some(500);
+    c.set("blob", ::std::iter::repeat("x").take(64).collect::<String>());
+    let options = LintOptions { max_lifetime: 3600, max_custom_size: 32 };
+    let warnings = c.lint_at(1000, &options);
+    assert_eq!(warnings, vec![ClaimsWarning::Expired,
+                              ClaimsWarning::MissingIssuer,
+                              ClaimsWarning::MissingAudience,
+                              ClaimsWarning::IssuedInFuture,
+                              ClaimsWarning::CustomClaimsTooLarge { size: 75, limit: 32 }]);
+
+    c.exp = Some(1000 + 7200);
+    assert!(c.lint_at(1000, &options).contains(&ClaimsWarning::ExpiresTooLate { max_lifetime: 3600 }));
+}
+
+#[test]
+fn trace_context_can_be_carried_in_claims() {
+    let ctx = TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01", Some("rojo=1")).unwrap();
+    let mut c = Claims::new();
+    assert!(c.trace_context().is_none());
+    c.set_trace_context(&ctx);
+    let json = c.to_json().unwrap();
+    let decoded: Claims = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.trace_context(), Some(ctx));
+}
+
 #[test]
 fn claims_can_be_serialized_to_and_from_json_preserving_all_fields() {
     let mut h = Claims::new();
     h.iss = Some("WHERE".to_owned());
     h.sub = Some("KEY".to_owned());
-    h.aud = Some("X5U".to_owned());
+    h.aud = Some("X5U".into());
     h.exp = Some(2000);
     h.nbf = Some(3000);
     h.iat = Some(45000);
@@ -241,3 +633,62 @@ fn claims_can_be_serialized_to_and_from_json_preserving_all_fields() {
     assert_eq!(new_h.iat.unwrap(), h.iat.unwrap());
     assert_eq!(new_h.jti.unwrap(), h.jti.unwrap());
 }
+
+#[test]
+fn try_get_distinguishes_missing_from_wrong_type() {
+    let mut claims = Claims::new();
+    claims.set("tenant", 42);
+    assert_eq!(claims.try_get::<u64>("tenant"), Ok(42));
+    assert_eq!(claims.try_get::<String>("tenant"), Err(ClaimError::WrongType { found: "number" }));
+    assert_eq!(claims.try_get::<String>("absent"), Err(ClaimError::Missing));
+}
+
+#[test]
+fn an_audience_can_be_a_string_or_an_array() {
+    let mut claims = Claims::new();
+    claims.aud = Some("partner".into());
+    assert_eq!(claims.to_json().unwrap(), r#"{"aud":"partner"}"#);
+
+    claims.aud = Some(vec!["partner".to_owned()].into());
+    let json = claims.to_json().unwrap();
+    assert_eq!(json, r#"{"aud":["partner"]}"#);
+    let decoded: Claims = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.aud, Some(Audience::Multiple(vec!["partner".to_owned()])));
+
+    let decoded: Claims = serde_json::from_str(r#"{"aud":["a","b"]}"#).unwrap();
+    let aud = decoded.aud.clone().unwrap();
+    assert!(aud.contains("a") && aud.contains("b") && !aud.contains("c"));
+    assert_eq!(aud.len(), 2);
+    assert!(!aud.is_empty() && Audience::Multiple(Vec::new()).is_empty());
+    assert_eq!(decoded.to_json().unwrap(), r#"{"aud":["a","b"]}"#);
+
+    assert!(serde_json::from_str::<Claims>(r#"{"aud":["a",1]}"#).is_err());
+    assert!(serde_json::from_str::<Claims>(r#"{"aud":7}"#).is_err());
+}
+
+#[test]
+fn tokens_from_the_future_report_their_skew() {
+    let mut c = Claims::new();
+    assert!(c.check_issued_at(1000, 0).is_ok());
+    c.iat = Some(1030);
+    assert!(c.check_issued_at(1000, 30).is_ok());
+    assert_err!(c.check_issued_at(1000, 10), Error::IssuedInFuture { skew: 30 });
+    assert_eq!(c.check_issued_at(1000, 10).unwrap_err().reject_reason().as_str(), "clock_skew");
+    assert!(c.check_issued_at(1000, u64::max_value()).is_ok());
+}
+
+#[test]
+fn iter_all_walks_custom_and_registered_claims() {
+    let mut c = Claims::with_insertion_order();
+    c.set("role", "admin");
+    c.sub = Some("alice".to_owned());
+    c.aud = Some(vec!["api".to_owned()].into());
+    c.exp = Some(2000);
+    let all: Vec<(String, Value)> = c.iter_all().collect();
+    assert_eq!(all, vec![
+        ("role".to_owned(), Value::String("admin".to_owned())),
+        ("sub".to_owned(), Value::String("alice".to_owned())),
+        ("aud".to_owned(), Value::Array(vec![Value::String("api".to_owned())])),
+        ("exp".to_owned(), Value::U64(2000)),
+    ]);
+}
@@ -0,0 +1,64 @@
+#![allow(dead_code)]
+
+use time;
+
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        let now = time::get_time().sec;
+        if now > 0 { now as u64 } else { 0 }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
+
+// Shifts another clock, to simulate a skewed host or a point in the future.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct OffsetClock<C = SystemClock> {
+    pub clock: C,
+    pub offset: i64,
+}
+
+impl OffsetClock {
+    pub fn new(offset: i64) -> OffsetClock {
+        OffsetClock { clock: SystemClock, offset: offset }
+    }
+}
+
+impl<C: Clock> Clock for OffsetClock<C> {
+    fn now(&self) -> u64 {
+        let now = self.clock.now();
+        if self.offset < 0 {
+            now.saturating_sub(self.offset.unsigned_abs())
+        } else {
+            now.saturating_add(self.offset as u64)
+        }
+    }
+}
+
+#[test]
+fn offset_clocks_shift_the_underlying_clock() {
+    assert_eq!(OffsetClock { clock: FixedClock(1000), offset: 300 }.now(), 1300);
+    assert_eq!(OffsetClock { clock: FixedClock(1000), offset: -300 }.now(), 700);
+    assert_eq!(OffsetClock { clock: FixedClock(10), offset: -300 }.now(), 0);
+}
+
+#[test]
+fn fixed_clock_always_reports_the_same_time() {
+    let clock = FixedClock(1457396280);
+    assert_eq!(clock.now(), 1457396280);
+    assert_eq!(clock.now(), clock.now());
+}
@@ -0,0 +1,34 @@
+#![allow(dead_code)]
+
+use jws::{JWS, ParsedToken};
+use jws_header::ALGORITHM;
+use error::Result;
+
+pub fn encode(jws: &JWS, secret: &[u8], alg: ALGORITHM) -> Result<String> {
+    jws.encode(secret, alg)
+}
+
+pub fn decode(value: String, secret: &[u8], algorithm: ALGORITHM, decode_claims: bool) -> Result<JWS> {
+    let parsed = try!(ParsedToken::parse(value.as_str()));
+    if decode_claims {
+        parsed.verify(secret, algorithm)
+    } else {
+        parsed.verify_raw(secret, algorithm)
+    }
+}
+
+#[test]
+fn compat_wrappers_keep_the_old_signatures() {
+    use claims::Claims;
+    use jws_header::Header;
+
+    let mut claims = Claims::new();
+    claims.sub = Some("subject".to_owned());
+    let jws = JWS::from_claims(Header::new(), claims);
+    let token = encode(&jws, ::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap();
+
+    let decoded = decode(token.clone(), ::TEST_HS256_SECRET, ALGORITHM::HS256, true).unwrap();
+    assert_eq!(decoded.claims().and_then(|claims| claims.sub.clone()), Some("subject".to_owned()));
+    let raw = decode(token, ::TEST_HS256_SECRET, ALGORITHM::HS256, false).unwrap();
+    assert!(raw.claims().is_none());
+}
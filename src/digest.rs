@@ -0,0 +1,191 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use openssl::hash::{Hasher, MessageDigest};
+use serde_json::Value;
+use jws::base64_url_encode_bytes;
+use error::{Error, Result};
+
+// Object ids of detached content referenced by URI, from JAdES (ETSI TS 119 182-1).
+const OBJECT_ID_BY_URI_HASH: &'static str = "http://uri.etsi.org/19182/ObjectIdByURIHash";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DigestAlgorithm {
+    Sha256,
+    Sha384,
+    Sha512
+}
+
+impl DigestAlgorithm {
+    pub fn uri(&self) -> &'static str {
+        match *self {
+            DigestAlgorithm::Sha256 => "http://www.w3.org/2001/04/xmlenc#sha256",
+            DigestAlgorithm::Sha384 => "http://www.w3.org/2001/04/xmldsig-more#sha384",
+            DigestAlgorithm::Sha512 => "http://www.w3.org/2001/04/xmlenc#sha512",
+        }
+    }
+
+    pub fn from_uri(uri: &str) -> Option<DigestAlgorithm> {
+        [DigestAlgorithm::Sha256, DigestAlgorithm::Sha384, DigestAlgorithm::Sha512].iter()
+            .find(|alg| alg.uri() == uri)
+            .cloned()
+    }
+
+    fn message_digest(&self) -> MessageDigest {
+        match *self {
+            DigestAlgorithm::Sha256 => MessageDigest::sha256(),
+            DigestAlgorithm::Sha384 => MessageDigest::sha384(),
+            DigestAlgorithm::Sha512 => MessageDigest::sha512(),
+        }
+    }
+}
+
+// Implement this to canonicalize content before hashing; the signer and the
+// verifier then hash exactly the same bytes.
+pub trait Digestable {
+    fn write_to(&mut self, hasher: &mut Hasher) -> Result<()>;
+
+    fn digest(&mut self, algorithm: DigestAlgorithm) -> Result<Vec<u8>> {
+        let mut hasher = try!(Hasher::new(algorithm.message_digest()));
+        try!(self.write_to(&mut hasher));
+        Ok(try!(hasher.finish()).to_vec())
+    }
+
+    fn digest_b64(&mut self, algorithm: DigestAlgorithm) -> Result<String> {
+        Ok(base64_url_encode_bytes(try!(self.digest(algorithm)).as_slice()))
+    }
+}
+
+impl<'a> Digestable for &'a [u8] {
+    fn write_to(&mut self, hasher: &mut Hasher) -> Result<()> {
+        try!(hasher.update(self));
+        Ok(())
+    }
+}
+
+impl<'a> Digestable for &'a str {
+    fn write_to(&mut self, hasher: &mut Hasher) -> Result<()> {
+        try!(hasher.update(self.as_bytes()));
+        Ok(())
+    }
+}
+
+// Hashes a reader in fixed-size chunks, so large content is never held in memory.
+#[derive(Debug)]
+pub struct Streamed<R>(pub R);
+
+impl<R: Read> Digestable for Streamed<R> {
+    fn write_to(&mut self, hasher: &mut Hasher) -> Result<()> {
+        let mut buffer = [0u8; 8192];
+        loop {
+            let read = try!(self.0.read(&mut buffer).map_err(Error::ReadError));
+            if read == 0 {
+                return Ok(());
+            }
+            try!(hasher.update(&buffer[..read]));
+        }
+    }
+}
+
+// The JAdES sigD header parameter, naming detached content by URI and its digest.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SigD {
+    pub algorithm: DigestAlgorithm,
+    pub pars: Vec<String>,
+    pub hash_values: Vec<String>,
+}
+
+impl SigD {
+    pub fn new(algorithm: DigestAlgorithm) -> SigD {
+        SigD {
+            algorithm: algorithm,
+            pars: Vec::new(),
+            hash_values: Vec::new(),
+        }
+    }
+
+    pub fn add<D: Digestable>(&mut self, name: &str, mut content: D) -> Result<()> {
+        let hash_value = try!(content.digest_b64(self.algorithm));
+        self.pars.push(name.to_owned());
+        self.hash_values.push(hash_value);
+        Ok(())
+    }
+
+    pub fn check<D: Digestable>(&self, name: &str, mut content: D) -> Result<()> {
+        let expected = match self.pars.iter().position(|par| par == name) {
+            Some(index) => &self.hash_values[index],
+            None => return Err(Error::DigestMismatch(name.to_owned()))
+        };
+        if try!(content.digest_b64(self.algorithm)) != *expected {
+            return Err(Error::DigestMismatch(name.to_owned()));
+        }
+        Ok(())
+    }
+
+    pub fn to_value(&self) -> Value {
+        let mut object = BTreeMap::new();
+        object.insert("mId".to_owned(), Value::String(OBJECT_ID_BY_URI_HASH.to_owned()));
+        object.insert("pars".to_owned(), Value::Array(self.pars.iter().cloned().map(Value::String).collect()));
+        object.insert("hashM".to_owned(), Value::String(self.algorithm.uri().to_owned()));
+        object.insert("hashV".to_owned(), Value::Array(self.hash_values.iter().cloned().map(Value::String).collect()));
+        Value::Object(object)
+    }
+
+    pub fn from_value(value: &Value) -> Result<SigD> {
+        let strings = |name: &str| -> Result<Vec<String>> {
+            match value.find(name) {
+                Some(&Value::Array(ref items)) => items.iter()
+                    .map(|item| item.as_str().map(|item| item.to_owned()).ok_or(Error::MalformedToken))
+                    .collect(),
+                _ => Err(Error::MalformedToken)
+            }
+        };
+        if value.find("mId").and_then(|id| id.as_str()) != Some(OBJECT_ID_BY_URI_HASH) {
+            return Err(Error::MalformedToken);
+        }
+        let uri = try!(value.find("hashM").and_then(|uri| uri.as_str()).ok_or(Error::MalformedToken));
+        let algorithm = try!(DigestAlgorithm::from_uri(uri).ok_or_else(|| Error::UnsupportedAlgorithm(uri.to_owned())));
+        let pars = try!(strings("pars"));
+        let hash_values = try!(strings("hashV"));
+        if pars.len() != hash_values.len() {
+            return Err(Error::MalformedToken);
+        }
+        Ok(SigD {
+            algorithm: algorithm,
+            pars: pars,
+            hash_values: hash_values,
+        })
+    }
+}
+
+#[test]
+fn streamed_and_in_memory_digests_agree() {
+    let content = vec![7u8; 100000];
+    for alg in &[DigestAlgorithm::Sha256, DigestAlgorithm::Sha384, DigestAlgorithm::Sha512] {
+        let in_memory = (&content[..]).digest(*alg).unwrap();
+        assert_eq!(Streamed(&content[..]).digest(*alg).unwrap(), in_memory);
+        assert_eq!(DigestAlgorithm::from_uri(alg.uri()), Some(*alg));
+    }
+    assert_eq!("abc".digest_b64(DigestAlgorithm::Sha256).unwrap(), "ungWv48Bz-pBQUDeXa4iI7ADYaOWF3qctBD_YfIAFa0");
+}
+
+#[test]
+fn sig_d_headers_bind_detached_content() {
+    use jws::{JWS, ParsedToken};
+    use jws_header::{Header, ALGORITHM};
+
+    let mut sig_d = SigD::new(DigestAlgorithm::Sha256);
+    sig_d.add("invoice.xml", "<invoice/>").unwrap();
+    sig_d.add("logo.png", Streamed(&b"\x89PNG"[..])).unwrap();
+    let mut header = Header::new();
+    header.set("sigD", sig_d.to_value());
+    let token = JWS::from_custom(header, Vec::new()).encode(::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap();
+
+    let parsed = ParsedToken::parse(&token).unwrap();
+    let received = SigD::from_value(&parsed.header().get::<Value>("sigD").unwrap()).unwrap();
+    assert_eq!(received, sig_d);
+    assert!(received.check("invoice.xml", "<invoice/>").is_ok());
+    assert_err!(received.check("invoice.xml", "<invoice amount=\"0\"/>"), Error::DigestMismatch(name) => assert_eq!(name, "invoice.xml"));
+    assert!(received.check("other.xml", "<invoice/>").is_err());
+}
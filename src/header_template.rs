@@ -0,0 +1,133 @@
+#![allow(dead_code)]
+
+use openssl::hash::{hash, MessageDigest};
+use jws::{base64_url_encode_bytes, ecdsa_params};
+use jws_header::{Header, ALGORITHM};
+use signing;
+use validation::MIN_RSA_BITS;
+use error::{Error, Result};
+
+const KEY_MATERIAL_PARAMS: [&'static str; 6] = ["d", "k", "p", "q", "dp", "dq"];
+
+// Recommendations that do not make a header invalid.
+#[derive(Debug, PartialEq, Clone)]
+pub enum HeaderWarning {
+    MissingKid,
+}
+
+fn is_ecdsa(alg: &ALGORITHM) -> bool {
+    match *alg {
+        ALGORITHM::ES256 | ALGORITHM::ES384 | ALGORITHM::ES512 | ALGORITHM::ES256K => true,
+        _ => false
+    }
+}
+
+fn required_bits(alg: &ALGORITHM) -> usize {
+    match *alg {
+        ALGORITHM::HS256 | ALGORITHM::ES256 | ALGORITHM::ES256K => 256,
+        ALGORITHM::HS384 | ALGORITHM::ES384 => 384,
+        ALGORITHM::HS512 | ALGORITHM::ES512 => 512,
+        ALGORITHM::RS256 | ALGORITHM::RS384 | ALGORITHM::RS512 |
+        ALGORITHM::PS256 | ALGORITHM::PS384 | ALGORITHM::PS512 => MIN_RSA_BITS,
+        ALGORITHM::EdDSA | ALGORITHM::None | ALGORITHM::Custom(_) => 0,
+    }
+}
+
+fn key_bits(alg: &ALGORITHM, key: &[u8]) -> Result<usize> {
+    match *alg {
+        ALGORITHM::HS256 | ALGORITHM::HS384 | ALGORITHM::HS512 => Ok(key.len() * 8),
+        ALGORITHM::RS256 | ALGORITHM::RS384 | ALGORITHM::RS512 |
+        ALGORITHM::PS256 | ALGORITHM::PS384 | ALGORITHM::PS512 => {
+            let pkey = try!(signing::private_key(key).map_err(|err| Error::private_key_parse(key, err)));
+            Ok(pkey.bits() as usize)
+        },
+        ALGORITHM::ES256 | ALGORITHM::ES384 | ALGORITHM::ES512 | ALGORITHM::ES256K => {
+            let pkey = try!(signing::private_key(key).map_err(|err| Error::private_key_parse(key, err)));
+            try!(ecdsa_params(&pkey, alg, false));
+            Ok(pkey.bits() as usize)
+        },
+        ALGORITHM::EdDSA => Ok(try!(signing::eddsa_key(key).map_err(|err| Error::private_key_parse(key, err))).bits() as usize),
+        ALGORITHM::None | ALGORITHM::Custom(_) => Err(Error::UnsupportedAlgorithm(alg.name().to_owned()))
+    }
+}
+
+// ECDSA templates get a kid derived from the public key, so verifiers holding
+// several EC keys can pick one without trying each.
+pub fn header_for_key(alg: ALGORITHM, key: &[u8]) -> Result<Header> {
+    let mut header = Header::new();
+    header.alg = alg;
+    try!(check_header(&header, key));
+    if is_ecdsa(&header.alg) {
+        let public = try!(try!(signing::private_key(key).map_err(|err| Error::private_key_parse(key, err))).public_key_to_der());
+        header.kid = Some(base64_url_encode_bytes(&try!(hash(MessageDigest::sha256(), &public))));
+    }
+    Ok(header)
+}
+
+pub fn check_header(header: &Header, key: &[u8]) -> Result<Vec<HeaderWarning>> {
+    for param in KEY_MATERIAL_PARAMS.iter() {
+        if header.get::<::serde_json::Value>(param).is_some() {
+            return Err(Error::ForbiddenHeaderParameter(param.to_string()));
+        }
+    }
+    if header.alg == ALGORITHM::EdDSA && header.get::<::serde_json::Value>("crv").is_some() {
+        return Err(Error::ForbiddenHeaderParameter("crv".to_owned()));
+    }
+    let bits = try!(key_bits(&header.alg, key));
+    let required = required_bits(&header.alg);
+    if bits < required {
+        return Err(Error::WeakKey { alg: header.alg.clone(), bits: bits, required: required });
+    }
+    let mut warnings = Vec::new();
+    if is_ecdsa(&header.alg) && header.kid.is_none() {
+        warnings.push(HeaderWarning::MissingKid);
+    }
+    Ok(warnings)
+}
+
+#[test]
+fn hmac_templates_require_a_secret_as_long_as_the_hash() {
+    assert!(header_for_key(ALGORITHM::HS256, &[0; 32]).is_ok());
+    assert_err!(header_for_key(ALGORITHM::HS512, &[0; 32]), Error::WeakKey { bits: 256, required: 512, .. });
+}
+
+#[test]
+fn rsa_templates_reject_short_keys() {
+    let pkey = ::openssl::pkey::PKey::from_rsa(::openssl::rsa::Rsa::generate(1024).unwrap()).unwrap();
+    let pem = pkey.private_key_to_pem_pkcs8().unwrap();
+    assert_err!(header_for_key(ALGORITHM::PS256, pem.as_slice()), Error::WeakKey { bits: 1024, required: 2048, .. });
+}
+
+#[test]
+fn headers_must_not_carry_key_material_or_unusable_keys() {
+    let mut header = Header::new();
+    header.alg = ALGORITHM::HS256;
+    header.set("k", "c2VjcmV0");
+    assert!(check_header(&header, &[0; 32]).is_err());
+    assert!(header_for_key(ALGORITHM::ES256, &[0; 32]).is_err());
+
+    let mut header = Header::new();
+    header.alg = ALGORITHM::EdDSA;
+    assert!(check_header(&header, &[1; 32]).is_ok());
+    header.set("crv", "Ed25519");
+    assert!(check_header(&header, &[1; 32]).is_err());
+}
+
+#[test]
+fn ecdsa_headers_without_a_kid_are_flagged() {
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+
+    let key = PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap()).unwrap();
+    let pem = key.private_key_to_pem_pkcs8().unwrap();
+    let mut header = Header::new();
+    header.alg = ALGORITHM::ES256;
+    assert_eq!(check_header(&header, &pem).unwrap(), vec![HeaderWarning::MissingKid]);
+
+    let template = header_for_key(ALGORITHM::ES256, &pem).unwrap();
+    assert!(template.kid.is_some());
+    assert_eq!(template.kid, header_for_key(ALGORITHM::ES256, &pem).unwrap().kid);
+    assert_eq!(check_header(&template, &pem).unwrap(), vec![]);
+    assert_eq!(check_header(&Header::new(), &[0; 32]).unwrap(), vec![]);
+}
@@ -0,0 +1,229 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use openssl::hash::{hash, MessageDigest};
+use serde_json::Value;
+use clock::{Clock, SystemClock};
+use jws::base64_url_encode_bytes;
+use jws_header::{Header, ALGORITHM};
+use simple::DecodingKey;
+use validation::Validation;
+use error::{Error, Result};
+#[cfg(test)]
+use testing::{hs256_decoding_key, id_token, id_token_claims, id_token_validator};
+
+// Claims OpenID Connect Core, section 2, requires in every ID token.
+const REQUIRED_CLAIMS: [&'static str; 5] = ["iss", "sub", "aud", "exp", "iat"];
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct IdToken {
+    pub header: Header,
+    pub iss: String,
+    pub sub: String,
+    pub aud: Vec<String>,
+    pub exp: u64,
+    pub iat: u64,
+    pub azp: Option<String>,
+    pub nonce: Option<String>,
+    pub auth_time: Option<u64>,
+    pub claims: BTreeMap<String, Value>,
+}
+
+// The parts of an implicit or hybrid flow response that the ID token binds.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AuthResponse<'a> {
+    pub id_token: &'a str,
+    pub access_token: Option<&'a str>,
+    pub code: Option<&'a str>,
+}
+
+// The issuer and client id are filled into `validation` on every call; the
+// rest of it (algorithms, leeway, limits, key floors) applies as configured.
+#[derive(Debug, PartialEq, Clone)]
+pub struct IdTokenValidator {
+    pub issuer: String,
+    pub client_id: String,
+    pub validation: Validation,
+}
+
+// The hash is the one the ID token's alg uses; EdDSA uses SHA-512, as for Ed25519.
+pub fn left_half_hash(value: &str, algorithm: &ALGORITHM) -> Result<String> {
+    let digest = match *algorithm {
+        ALGORITHM::HS256 | ALGORITHM::RS256 | ALGORITHM::PS256 | ALGORITHM::ES256 | ALGORITHM::ES256K => MessageDigest::sha256(),
+        ALGORITHM::HS384 | ALGORITHM::RS384 | ALGORITHM::PS384 | ALGORITHM::ES384 => MessageDigest::sha384(),
+        ALGORITHM::HS512 | ALGORITHM::RS512 | ALGORITHM::PS512 | ALGORITHM::ES512 | ALGORITHM::EdDSA => MessageDigest::sha512(),
+        ALGORITHM::None | ALGORITHM::Custom(_) => return Err(Error::UnsupportedAlgorithm(algorithm.name().to_owned()))
+    };
+    let digest = try!(hash(digest, value.as_bytes()));
+    Ok(base64_url_encode_bytes(&digest[..digest.len() / 2]))
+}
+
+pub fn at_hash(access_token: &str, algorithm: &ALGORITHM) -> Result<String> {
+    left_half_hash(access_token, algorithm)
+}
+
+pub fn c_hash(code: &str, algorithm: &ALGORITHM) -> Result<String> {
+    left_half_hash(code, algorithm)
+}
+
+impl IdToken {
+    // A value handed over with the ID token must be bound to it, so the claim is required.
+    fn check_hash(&self, claim: &'static str, value: &str) -> Result<()> {
+        let expected = try!(left_half_hash(value, &self.header.alg));
+        match self.claims.get(claim).and_then(|found| found.as_str()) {
+            Some(found) if found == expected => Ok(()),
+            Some(_) => Err(Error::ClaimMismatch { claim: claim }),
+            None => Err(Error::MissingClaims(vec![claim.to_owned()]))
+        }
+    }
+
+    pub fn check_at_hash(&self, access_token: &str) -> Result<()> {
+        self.check_hash("at_hash", access_token)
+    }
+
+    pub fn check_c_hash(&self, code: &str) -> Result<()> {
+        self.check_hash("c_hash", code)
+    }
+}
+
+impl IdTokenValidator {
+    // Defaults to RS256, as OpenID Connect does when the client registration names no algorithm.
+    pub fn new(issuer: &str, client_id: &str) -> IdTokenValidator {
+        let mut validation = Validation::new();
+        validation.algorithms = vec![ALGORITHM::RS256];
+        validation.require_algorithms = true;
+        validation.validate_iat = true;
+        validation.required_claims = REQUIRED_CLAIMS.iter().map(|name| name.to_string()).collect();
+        IdTokenValidator {
+            issuer: issuer.to_owned(),
+            client_id: client_id.to_owned(),
+            validation: validation,
+        }
+    }
+
+    pub fn validate(&self, token: &str, key: &DecodingKey, nonce: Option<&str>) -> Result<IdToken> {
+        self.validate_with_clock(token, key, nonce, &SystemClock)
+    }
+
+    pub fn validate_with_clock<C: Clock>(&self, token: &str, key: &DecodingKey, nonce: Option<&str>, clock: &C) -> Result<IdToken> {
+        self.validate_at(token, key, nonce, clock.now())
+    }
+
+    // Follows the ID token validation steps of OpenID Connect Core, section 3.1.3.7.
+    // Validation covers the signature, iss, aud, exp and iat; azp and nonce are
+    // checked here.
+    pub fn validate_at(&self, token: &str, key: &DecodingKey, nonce: Option<&str>, now: u64) -> Result<IdToken> {
+        let mut options = self.validation.clone();
+        options.issuer = Some(self.issuer.clone());
+        options.audience = Some(self.client_id.clone());
+        let (header, claims) = try!(options.decode_with_key_at(token, key, now));
+
+        let aud: Vec<String> = claims.aud.as_ref().map(|aud| aud.iter().cloned().collect()).unwrap_or_default();
+        // With several audiences, azp names the party the token was issued to.
+        let azp = claims.get::<String>("azp");
+        match azp {
+            Some(ref azp) if *azp != self.client_id => return Err(Error::ClaimMismatch { claim: "azp" }),
+            None if aud.len() > 1 => return Err(Error::MissingClaims(vec!["azp".to_owned()])),
+            _ => {}
+        }
+
+        let token_nonce = claims.get::<String>("nonce");
+        if let Some(expected) = nonce {
+            match token_nonce {
+                Some(ref found) if found == expected => {},
+                Some(_) => return Err(Error::ClaimMismatch { claim: "nonce" }),
+                None => return Err(Error::MissingClaims(vec!["nonce".to_owned()]))
+            }
+        }
+
+        Ok(IdToken {
+            header: header,
+            iss: claims.iss.clone().unwrap_or_default(),
+            sub: claims.sub.clone().unwrap_or_default(),
+            aud: aud,
+            exp: claims.exp.unwrap_or(0),
+            iat: claims.iat.unwrap_or(0),
+            azp: azp,
+            nonce: token_nonce,
+            auth_time: claims.get::<u64>("auth_time"),
+            claims: claims.iter_all().collect(),
+        })
+    }
+
+    // For responses that carry an access token or code next to the ID token,
+    // such as the implicit and hybrid flows.
+    pub fn validate_response_at(&self, response: &AuthResponse, key: &DecodingKey, nonce: Option<&str>, now: u64) -> Result<IdToken> {
+        let id_token = try!(self.validate_at(response.id_token, key, nonce, now));
+        if let Some(access_token) = response.access_token {
+            try!(id_token.check_at_hash(access_token));
+        }
+        if let Some(code) = response.code {
+            try!(id_token.check_c_hash(code));
+        }
+        Ok(id_token)
+    }
+}
+
+#[test]
+fn valid_id_tokens_are_returned_with_their_claims() {
+    let token = id_token(id_token_claims(Value::String("client-a".to_owned())));
+    let id_token = id_token_validator().validate_at(&token, &hs256_decoding_key(), Some("n-0S6"), 1500).unwrap();
+    assert_eq!(id_token.sub, "user-1");
+    assert_eq!(id_token.aud, vec!["client-a".to_owned()]);
+    assert_eq!(id_token.nonce, Some("n-0S6".to_owned()));
+
+    assert_err!(IdTokenValidator::new("https://login.example.com", "client-a").validate_at(&token, &hs256_decoding_key(), None, 1500), Error::AlgorithmNotAllowed(alg) => assert_eq!(alg, "HS256"));
+}
+
+#[test]
+fn multiple_audiences_require_a_matching_azp() {
+    let audiences = Value::Array(vec![Value::String("client-a".to_owned()), Value::String("client-b".to_owned())]);
+    let without_azp = id_token(id_token_claims(audiences.clone()));
+    assert_err!(id_token_validator().validate_at(&without_azp, &hs256_decoding_key(), None, 1500), Error::MissingClaims(claims) => assert_eq!(claims, vec!["azp".to_owned()]));
+
+    let mut claims = id_token_claims(audiences);
+    if let Value::Object(ref mut claims) = claims {
+        claims.insert("azp".to_owned(), Value::String("client-a".to_owned()));
+    }
+    assert!(id_token_validator().validate_at(&id_token(claims.clone()), &hs256_decoding_key(), None, 1500).is_ok());
+    let mut other_client = id_token_validator();
+    other_client.client_id = "client-b".to_owned();
+    assert_err!(other_client.validate_at(&id_token(claims), &hs256_decoding_key(), None, 1500), Error::ClaimMismatch { claim: "azp" });
+}
+
+#[test]
+fn id_tokens_are_checked_against_issuer_nonce_and_time() {
+    let token = id_token(id_token_claims(Value::String("client-a".to_owned())));
+    let validator = id_token_validator();
+    assert_err!(validator.validate_at(&token, &hs256_decoding_key(), Some("other"), 1500), Error::ClaimMismatch { claim: "nonce" });
+    assert_err!(validator.validate_at(&token, &hs256_decoding_key(), None, 2060), Error::TokenExpired { exp: 2000, now: 2060 });
+    assert_err!(validator.validate_at(&token, &hs256_decoding_key(), None, 900), Error::IssuedInFuture { skew: 100 });
+    let mut wrong_issuer = validator.clone();
+    wrong_issuer.issuer = "https://other.example".to_owned();
+    assert_err!(wrong_issuer.validate_at(&token, &hs256_decoding_key(), None, 1500), Error::ClaimMismatch { claim: "iss" });
+    assert_err!(validator.validate_at(&id_token(Value::Object(BTreeMap::new())), &hs256_decoding_key(), None, 1500), Error::MissingClaims(claims) => assert_eq!(claims.len(), 5));
+}
+
+#[test]
+fn left_half_hashes_match_the_oidc_examples() {
+    assert_eq!(at_hash("jHkWEdUXMU1BwAsC4vtUsZwnNvTIxEl0z9K3vx5KF0Y", &ALGORITHM::RS256).unwrap(), "77QmUPtjPfzWtF2AnpK9RQ");
+    assert_eq!(c_hash("Qcb0Orv1zh30vL1MPRsbm-diHiMwcLyZvn1arpZv-Jxf_11jnpEX3Tgfvk", &ALGORITHM::RS256).unwrap(), "LDktKdoQak3Pk0cnXxCltA");
+    assert_eq!(at_hash("token", &ALGORITHM::ES384).unwrap().len(), 32);
+    assert!(at_hash("token", &ALGORITHM::None).is_err());
+}
+
+#[test]
+fn hybrid_responses_bind_the_access_token_and_code() {
+    let mut claims = id_token_claims(Value::String("client-a".to_owned()));
+    if let Value::Object(ref mut claims) = claims {
+        claims.insert("at_hash".to_owned(), Value::String(at_hash("access", &ALGORITHM::HS256).unwrap()));
+    }
+    let token = id_token(claims);
+    let validator = id_token_validator();
+    let response = AuthResponse { id_token: &token, access_token: Some("access"), code: None };
+    assert!(validator.validate_response_at(&response, &hs256_decoding_key(), None, 1500).is_ok());
+    let swapped = AuthResponse { access_token: Some("swapped"), ..response };
+    assert_err!(validator.validate_response_at(&swapped, &hs256_decoding_key(), None, 1500), Error::ClaimMismatch { claim: "at_hash" });
+    let with_code = AuthResponse { id_token: &token, access_token: None, code: Some("code") };
+    assert_err!(validator.validate_response_at(&with_code, &hs256_decoding_key(), None, 1500), Error::MissingClaims(claims) => assert_eq!(claims, vec!["c_hash".to_owned()]));
+}
@@ -0,0 +1,320 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::collections::hash_map;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use serde_json::{self, Value};
+use claims::Claims;
+use clock::{Clock, SystemClock};
+use jws::ParsedToken;
+use jws_header::{Header, ALGORITHM};
+use typ::Typ;
+use validation::Validation;
+use error::{Error, Result};
+#[cfg(test)]
+use testing::{issued_by, issuer_registry};
+
+// Fields left as None fall back to the registry defaults.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct IssuerPolicy {
+    pub algorithms: Option<Vec<ALGORITHM>>,
+    pub min_rsa_bits: Option<usize>,
+    pub audience: Option<String>,
+    pub typ: Option<Typ>,
+    pub required_claims: Option<Vec<String>>,
+    pub leeway: Option<u64>,
+    pub validate_iat: Option<bool>,
+    pub max_age: Option<u64>,
+    // Where the issuer publishes its keys; only read when the keys are fetched.
+    pub jwks_uri: Option<String>,
+}
+
+impl IssuerPolicy {
+    pub fn new() -> IssuerPolicy {
+        IssuerPolicy::default()
+    }
+
+    pub fn apply(&self, defaults: &Validation) -> Validation {
+        let mut options = defaults.clone();
+        if let Some(ref algorithms) = self.algorithms {
+            options.algorithms = algorithms.clone();
+            options.require_algorithms = true;
+        }
+        if self.min_rsa_bits.is_some() {
+            options.min_rsa_bits = self.min_rsa_bits;
+        }
+        if self.audience.is_some() {
+            options.audience = self.audience.clone();
+        }
+        if self.typ.is_some() {
+            options.typ = self.typ.clone();
+        }
+        if let Some(ref required_claims) = self.required_claims {
+            options.required_claims = required_claims.clone();
+        }
+        if let Some(leeway) = self.leeway {
+            options.leeway = leeway;
+        }
+        if let Some(validate_iat) = self.validate_iat {
+            options.validate_iat = validate_iat;
+        }
+        if self.max_age.is_some() {
+            options.max_age = self.max_age;
+        }
+        options
+    }
+
+    fn from_config(value: &Value) -> Result<(String, IssuerPolicy)> {
+        let members = match *value {
+            Value::Object(ref members) => members,
+            _ => return Err(invalid_config("each issuer must be an object"))
+        };
+        let mut issuer = None;
+        let mut policy = IssuerPolicy::new();
+        for (name, member) in members {
+            match name.as_str() {
+                "issuer" => issuer = Some(try!(config_string(name, member))),
+                "jwks_uri" => policy.jwks_uri = Some(try!(config_string(name, member))),
+                "audience" => policy.audience = Some(try!(config_string(name, member))),
+                "typ" => policy.typ = Some(Typ::new(try!(config_string(name, member)))),
+                "algorithms" => policy.algorithms = Some(try!(try!(config_strings(name, member)).iter().map(|alg| {
+                    match ALGORITHM::from_name(alg) {
+                        Some(ALGORITHM::None) | None => Err(Error::InvalidConfig(format!("unsupported algorithm {}", alg))),
+                        Some(alg) => Ok(alg)
+                    }
+                }).collect())),
+                "required_claims" => policy.required_claims = Some(try!(config_strings(name, member))),
+                "min_rsa_bits" => policy.min_rsa_bits = Some(try!(config_u64(name, member)) as usize),
+                "leeway" => policy.leeway = Some(try!(config_u64(name, member))),
+                "max_age" => policy.max_age = Some(try!(config_u64(name, member))),
+                "validate_iat" => policy.validate_iat = Some(try!(member.as_bool().ok_or_else(|| {
+                    Error::InvalidConfig(format!("{} must be a boolean", name))
+                }))),
+                // A misspelt setting would otherwise silently leave the default in place.
+                _ => return Err(Error::InvalidConfig(format!("unknown setting {}", name)))
+            }
+        }
+        match issuer {
+            Some(issuer) => Ok((issuer, policy)),
+            None => Err(invalid_config("an entry has no issuer"))
+        }
+    }
+}
+
+fn invalid_config(reason: &str) -> Error {
+    Error::InvalidConfig(reason.to_owned())
+}
+
+fn config_string(name: &str, value: &Value) -> Result<String> {
+    value.as_str().map(|value| value.to_owned()).ok_or_else(|| Error::InvalidConfig(format!("{} must be a string", name)))
+}
+
+fn config_strings(name: &str, value: &Value) -> Result<Vec<String>> {
+    match *value {
+        Value::Array(ref values) => values.iter().map(|value| config_string(name, value)).collect(),
+        _ => Err(Error::InvalidConfig(format!("{} must be a list of strings", name)))
+    }
+}
+
+fn config_u64(name: &str, value: &Value) -> Result<u64> {
+    value.as_u64().ok_or_else(|| Error::InvalidConfig(format!("{} must be a non-negative integer", name)))
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct IssuerRegistry {
+    defaults: Validation,
+    issuers: HashMap<String, IssuerPolicy>,
+}
+
+impl IssuerRegistry {
+    pub fn new(defaults: Validation) -> IssuerRegistry {
+        IssuerRegistry {
+            defaults: defaults,
+            issuers: HashMap::new(),
+        }
+    }
+
+    // Reads {"issuers": [{"issuer": ..., "jwks_uri": ..., "algorithms": [...], "audience": ...}, ...]}.
+    // Each entry also accepts the other IssuerPolicy fields; anything left out falls back to defaults.
+    pub fn from_config(json: &str, defaults: Validation) -> Result<IssuerRegistry> {
+        let value: Value = try!(serde_json::from_str(json));
+        let issuers = match value.find("issuers") {
+            Some(&Value::Array(ref issuers)) => issuers,
+            _ => return Err(invalid_config("issuers must be a list"))
+        };
+        let mut registry = IssuerRegistry::new(defaults);
+        for entry in issuers {
+            let (issuer, policy) = try!(IssuerPolicy::from_config(entry));
+            if registry.issuers.contains_key(&issuer) {
+                return Err(Error::InvalidConfig(format!("{} is listed twice", issuer)));
+            }
+            registry.register(&issuer, policy);
+        }
+        Ok(registry)
+    }
+
+    pub fn from_config_file<P: AsRef<Path>>(path: P, defaults: Validation) -> Result<IssuerRegistry> {
+        let mut json = String::new();
+        try!(File::open(path).and_then(|mut file| file.read_to_string(&mut json)).map_err(Error::ReadError));
+        IssuerRegistry::from_config(&json, defaults)
+    }
+
+    pub fn register(&mut self, issuer: &str, policy: IssuerPolicy) {
+        self.issuers.insert(issuer.to_owned(), policy);
+    }
+
+    pub fn policy(&self, issuer: &str) -> Option<&IssuerPolicy> {
+        self.issuers.get(issuer)
+    }
+
+    pub fn issuers<'a>(&'a self) -> hash_map::Iter<'a, String, IssuerPolicy> {
+        self.issuers.iter()
+    }
+
+    pub fn defaults(&self) -> &Validation {
+        &self.defaults
+    }
+
+    // The issuer claim is always pinned, so a policy only ever applies to its own issuer.
+    pub fn options_for(&self, issuer: &str) -> Option<Validation> {
+        self.issuers.get(issuer).map(|policy| {
+            let mut options = policy.apply(&self.defaults);
+            options.issuer = Some(issuer.to_owned());
+            options
+        })
+    }
+
+    // Reads iss before the signature is checked, only to choose the policy that checks it.
+    pub fn options_for_token(&self, token: &str) -> Result<Validation> {
+        let parsed = try!(ParsedToken::parse_with_validation(token, &self.defaults));
+        let issuer = try!(parsed.claims_unverified(&self.defaults.limits)).iss;
+        match issuer.as_ref().and_then(|issuer| self.options_for(issuer)) {
+            Some(options) => Ok(options),
+            None => Err(Error::UnknownIssuer(issuer))
+        }
+    }
+
+    pub fn decode(&self, token: &str, secret: &[u8], algorithm: ALGORITHM) -> Result<(Header, Claims)> {
+        self.decode_with_clock(token, secret, algorithm, &SystemClock)
+    }
+
+    pub fn decode_with_clock<C: Clock>(&self, token: &str, secret: &[u8], algorithm: ALGORITHM, clock: &C) -> Result<(Header, Claims)> {
+        self.decode_at(token, secret, algorithm, clock.now())
+    }
+
+    pub fn decode_at(&self, token: &str, secret: &[u8], algorithm: ALGORITHM, now: u64) -> Result<(Header, Claims)> {
+        try!(self.options_for_token(token)).decode_at(token, secret, algorithm, now)
+    }
+}
+
+#[test]
+fn policies_compose_with_the_defaults() {
+    let registry = issuer_registry();
+    let partner = registry.options_for("partner").unwrap();
+    assert_eq!(partner.algorithms, vec![ALGORITHM::RS256, ALGORITHM::ES256]);
+    assert_eq!(partner.audience, Some("api".to_owned()));
+    assert_eq!(partner.issuer, Some("partner".to_owned()));
+    assert_eq!(partner.min_rsa_bits, Some(3072));
+    assert_eq!(registry.options_for("legacy").unwrap().algorithms, vec![ALGORITHM::HS256]);
+    assert!(registry.options_for("stranger").is_none());
+}
+
+#[test]
+fn each_issuer_is_held_to_its_own_policy() {
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+
+    let registry = issuer_registry();
+    let legacy = issued_by("legacy", ::TEST_HS256_SECRET, ALGORITHM::HS256);
+    assert!(registry.decode_at(&legacy, ::TEST_HS256_SECRET, ALGORITHM::HS256, 1000).is_ok());
+    assert_err!(registry.decode_at(&issued_by("modern", ::TEST_HS256_SECRET, ALGORITHM::HS256), ::TEST_HS256_SECRET, ALGORITHM::HS256, 1000), Error::AlgorithmNotAllowed(alg) => assert_eq!(alg, "HS256"));
+
+    let ed25519 = PKey::generate_ed25519().unwrap().private_key_to_pem_pkcs8().unwrap();
+    assert!(registry.decode_at(&issued_by("modern", &ed25519, ALGORITHM::EdDSA), &ed25519, ALGORITHM::EdDSA, 1000).is_ok());
+
+    let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap().private_key_to_pem_pkcs8().unwrap();
+    assert_err!(registry.decode_at(&issued_by("partner", &rsa, ALGORITHM::RS256), &rsa, ALGORITHM::RS256, 1000), Error::WeakKey { bits: 2048, required: 3072, .. });
+
+    assert_err!(registry.decode_at(&issued_by("stranger", ::TEST_HS256_SECRET, ALGORITHM::HS256), ::TEST_HS256_SECRET, ALGORITHM::HS256, 1000), Error::UnknownIssuer(iss) => assert_eq!(iss, Some("stranger".to_owned())));
+}
+
+#[test]
+fn issuer_policies_keep_exp_and_nbf_checks() {
+    let secret = ::TEST_HS256_SECRET;
+    let mut registry = IssuerRegistry::new(Validation::new());
+    registry.register("https://a", IssuerPolicy::new());
+    registry.register("https://b", IssuerPolicy { leeway: Some(5000), ..IssuerPolicy::new() });
+    let token = |issuer: &str| {
+        let mut claims = Claims::new();
+        claims.iss = Some(issuer.to_owned());
+        claims.exp = Some(1000);
+        ::jws::JWS::from_claims(Header::new(), claims).encode(secret, ALGORITHM::HS256).unwrap()
+    };
+
+    assert!(registry.decode_at(&token("https://a"), secret, ALGORITHM::HS256, 900).is_ok());
+    assert_err!(registry.decode_at(&token("https://a"), secret, ALGORITHM::HS256, 5000), Error::TokenExpired { exp: 1000, now: 5000 });
+    assert!(registry.decode_at(&token("https://b"), secret, ALGORITHM::HS256, 5000).is_ok());
+}
+
+#[test]
+fn default_hooks_and_replay_store_apply_to_every_issuer() {
+    use std::sync::{Arc, Mutex};
+    use replay::MemoryReplayStore;
+
+    let secret = ::TEST_HS256_SECRET;
+    let store = Arc::new(Mutex::new(MemoryReplayStore::new()));
+    let defaults = Validation::new()
+        .with_validator(|claims: &Claims| match claims.sub {
+            Some(_) => Ok(()),
+            None => Err(Error::ClaimRejected("no subject".to_owned()))
+        })
+        .with_replay_store(store.clone());
+    let mut registry = IssuerRegistry::new(defaults);
+    registry.register("https://a", IssuerPolicy::new());
+    let token = |sub: Option<&str>| {
+        let mut claims = Claims::new();
+        claims.iss = Some("https://a".to_owned());
+        claims.sub = sub.map(|sub| sub.to_owned());
+        claims.jti = Some("once".to_owned());
+        claims.exp = Some(2000);
+        ::jws::JWS::from_claims(Header::new(), claims).encode(secret, ALGORITHM::HS256).unwrap()
+    };
+
+    assert_err!(registry.decode_at(&token(None), secret, ALGORITHM::HS256, 1000), Error::ClaimRejected(_));
+    assert!(registry.decode_at(&token(Some("user")), secret, ALGORITHM::HS256, 1000).is_ok());
+    assert_err!(registry.decode_at(&token(Some("user")), secret, ALGORITHM::HS256, 1001), Error::TokenReplayed);
+}
+
+#[test]
+fn registries_load_from_a_config() {
+    let config = r#"{"issuers": [
+        {"issuer": "https://a", "jwks_uri": "https://a/jwks.json", "algorithms": ["ES256"], "audience": "api"},
+        {"issuer": "https://b", "algorithms": ["RS256", "PS256"], "min_rsa_bits": 3072, "leeway": 5, "required_claims": ["sub"]}
+    ]}"#;
+    let registry = IssuerRegistry::from_config(config, Validation::new()).unwrap();
+    let a = registry.policy("https://a").unwrap();
+    assert_eq!(a.jwks_uri, Some("https://a/jwks.json".to_owned()));
+    let b = registry.options_for("https://b").unwrap();
+    assert_eq!(b.algorithms, vec![ALGORITHM::RS256, ALGORITHM::PS256]);
+    assert_eq!(b.min_rsa_bits, Some(3072));
+    assert_eq!(b.leeway, 5);
+    assert_eq!(b.required_claims, vec!["sub".to_owned()]);
+    assert_eq!(registry.options_for("https://a").unwrap().audience, Some("api".to_owned()));
+    assert_eq!(registry.issuers().count(), 2);
+
+    for config in &[
+        r#"{"issuers": [{"issuer": "https://a", "algorithm": ["ES256"]}]}"#,
+        r#"{"issuers": [{"issuer": "https://a", "algorithms": ["none"]}]}"#,
+        r#"{"issuers": [{"issuer": "https://a"}, {"issuer": "https://a"}]}"#,
+        r#"{"issuers": [{"jwks_uri": "https://a/jwks.json"}]}"#,
+        r#"{"issuers": [{"issuer": "https://a", "leeway": -1}]}"#,
+        r#"{"issuer": "https://a"}"#,
+    ] {
+        match IssuerRegistry::from_config(config, Validation::new()) {
+            Err(Error::InvalidConfig(_)) => {},
+            other => panic!("expected InvalidConfig for {}, got {:?}", config, other)
+        }
+    }
+}
@@ -0,0 +1,570 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::result;
+use openssl::bn::{BigNum, BigNumContext, BigNumRef};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Private, Public};
+use openssl::rsa::Rsa;
+use openssl::stack::Stack;
+use openssl::x509::{X509, X509StoreContext};
+use openssl::x509::store::X509StoreRef;
+use rustc_serialize::base64::{self, FromBase64, ToBase64};
+use serde;
+use serde::Serialize;
+use serde_json::{self, Value};
+use jws::base64_url_encode_bytes;
+use error::{Error, Result};
+use signing;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RsaPrivateParams {
+    pub d: Vec<u8>,
+    pub p: Vec<u8>,
+    pub q: Vec<u8>,
+    pub dp: Vec<u8>,
+    pub dq: Vec<u8>,
+    pub qi: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum JwkParams {
+    Oct { k: Vec<u8> },
+    Rsa { n: Vec<u8>, e: Vec<u8>, private: Option<RsaPrivateParams> },
+    Ec { crv: String, x: Vec<u8>, y: Vec<u8>, d: Option<Vec<u8>> },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Jwk {
+    pub kid: Option<String>,
+    pub key_use: Option<String>,
+    pub alg: Option<String>,
+    pub params: JwkParams,
+    // DER certificates, the first one holding this key (RFC 7517 4.7).
+    pub x5c: Vec<Vec<u8>>,
+    // SHA-1 and SHA-256 digests of the first certificate's DER.
+    pub x5t: Option<Vec<u8>>,
+    pub x5t_s256: Option<Vec<u8>>,
+}
+
+// RFC 7518 6.2.1.1 curve names and the fixed length of their coordinates.
+fn curve(crv: &str) -> Result<(Nid, usize)> {
+    match crv {
+        "P-256" => Ok((Nid::X9_62_PRIME256V1, 32)),
+        "P-384" => Ok((Nid::SECP384R1, 48)),
+        "P-521" => Ok((Nid::SECP521R1, 66)),
+        "secp256k1" => Ok((Nid::SECP256K1, 32)),
+        _ => Err(Error::InvalidJwk(format!("unsupported curve {}", crv)))
+    }
+}
+
+fn curve_name(nid: Nid) -> Result<&'static str> {
+    match nid {
+        Nid::X9_62_PRIME256V1 => Ok("P-256"),
+        Nid::SECP384R1 => Ok("P-384"),
+        Nid::SECP521R1 => Ok("P-521"),
+        Nid::SECP256K1 => Ok("secp256k1"),
+        _ => Err(Error::InvalidJwk("the key is on an unsupported curve".to_owned()))
+    }
+}
+
+fn bytes(n: &BigNumRef) -> Vec<u8> {
+    n.to_vec()
+}
+
+fn bignum(bytes: &[u8]) -> Result<BigNum> {
+    Ok(try!(BigNum::from_slice(bytes)))
+}
+
+fn member(object: &BTreeMap<String, Value>, name: &str) -> Result<Vec<u8>> {
+    match object.get(name).and_then(|value| value.as_str()) {
+        Some(encoded) => Ok(try!(encoded.from_base64())),
+        None => Err(Error::InvalidJwk(format!("missing the {} parameter", name)))
+    }
+}
+
+fn optional_string(object: &BTreeMap<String, Value>, name: &str) -> Option<String> {
+    object.get(name).and_then(|value| value.as_str()).map(|value| value.to_owned())
+}
+
+fn public_params(key: &PKey<Public>) -> Result<JwkParams> {
+    match key.id() {
+        Id::RSA => {
+            let rsa = try!(key.rsa());
+            Ok(JwkParams::Rsa { n: bytes(rsa.n()), e: bytes(rsa.e()), private: None })
+        },
+        Id::EC => {
+            let ec_key = try!(key.ec_key());
+            let nid = match ec_key.group().curve_name() {
+                Some(nid) => nid,
+                None => return Err(Error::InvalidJwk("the key has explicit curve parameters".to_owned()))
+            };
+            let crv = try!(curve_name(nid));
+            let (_, len) = try!(curve(crv));
+            let mut x = try!(BigNum::new());
+            let mut y = try!(BigNum::new());
+            let mut ctx = try!(BigNumContext::new());
+            try!(ec_key.public_key().affine_coordinates_gfp(ec_key.group(), &mut x, &mut y, &mut ctx));
+            Ok(JwkParams::Ec {
+                crv: crv.to_owned(),
+                x: try!(x.to_vec_padded(len as i32)),
+                y: try!(y.to_vec_padded(len as i32)),
+                d: None,
+            })
+        },
+        _ => Err(Error::InvalidJwk("only RSA and EC keys have a JWK form".to_owned()))
+    }
+}
+
+fn private_params(key: &PKey<Private>) -> Result<JwkParams> {
+    let public = try!(PKey::public_key_from_der(&try!(key.public_key_to_der())));
+    match try!(public_params(&public)) {
+        JwkParams::Rsa { n, e, .. } => {
+            let rsa = try!(key.rsa());
+            let private = match (rsa.p(), rsa.q(), rsa.dmp1(), rsa.dmq1(), rsa.iqmp()) {
+                (Some(p), Some(q), Some(dp), Some(dq), Some(qi)) => RsaPrivateParams {
+                    d: bytes(rsa.d()),
+                    p: bytes(p),
+                    q: bytes(q),
+                    dp: bytes(dp),
+                    dq: bytes(dq),
+                    qi: bytes(qi),
+                },
+                _ => return Err(Error::InvalidJwk("the RSA key has no CRT parameters".to_owned()))
+            };
+            Ok(JwkParams::Rsa { n: n, e: e, private: Some(private) })
+        },
+        JwkParams::Ec { crv, x, y, .. } => {
+            let (_, len) = try!(curve(&crv));
+            let d = try!(try!(key.ec_key()).private_key().to_vec_padded(len as i32));
+            Ok(JwkParams::Ec { crv: crv, x: x, y: y, d: Some(d) })
+        },
+        params => Ok(params)
+    }
+}
+
+impl Jwk {
+    pub fn oct(secret: &[u8]) -> Jwk {
+        Jwk {
+            kid: None,
+            key_use: None,
+            alg: None,
+            params: JwkParams::Oct { k: secret.to_vec() },
+            x5c: Vec::new(),
+            x5t: None,
+            x5t_s256: None,
+        }
+    }
+
+    // Accepts the same PEM or DER keys and certificates as signing and verification.
+    pub fn from_key(key: &[u8]) -> Result<Jwk> {
+        let params = match signing::private_key(key) {
+            Ok(private) => try!(private_params(&private)),
+            Err(_) => try!(public_params(&try!(signing::public_key(key).map_err(|err| Error::public_key_parse(key, err)))))
+        };
+        Ok(Jwk {
+            kid: None,
+            key_use: None,
+            alg: None,
+            params: params,
+            x5c: Vec::new(),
+            x5t: None,
+            x5t_s256: None,
+        })
+    }
+
+    pub fn is_private(&self) -> bool {
+        match self.params {
+            JwkParams::Oct { .. } => true,
+            JwkParams::Rsa { ref private, .. } => private.is_some(),
+            JwkParams::Ec { ref d, .. } => d.is_some(),
+        }
+    }
+
+    // Symmetric keys have no public half.
+    pub fn to_public(&self) -> Option<Jwk> {
+        let params = match self.params {
+            JwkParams::Oct { .. } => return None,
+            JwkParams::Rsa { ref n, ref e, .. } => JwkParams::Rsa { n: n.clone(), e: e.clone(), private: None },
+            JwkParams::Ec { ref crv, ref x, ref y, .. } => JwkParams::Ec { crv: crv.clone(), x: x.clone(), y: y.clone(), d: None },
+        };
+        Some(Jwk { params: params, ..self.clone() })
+    }
+
+    // The key as `encode` and `decode_jwt` take it: the secret for oct keys,
+    // PKCS#8 DER for private keys and SPKI DER for public keys.
+    pub fn to_key(&self) -> Result<Vec<u8>> {
+        match self.params {
+            JwkParams::Oct { ref k } => Ok(k.clone()),
+            JwkParams::Rsa { ref n, ref e, private: None } => {
+                let rsa = try!(Rsa::from_public_components(try!(bignum(n)), try!(bignum(e))));
+                Ok(try!(try!(PKey::from_rsa(rsa)).public_key_to_der()))
+            },
+            JwkParams::Rsa { ref n, ref e, private: Some(ref private) } => {
+                let rsa = try!(Rsa::from_private_components(
+                    try!(bignum(n)), try!(bignum(e)), try!(bignum(&private.d)),
+                    try!(bignum(&private.p)), try!(bignum(&private.q)),
+                    try!(bignum(&private.dp)), try!(bignum(&private.dq)), try!(bignum(&private.qi))));
+                Ok(try!(try!(PKey::from_rsa(rsa)).private_key_to_pkcs8()))
+            },
+            JwkParams::Ec { ref crv, ref x, ref y, ref d } => {
+                let (nid, _) = try!(curve(crv));
+                let group = try!(EcGroup::from_curve_name(nid));
+                let (x, y) = (try!(bignum(x)), try!(bignum(y)));
+                let public = try!(EcKey::from_public_key_affine_coordinates(&group, &x, &y));
+                match *d {
+                    None => Ok(try!(try!(PKey::from_ec_key(public)).public_key_to_der())),
+                    Some(ref d) => {
+                        let d = try!(bignum(d));
+                        let private = try!(EcKey::from_private_components(&group, &d, public.public_key()));
+                        try!(private.check_key());
+                        Ok(try!(try!(PKey::from_ec_key(private)).private_key_to_pkcs8()))
+                    }
+                }
+            },
+        }
+    }
+
+    // RFC 7638: SHA-256 over the required public members in lexicographic
+    // order. A private key has the same thumbprint as its public half.
+    pub fn thumbprint(&self) -> Result<String> {
+        let mut members = BTreeMap::new();
+        {
+            let mut put = |name: &str, value: String| {
+                members.insert(name.to_owned(), Value::String(value));
+            };
+            match self.params {
+                JwkParams::Oct { ref k } => {
+                    put("k", base64_url_encode_bytes(k));
+                    put("kty", "oct".to_owned());
+                },
+                JwkParams::Rsa { ref n, ref e, .. } => {
+                    put("e", base64_url_encode_bytes(e));
+                    put("kty", "RSA".to_owned());
+                    put("n", base64_url_encode_bytes(n));
+                },
+                JwkParams::Ec { ref crv, ref x, ref y, .. } => {
+                    put("crv", crv.clone());
+                    put("kty", "EC".to_owned());
+                    put("x", base64_url_encode_bytes(x));
+                    put("y", base64_url_encode_bytes(y));
+                },
+            }
+        }
+        let canonical = try!(serde_json::to_string(&Value::Object(members)));
+        Ok(base64_url_encode_bytes(&try!(hash(MessageDigest::sha256(), canonical.as_bytes()))))
+    }
+
+    pub fn from_json(json: &str) -> Result<Jwk> {
+        Jwk::from_value(try!(serde_json::from_str(json)))
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(try!(serde_json::to_string(&self.to_value())))
+    }
+
+    pub fn from_value(value: Value) -> Result<Jwk> {
+        let object = match value {
+            Value::Object(object) => object,
+            _ => return Err(Error::InvalidJwk("a JWK must be a JSON object".to_owned()))
+        };
+        let params = match object.get("kty").and_then(|kty| kty.as_str()) {
+            Some("oct") => JwkParams::Oct { k: try!(member(&object, "k")) },
+            Some("RSA") => {
+                let private = if object.contains_key("d") {
+                    Some(RsaPrivateParams {
+                        d: try!(member(&object, "d")),
+                        p: try!(member(&object, "p")),
+                        q: try!(member(&object, "q")),
+                        dp: try!(member(&object, "dp")),
+                        dq: try!(member(&object, "dq")),
+                        qi: try!(member(&object, "qi")),
+                    })
+                } else {
+                    None
+                };
+                JwkParams::Rsa { n: try!(member(&object, "n")), e: try!(member(&object, "e")), private: private }
+            },
+            Some("EC") => {
+                let crv = match optional_string(&object, "crv") {
+                    Some(crv) => crv,
+                    None => return Err(Error::InvalidJwk("missing the crv parameter".to_owned()))
+                };
+                try!(curve(&crv));
+                let d = if object.contains_key("d") { Some(try!(member(&object, "d"))) } else { None };
+                JwkParams::Ec { crv: crv, x: try!(member(&object, "x")), y: try!(member(&object, "y")), d: d }
+            },
+            Some(kty) => return Err(Error::InvalidJwk(format!("unsupported key type {}", kty))),
+            None => return Err(Error::InvalidJwk("missing the kty parameter".to_owned()))
+        };
+        let x5c = match object.get("x5c") {
+            None => Vec::new(),
+            Some(&Value::Array(ref chain)) => try!(chain.iter().map(|cert| {
+                cert.as_str().and_then(|encoded| encoded.from_base64().ok())
+                    .ok_or_else(|| Error::InvalidJwk("x5c holds a certificate that is not base64".to_owned()))
+            }).collect()),
+            Some(_) => return Err(Error::InvalidJwk("x5c must be an array".to_owned()))
+        };
+        let digest = |name: &str| if object.contains_key(name) { member(&object, name).map(Some) } else { Ok(None) };
+        let jwk = Jwk {
+            kid: optional_string(&object, "kid"),
+            key_use: optional_string(&object, "use"),
+            alg: optional_string(&object, "alg"),
+            params: params,
+            x5c: x5c,
+            x5t: try!(digest("x5t")),
+            x5t_s256: try!(digest("x5t#S256")),
+        };
+        try!(jwk.check_certificates());
+        Ok(jwk)
+    }
+
+    // The first certificate must hold this key, and x5t and x5t#S256 must be
+    // its digests. A thumbprint without a certificate cannot be checked.
+    fn check_certificates(&self) -> Result<()> {
+        let leaf = match self.x5c.first() {
+            Some(der) => try!(X509::from_der(der).map_err(|_| Error::InvalidJwk("x5c holds a certificate that cannot be parsed".to_owned()))),
+            None => return Ok(())
+        };
+        let certified = try!(public_params(&try!(leaf.public_key())));
+        if self.to_public().map(|jwk| jwk.params) != Some(certified) {
+            return Err(Error::InvalidJwk("the first x5c certificate holds a different key".to_owned()));
+        }
+        for &(name, expected, digest) in [("x5t", &self.x5t, MessageDigest::sha1()), ("x5t#S256", &self.x5t_s256, MessageDigest::sha256())].iter() {
+            if let Some(ref expected) = *expected {
+                if try!(leaf.digest(digest)).to_vec() != *expected {
+                    return Err(Error::InvalidJwk(format!("{} does not match the first x5c certificate", name)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn certificates(&self) -> Result<Vec<X509>> {
+        Ok(try!(self.x5c.iter().map(|der| X509::from_der(der)).collect()))
+    }
+
+    // Builds a path from the first certificate through the rest of x5c to a
+    // certificate in `trusted`. Only needed when the JWK Set itself is not
+    // trusted, since the key is already bound to the certificate on parsing.
+    pub fn verify_chain(&self, trusted: &X509StoreRef) -> Result<()> {
+        let mut certificates = try!(self.certificates()).into_iter();
+        let leaf = match certificates.next() {
+            Some(leaf) => leaf,
+            None => return Err(Error::InvalidCertificateChain("the JWK has no x5c certificates".to_owned()))
+        };
+        let mut chain = try!(Stack::new());
+        for certificate in certificates {
+            try!(chain.push(certificate));
+        }
+        let mut context = try!(X509StoreContext::new());
+        let failure = try!(context.init(trusted, &leaf, &chain, |context| {
+            Ok(if try!(context.verify_cert()) { None } else { Some(context.error().to_string()) })
+        }));
+        match failure {
+            Some(reason) => Err(Error::InvalidCertificateChain(reason)),
+            None => Ok(())
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        let mut object = BTreeMap::new();
+        {
+            let mut put = |name: &str, bytes: &[u8]| {
+                object.insert(name.to_owned(), Value::String(base64_url_encode_bytes(bytes)));
+            };
+            match self.params {
+                JwkParams::Oct { ref k } => put("k", k),
+                JwkParams::Rsa { ref n, ref e, ref private } => {
+                    put("n", n);
+                    put("e", e);
+                    if let Some(ref private) = *private {
+                        put("d", &private.d);
+                        put("p", &private.p);
+                        put("q", &private.q);
+                        put("dp", &private.dp);
+                        put("dq", &private.dq);
+                        put("qi", &private.qi);
+                    }
+                },
+                JwkParams::Ec { ref x, ref y, ref d, .. } => {
+                    put("x", x);
+                    put("y", y);
+                    if let Some(ref d) = *d {
+                        put("d", d);
+                    }
+                },
+            }
+        }
+        let kty = match self.params {
+            JwkParams::Oct { .. } => "oct",
+            JwkParams::Rsa { .. } => "RSA",
+            JwkParams::Ec { ref crv, .. } => {
+                object.insert("crv".to_owned(), Value::String(crv.clone()));
+                "EC"
+            },
+        };
+        object.insert("kty".to_owned(), Value::String(kty.to_owned()));
+        for &(name, ref value) in [("kid", &self.kid), ("use", &self.key_use), ("alg", &self.alg)].iter() {
+            if let Some(ref value) = **value {
+                object.insert(name.to_owned(), Value::String(value.clone()));
+            }
+        }
+        if !self.x5c.is_empty() {
+            // Unlike every other member, x5c is standard base64 with padding.
+            let chain = self.x5c.iter().map(|der| Value::String(der.to_base64(base64::STANDARD))).collect();
+            object.insert("x5c".to_owned(), Value::Array(chain));
+        }
+        for &(name, ref digest) in [("x5t", &self.x5t), ("x5t#S256", &self.x5t_s256)].iter() {
+            if let Some(ref digest) = **digest {
+                object.insert(name.to_owned(), Value::String(base64_url_encode_bytes(digest)));
+            }
+        }
+        Value::Object(object)
+    }
+}
+
+impl Serialize for Jwk {
+    fn serialize<S>(&self, serializer: &mut S) -> result::Result<(), S::Error>
+        where S: serde::Serializer
+    {
+        self.to_value().serialize(serializer)
+    }
+}
+
+impl serde::Deserialize for Jwk {
+    fn deserialize<D>(deserializer: &mut D) -> result::Result<Jwk, D::Error>
+        where D: serde::Deserializer
+    {
+        let value: Value = try!(serde::Deserialize::deserialize(deserializer));
+        Jwk::from_value(value).map_err(|err| serde::de::Error::custom(err.to_string()))
+    }
+}
+
+#[test]
+fn rfc7517_public_keys_import_and_export() {
+    // RFC 7517 A.1
+    let ec = Jwk::from_json(r#"{"kty":"EC","crv":"P-256","x":"MKBCTNIcKUSDii11ySs3526iDZ8AiTo7Tu6KPAqv7D4","y":"4Etl6SRW2YiLUrN5vfvVHuhp7x8PxltmWWlbbM4IFyM","use":"enc","kid":"1"}"#).unwrap();
+    assert_eq!(ec.kid, Some("1".to_owned()));
+    assert_eq!(ec.key_use, Some("enc".to_owned()));
+    assert!(!ec.is_private());
+    assert!(PKey::public_key_from_der(&ec.to_key().unwrap()).is_ok());
+    assert_eq!(Jwk::from_json(&ec.to_json().unwrap()).unwrap(), ec);
+
+    let oct = Jwk::from_json(r#"{"kty":"oct","k":"AyM1SysPpbyDfgZld3umj1qzKObwVMkoqQ-EstJQLr_T-1qS0gZH75aKtMN3Yj0iPS4hcgUuTwjAzZr1Z9CAow","kid":"HMAC key used in JWS spec Appendix A.1 example"}"#).unwrap();
+    match oct.params {
+        JwkParams::Oct { ref k } => assert_eq!(k.len(), 64),
+        ref other => panic!("expected an oct key, got {:?}", other)
+    }
+    assert!(oct.to_public().is_none());
+}
+
+#[test]
+fn generated_keys_survive_the_jwk_round_trip() {
+    use claims::Claims;
+    use jws::JWS;
+    use jws_header::{Header, ALGORITHM};
+    use openssl::ec::EcGroup;
+
+    let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let k1 = PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::SECP256K1).unwrap()).unwrap()).unwrap();
+    for &(ref key, ref alg) in [(rsa, ALGORITHM::RS256), (k1, ALGORITHM::ES256K)].iter() {
+        let private = Jwk::from_key(&key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        assert!(private.is_private());
+        let public = private.to_public().unwrap();
+        assert_eq!(Jwk::from_key(&key.public_key_to_pem().unwrap()).unwrap(), public);
+
+        let reloaded = Jwk::from_json(&private.to_json().unwrap()).unwrap();
+        let mut header = Header::new();
+        header.alg = alg.clone();
+        let token = JWS::from_claims(header, Claims::new()).encode(&reloaded.to_key().unwrap(), alg.clone()).unwrap();
+        assert!(JWS::decode_jwt(token, &public.to_key().unwrap(), alg.clone()).is_ok());
+    }
+}
+
+#[test]
+fn rfc7638_thumbprints_ignore_optional_and_private_members() {
+    // RFC 7638 3.1
+    let jwk = Jwk::from_json(r#"{"kty":"RSA","n":"0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw","e":"AQAB","alg":"RS256","kid":"2011-04-29"}"#).unwrap();
+    assert_eq!(jwk.thumbprint().unwrap(), "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs");
+
+    let private = Jwk::from_key(&PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap().private_key_to_pem_pkcs8().unwrap()).unwrap();
+    assert_eq!(private.thumbprint().unwrap(), private.to_public().unwrap().thumbprint().unwrap());
+}
+
+#[cfg(test)]
+fn certificate(subject: &str, key: &PKey<Private>, issuer: Option<(&X509, &PKey<Private>)>) -> X509 {
+    use openssl::asn1::Asn1Time;
+    use openssl::x509::{X509Builder, X509NameBuilder};
+    use openssl::x509::extension::BasicConstraints;
+
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", subject).unwrap();
+    let name = name.build();
+    let mut builder = X509Builder::new().unwrap();
+    builder.set_version(2).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_pubkey(key).unwrap();
+    builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+    builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+    match issuer {
+        Some((issuer, issuer_key)) => {
+            builder.set_issuer_name(issuer.subject_name()).unwrap();
+            builder.sign(issuer_key, MessageDigest::sha256()).unwrap();
+        },
+        None => {
+            builder.set_issuer_name(&name).unwrap();
+            builder.append_extension(BasicConstraints::new().critical().ca().build().unwrap()).unwrap();
+            builder.sign(key, MessageDigest::sha256()).unwrap();
+        }
+    }
+    builder.build()
+}
+
+#[test]
+fn certificate_chains_are_bound_to_the_key_and_verified_on_request() {
+    use openssl::x509::store::X509StoreBuilder;
+    use jwk_set::JwkSet;
+
+    let ca_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let ca = certificate("Example Root", &ca_key, None);
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let leaf = certificate("signing.example", &key, Some((&ca, &ca_key)));
+
+    let mut jwk = Jwk::from_key(&key.public_key_to_pem().unwrap()).unwrap();
+    jwk.kid = Some("signing".to_owned());
+    jwk.x5c = vec![leaf.to_der().unwrap()];
+    jwk.x5t = Some(leaf.digest(MessageDigest::sha1()).unwrap().to_vec());
+    jwk.x5t_s256 = Some(leaf.digest(MessageDigest::sha256()).unwrap().to_vec());
+    let json = jwk.to_json().unwrap();
+    assert!(json.contains("\"x5t#S256\""));
+    assert_eq!(Jwk::from_json(&json).unwrap(), jwk);
+
+    let mut store = X509StoreBuilder::new().unwrap();
+    store.add_cert(ca.clone()).unwrap();
+    let store = store.build();
+    assert!(jwk.verify_chain(&store).is_ok());
+    assert_err!(jwk.verify_chain(&X509StoreBuilder::new().unwrap().build()), Error::InvalidCertificateChain(_));
+
+    let mut other = Jwk::from_key(&ca_key.public_key_to_pem().unwrap()).unwrap();
+    other.x5c = jwk.x5c.clone();
+    assert_err!(Jwk::from_json(&other.to_json().unwrap()), Error::InvalidJwk(reason) => assert!(reason.contains("different key")));
+    let mut wrong_digest = jwk.clone();
+    wrong_digest.x5t = Some(vec![0; 20]);
+    assert!(Jwk::from_json(&wrong_digest.to_json().unwrap()).is_err());
+
+    let set = JwkSet::new(vec![Jwk::oct(::TEST_HS256_SECRET), jwk.clone()]);
+    let (trusted, skipped) = set.with_trusted_chains(&store);
+    assert_eq!(trusted.keys(), &[jwk][..]);
+    assert_eq!(skipped.iter().map(|key| key.index).collect::<Vec<_>>(), vec![0]);
+}
+
+#[test]
+fn incomplete_or_unknown_jwks_are_rejected() {
+    assert_err!(Jwk::from_json(r#"{"kty":"RSA","n":"AQAB"}"#), Error::InvalidJwk(reason) => assert!(reason.contains(" e ")));
+    assert!(Jwk::from_json(r#"{"kty":"OKP","crv":"Ed25519","x":"AQAB"}"#).is_err());
+    assert!(Jwk::from_json(r#"{"kty":"EC","crv":"P-192","x":"AQAB","y":"AQAB"}"#).is_err());
+    assert!(Jwk::from_json(r#"["kty"]"#).is_err());
+}
@@ -0,0 +1,723 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+#[cfg(feature = "jwks-async")]
+use std::future::Future;
+use std::io::{self, Read};
+#[cfg(feature = "jwks-async")]
+use std::pin::Pin;
+#[cfg(feature = "jwks-async")]
+use std::task::{Context, Poll};
+#[cfg(feature = "jwks-async")]
+use futures_io::AsyncRead;
+use openssl::x509::store::X509StoreRef;
+use serde_json::{self, Value};
+use claims::Claims;
+use jwk::{Jwk, JwkParams};
+use jws::{JWS, ParsedToken};
+use jws_header::{Header, ALGORITHM};
+use limits::Limits;
+use error::{Error, Result};
+
+// Tokens without a kid are matched through by_family, so large sets are not
+// scanned key by key; by_thumbprint serves embedded jwk and cnf.jkt lookups.
+#[derive(Debug, PartialEq, Clone)]
+pub struct JwkSet {
+    keys: Vec<Jwk>,
+    by_kid: HashMap<String, Vec<usize>>,
+    by_family: HashMap<Family, Vec<usize>>,
+    by_thumbprint: HashMap<String, usize>,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+enum Family {
+    Hmac,
+    Rsa,
+    Ec(String),
+}
+
+impl Family {
+    fn of_key(jwk: &Jwk) -> Family {
+        match jwk.params {
+            JwkParams::Oct { .. } => Family::Hmac,
+            JwkParams::Rsa { .. } => Family::Rsa,
+            JwkParams::Ec { ref crv, .. } => Family::Ec(crv.clone()),
+        }
+    }
+
+    fn of_algorithm(alg: &ALGORITHM) -> Option<Family> {
+        match *alg {
+            ALGORITHM::HS256 | ALGORITHM::HS384 | ALGORITHM::HS512 => Some(Family::Hmac),
+            ALGORITHM::RS256 | ALGORITHM::RS384 | ALGORITHM::RS512 |
+            ALGORITHM::PS256 | ALGORITHM::PS384 | ALGORITHM::PS512 => Some(Family::Rsa),
+            ALGORITHM::ES256 => Some(Family::Ec("P-256".to_owned())),
+            ALGORITHM::ES384 => Some(Family::Ec("P-384".to_owned())),
+            ALGORITHM::ES512 => Some(Family::Ec("P-521".to_owned())),
+            ALGORITHM::ES256K => Some(Family::Ec("secp256k1".to_owned())),
+            ALGORITHM::EdDSA | ALGORITHM::None | ALGORITHM::Custom(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SkippedKey {
+    pub index: usize,
+    pub kid: Option<String>,
+    pub error: Error,
+}
+
+fn malformed() -> Error {
+    Error::InvalidJwk("the JWK set is not valid JSON".to_owned())
+}
+
+fn check_chain(key: &Value, max_x5c_chain: usize) -> Result<()> {
+    match key.find("x5c") {
+        Some(&Value::Array(ref chain)) if chain.len() > max_x5c_chain => {
+            Err(Error::ChainTooLong { length: chain.len(), limit: max_x5c_chain })
+        },
+        _ => Ok(())
+    }
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    byte == b' ' || byte == b'\t' || byte == b'\n' || byte == b'\r'
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Scanned {
+    More,
+    Done,
+    // The byte ends a number or literal and belongs to what follows it.
+    DoneBefore,
+}
+
+// Finds the end of one JSON value without parsing it.
+#[derive(Debug, Default)]
+struct ValueScan {
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    scalar: bool,
+}
+
+impl ValueScan {
+    fn new(first: u8) -> ValueScan {
+        match first {
+            b'"' => ValueScan { in_string: true, ..ValueScan::default() },
+            b'{' | b'[' => ValueScan { depth: 1, ..ValueScan::default() },
+            _ => ValueScan { scalar: true, ..ValueScan::default() }
+        }
+    }
+
+    fn step(&mut self, byte: u8) -> Scanned {
+        if self.in_string {
+            if self.escaped {
+                self.escaped = false;
+            } else if byte == b'\\' {
+                self.escaped = true;
+            } else if byte == b'"' {
+                self.in_string = false;
+                if self.depth == 0 {
+                    return Scanned::Done;
+                }
+            }
+            return Scanned::More;
+        }
+        if self.scalar {
+            return match byte {
+                b',' | b'}' | b']' => Scanned::DoneBefore,
+                _ if is_whitespace(byte) => Scanned::DoneBefore,
+                _ => Scanned::More
+            };
+        }
+        match byte {
+            b'"' => self.in_string = true,
+            b'{' | b'[' => self.depth += 1,
+            b'}' | b']' => {
+                self.depth -= 1;
+                if self.depth == 0 {
+                    return Scanned::Done;
+                }
+            },
+            _ => {}
+        }
+        Scanned::More
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum State {
+    Start,
+    Member,
+    Name,
+    Colon,
+    Value,
+    Skip,
+    AfterValue,
+    Key,
+    InKey,
+    AfterKey,
+    Done,
+}
+
+// Takes the JSON a byte at a time, however it arrives, so that only one key
+// is held in memory at a time. Keys over the size limit are skipped without
+// being buffered.
+struct Parser {
+    state: State,
+    scan: ValueScan,
+    captured: Vec<u8>,
+    key_size: usize,
+    in_keys: bool,
+    found: bool,
+    read: usize,
+    index: usize,
+    keys: Vec<Jwk>,
+    skipped: Vec<SkippedKey>,
+    max_size: usize,
+    max_key_size: usize,
+    max_x5c_chain: usize,
+}
+
+impl Parser {
+    fn new(limits: &Limits) -> Parser {
+        Parser {
+            state: State::Start,
+            scan: ValueScan::default(),
+            captured: Vec::new(),
+            key_size: 0,
+            in_keys: false,
+            found: false,
+            read: 0,
+            index: 0,
+            keys: Vec::new(),
+            skipped: Vec::new(),
+            max_size: limits.max_jwks_size,
+            max_key_size: limits.max_jwk_size,
+            max_x5c_chain: limits.max_x5c_chain,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    // Bytes after the end of the set are ignored.
+    fn feed(&mut self, bytes: &[u8]) -> Result<()> {
+        for &byte in bytes {
+            if self.is_done() {
+                break;
+            }
+            self.read += 1;
+            if self.read > self.max_size {
+                return Err(Error::PayloadTooLarge { size: self.read, limit: self.max_size });
+            }
+            try!(self.step(byte));
+        }
+        Ok(())
+    }
+
+    fn capture(&mut self, byte: u8) {
+        self.key_size += 1;
+        if self.key_size <= self.max_key_size {
+            self.captured.push(byte);
+        } else if !self.captured.is_empty() {
+            self.captured = Vec::new();
+        }
+    }
+
+    fn begin(&mut self, byte: u8, state: State) {
+        self.scan = ValueScan::new(byte);
+        self.captured.clear();
+        self.key_size = 0;
+        self.capture(byte);
+        self.state = state;
+    }
+
+    fn step(&mut self, byte: u8) -> Result<()> {
+        match self.state {
+            _ if is_whitespace(byte) && self.waits_for_token() => {},
+            State::Start => match byte {
+                b'{' => self.state = State::Member,
+                _ => return Err(Error::InvalidJwk("a JWK set must be a JSON object".to_owned()))
+            },
+            State::Member => match byte {
+                b'"' => self.begin(byte, State::Name),
+                b'}' => self.state = State::Done,
+                _ => return Err(malformed())
+            },
+            State::Name => {
+                self.capture(byte);
+                if self.key_size > self.max_key_size {
+                    return Err(Error::PayloadTooLarge { size: self.key_size, limit: self.max_key_size });
+                }
+                if self.scan.step(byte) == Scanned::Done {
+                    let name: String = try!(serde_json::from_slice(&self.captured));
+                    self.in_keys = name == "keys";
+                    self.state = State::Colon;
+                }
+            },
+            State::Colon => match byte {
+                b':' => self.state = State::Value,
+                _ => return Err(malformed())
+            },
+            State::Value => if self.in_keys {
+                if byte != b'[' {
+                    return Err(Error::InvalidJwk("a JWK set must have a keys array".to_owned()));
+                }
+                self.found = true;
+                self.state = State::Key;
+            } else {
+                self.scan = ValueScan::new(byte);
+                self.state = State::Skip;
+            },
+            State::Skip => match self.scan.step(byte) {
+                Scanned::More => {},
+                Scanned::Done => self.state = State::AfterValue,
+                Scanned::DoneBefore => {
+                    self.state = State::AfterValue;
+                    return self.step(byte);
+                }
+            },
+            State::AfterValue => match byte {
+                b',' => self.state = State::Member,
+                b'}' => self.state = State::Done,
+                _ => return Err(malformed())
+            },
+            State::Key => match byte {
+                b']' => self.state = State::AfterValue,
+                _ => self.begin(byte, State::InKey)
+            },
+            State::InKey => match self.scan.step(byte) {
+                Scanned::More => self.capture(byte),
+                Scanned::Done => {
+                    self.capture(byte);
+                    self.end_key();
+                },
+                Scanned::DoneBefore => {
+                    self.end_key();
+                    return self.step(byte);
+                }
+            },
+            State::AfterKey => match byte {
+                b',' => self.state = State::Key,
+                b']' => self.state = State::AfterValue,
+                _ => return Err(malformed())
+            },
+            State::Done => {}
+        }
+        Ok(())
+    }
+
+    fn waits_for_token(&self) -> bool {
+        match self.state {
+            State::Name | State::Skip | State::InKey | State::Done => false,
+            _ => true
+        }
+    }
+
+    fn end_key(&mut self) {
+        let index = self.index;
+        self.index += 1;
+        self.state = State::AfterKey;
+        if self.key_size > self.max_key_size {
+            let error = Error::PayloadTooLarge { size: self.key_size, limit: self.max_key_size };
+            self.skipped.push(SkippedKey { index: index, kid: None, error: error });
+            return;
+        }
+        let raw = ::std::mem::take(&mut self.captured);
+        match serde_json::from_slice::<Value>(&raw) {
+            Ok(value) => {
+                let kid = value.find("kid").and_then(|kid| kid.as_str()).map(|kid| kid.to_owned());
+                match check_chain(&value, self.max_x5c_chain).and_then(|_| Jwk::from_value(value)) {
+                    Ok(jwk) => self.keys.push(jwk),
+                    Err(err) => self.skipped.push(SkippedKey { index: index, kid: kid, error: err })
+                }
+            },
+            Err(err) => self.skipped.push(SkippedKey { index: index, kid: None, error: Error::from(err) })
+        }
+    }
+
+    fn finish(self) -> Result<(JwkSet, Vec<SkippedKey>)> {
+        if !self.is_done() {
+            return Err(Error::InvalidJwk("the JWK set ends early".to_owned()));
+        }
+        if !self.found {
+            return Err(Error::InvalidJwk("a JWK set must have a keys array".to_owned()));
+        }
+        Ok((JwkSet::new(self.keys), self.skipped))
+    }
+}
+
+// Reads a JWK set from an AsyncRead, as JwkSet::from_reader does from a Read.
+#[cfg(feature = "jwks-async")]
+pub struct ReadJwkSet<R> {
+    reader: R,
+    parser: Option<Parser>,
+    buffer: Vec<u8>,
+}
+
+#[cfg(feature = "jwks-async")]
+impl<R: AsyncRead + Unpin> Future for ReadJwkSet<R> {
+    type Output = Result<(JwkSet, Vec<SkippedKey>)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            let read = match Pin::new(&mut this.reader).poll_read(cx, &mut this.buffer) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(ref err)) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(Error::ReadError(err))),
+                Poll::Ready(Ok(read)) => read
+            };
+            let mut parser = this.parser.take().expect("ReadJwkSet polled after completion");
+            if let Err(err) = parser.feed(&this.buffer[..read]) {
+                return Poll::Ready(Err(err));
+            }
+            if read == 0 || parser.is_done() {
+                return Poll::Ready(parser.finish());
+            }
+            this.parser = Some(parser);
+        }
+    }
+}
+
+pub fn fits(jwk: &Jwk, alg: &ALGORITHM) -> bool {
+    let kty_fits = match (&jwk.params, alg) {
+        (&JwkParams::Oct { .. }, &ALGORITHM::HS256) |
+        (&JwkParams::Oct { .. }, &ALGORITHM::HS384) |
+        (&JwkParams::Oct { .. }, &ALGORITHM::HS512) => true,
+        (&JwkParams::Rsa { .. }, &ALGORITHM::RS256) |
+        (&JwkParams::Rsa { .. }, &ALGORITHM::RS384) |
+        (&JwkParams::Rsa { .. }, &ALGORITHM::RS512) |
+        (&JwkParams::Rsa { .. }, &ALGORITHM::PS256) |
+        (&JwkParams::Rsa { .. }, &ALGORITHM::PS384) |
+        (&JwkParams::Rsa { .. }, &ALGORITHM::PS512) => true,
+        (&JwkParams::Ec { ref crv, .. }, &ALGORITHM::ES256) => crv == "P-256",
+        (&JwkParams::Ec { ref crv, .. }, &ALGORITHM::ES384) => crv == "P-384",
+        (&JwkParams::Ec { ref crv, .. }, &ALGORITHM::ES512) => crv == "P-521",
+        (&JwkParams::Ec { ref crv, .. }, &ALGORITHM::ES256K) => crv == "secp256k1",
+        _ => false
+    };
+    kty_fits
+        && jwk.key_use.as_ref().map_or(true, |key_use| key_use == "sig")
+        && jwk.alg.as_ref().map_or(true, |key_alg| key_alg == alg.name())
+}
+
+impl JwkSet {
+    pub fn new(keys: Vec<Jwk>) -> JwkSet {
+        let mut by_kid: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut by_family: HashMap<Family, Vec<usize>> = HashMap::new();
+        let mut by_thumbprint = HashMap::new();
+        for (index, jwk) in keys.iter().enumerate() {
+            if let Some(ref kid) = jwk.kid {
+                by_kid.entry(kid.clone()).or_insert_with(Vec::new).push(index);
+            }
+            by_family.entry(Family::of_key(jwk)).or_insert_with(Vec::new).push(index);
+            if let Ok(thumbprint) = jwk.thumbprint() {
+                by_thumbprint.entry(thumbprint).or_insert(index);
+            }
+        }
+        JwkSet {
+            keys: keys,
+            by_kid: by_kid,
+            by_family: by_family,
+            by_thumbprint: by_thumbprint,
+        }
+    }
+
+    // Keys this crate cannot use are skipped, as RFC 7517 section 5 asks.
+    pub fn from_json(json: &str) -> Result<JwkSet> {
+        JwkSet::from_json_with_limits(json, &Limits::new())
+    }
+
+    pub fn from_json_with_limits(json: &str, limits: &Limits) -> Result<JwkSet> {
+        if json.len() > limits.max_jwks_size {
+            return Err(Error::PayloadTooLarge { size: json.len(), limit: limits.max_jwks_size });
+        }
+        let keys = match try!(serde_json::from_str::<Value>(json)) {
+            Value::Object(mut object) => match object.remove("keys") {
+                Some(Value::Array(keys)) => keys,
+                _ => return Err(Error::InvalidJwk("a JWK set must have a keys array".to_owned()))
+            },
+            _ => return Err(Error::InvalidJwk("a JWK set must be a JSON object".to_owned()))
+        };
+        Ok(JwkSet::new(keys.into_iter()
+            .filter(|key| check_chain(key, limits.max_x5c_chain).is_ok())
+            .filter_map(|key| Jwk::from_value(key).ok())
+            .collect()))
+    }
+
+    // Unlike from_json, the document is never held in memory as a whole, and the keys
+    // that were skipped are reported along with the reason.
+    pub fn from_reader<R: Read>(reader: R) -> Result<(JwkSet, Vec<SkippedKey>)> {
+        JwkSet::from_reader_with_limits(reader, &Limits::new())
+    }
+
+    pub fn from_reader_with_limits<R: Read>(mut reader: R, limits: &Limits) -> Result<(JwkSet, Vec<SkippedKey>)> {
+        let mut parser = Parser::new(limits);
+        let mut buffer = [0; 8192];
+        while !parser.is_done() {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(Error::ReadError(err))
+            };
+            try!(parser.feed(&buffer[..read]));
+        }
+        parser.finish()
+    }
+
+    #[cfg(feature = "jwks-async")]
+    pub fn from_async_reader<R: AsyncRead + Unpin>(reader: R) -> ReadJwkSet<R> {
+        JwkSet::from_async_reader_with_limits(reader, &Limits::new())
+    }
+
+    #[cfg(feature = "jwks-async")]
+    pub fn from_async_reader_with_limits<R: AsyncRead + Unpin>(reader: R, limits: &Limits) -> ReadJwkSet<R> {
+        ReadJwkSet {
+            reader: reader,
+            parser: Some(Parser::new(limits)),
+            buffer: vec![0; 8192],
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        let mut object = ::std::collections::BTreeMap::new();
+        object.insert("keys".to_owned(), Value::Array(self.keys.iter().map(|jwk| jwk.to_value()).collect()));
+        Ok(try!(serde_json::to_string(&Value::Object(object))))
+    }
+
+    pub fn keys(&self) -> &[Jwk] {
+        &self.keys
+    }
+
+    // The keys whose x5c chain verifies against `trusted`. Keys without a
+    // chain cannot be vouched for and are skipped along with the rest.
+    pub fn with_trusted_chains(&self, trusted: &X509StoreRef) -> (JwkSet, Vec<SkippedKey>) {
+        let mut keys = Vec::new();
+        let mut skipped = Vec::new();
+        for (index, jwk) in self.keys.iter().enumerate() {
+            match jwk.verify_chain(trusted) {
+                Ok(()) => keys.push(jwk.clone()),
+                Err(err) => skipped.push(SkippedKey { index: index, kid: jwk.kid.clone(), error: err })
+            }
+        }
+        (JwkSet::new(keys), skipped)
+    }
+
+    pub fn get(&self, kid: &str) -> Option<&Jwk> {
+        self.by_kid.get(kid).and_then(|indexes| indexes.first()).map(|&index| &self.keys[index])
+    }
+
+    // A token without a kid is only matched when exactly one key of the
+    // algorithm's family fits.
+    pub fn find(&self, kid: Option<&str>, alg: &ALGORITHM) -> Option<&Jwk> {
+        let indexes = match kid {
+            Some(kid) => self.by_kid.get(kid),
+            None => Family::of_algorithm(alg).and_then(|family| self.by_family.get(&family))
+        };
+        let mut candidates: Vec<&Jwk> = indexes
+            .map(|indexes| indexes.iter().map(|&index| &self.keys[index]).collect())
+            .unwrap_or_else(Vec::new);
+        candidates.retain(|jwk| fits(jwk, alg));
+        match (kid, candidates.len()) {
+            (Some(_), _) | (None, 1) => candidates.first().cloned(),
+            _ => None
+        }
+    }
+
+    // The RFC 7638 thumbprint of a key, as carried in a cnf.jkt claim.
+    pub fn find_by_thumbprint(&self, thumbprint: &str, alg: &ALGORITHM) -> Option<&Jwk> {
+        self.by_thumbprint.get(thumbprint)
+            .map(|&index| &self.keys[index])
+            .filter(|jwk| fits(jwk, alg))
+    }
+
+    // A header jwk without a kid only selects a key of this set by its
+    // thumbprint; the embedded key itself is never used.
+    pub fn key_for(&self, header: &Header, alg: &ALGORITHM) -> Result<Vec<u8>> {
+        let kid = header.kid.as_ref().map(|kid| kid.as_str());
+        let embedded = match (kid, header.get::<Value>("jwk")) {
+            (None, Some(jwk)) => Jwk::from_value(jwk).and_then(|jwk| jwk.thumbprint()).ok(),
+            _ => None
+        };
+        let found = match embedded {
+            Some(ref thumbprint) => self.find_by_thumbprint(thumbprint, alg),
+            None => self.find(kid, alg)
+        };
+        match found {
+            Some(jwk) => jwk.to_key(),
+            None => Err(Error::UnknownKey { kid: header.kid.clone() })
+        }
+    }
+
+    // The algorithm comes from the caller, never from the token.
+    pub fn decode_jwt(&self, token: String, algorithm: ALGORITHM) -> Result<(Header, Claims)> {
+        let key = {
+            let parsed = try!(ParsedToken::parse(&token));
+            try!(self.key_for(parsed.header(), &algorithm))
+        };
+        JWS::decode_jwt(token, &key, algorithm)
+    }
+}
+
+#[cfg(test)]
+fn token_with_kid(kid: Option<&str>, secret: &[u8]) -> String {
+    let mut header = Header::new();
+    header.kid = kid.map(|kid| kid.to_owned());
+    JWS::from_claims(header, Claims::new()).encode(secret, ALGORITHM::HS256).unwrap()
+}
+
+#[test]
+fn keys_are_looked_up_by_kid() {
+    let set = JwkSet::from_json(r#"{"keys":[
+        {"kty":"oct","kid":"a","use":"sig","k":"c2VjcmV0IGtleSBhIGZvciB0aGUgSFMyNTYgdGVzdHM"},
+        {"kty":"oct","kid":"b","alg":"HS256","k":"c2VjcmV0IGtleSBiIGZvciB0aGUgSFMyNTYgdGVzdHM"},
+        {"kty":"OKP","kid":"c","crv":"X25519","x":"AQAB"}
+    ]}"#).unwrap();
+    assert_eq!(set.keys().len(), 2);
+    assert_eq!(set.get("b").unwrap().alg, Some("HS256".to_owned()));
+
+    assert!(set.decode_jwt(token_with_kid(Some("a"), b"secret key a for the HS256 tests"), ALGORITHM::HS256).is_ok());
+    assert!(set.decode_jwt(token_with_kid(Some("b"), b"secret key b for the HS256 tests"), ALGORITHM::HS256).is_ok());
+    assert!(set.decode_jwt(token_with_kid(Some("b"), b"secret key a for the HS256 tests"), ALGORITHM::HS256).is_err());
+    assert_err!(set.decode_jwt(token_with_kid(Some("c"), b"secret key a for the HS256 tests"), ALGORITHM::HS256), Error::UnknownKey { kid } => assert_eq!(kid, Some("c".to_owned())));
+
+    let round_trip = JwkSet::from_json(&set.to_json().unwrap()).unwrap();
+    assert_eq!(round_trip, set);
+}
+
+#[test]
+fn keys_are_filtered_by_use_and_alg() {
+    let set = JwkSet::from_json(r#"{"keys":[
+        {"kty":"oct","kid":"enc","use":"enc","k":"c2VjcmV0"},
+        {"kty":"oct","kid":"hs512","alg":"HS512","k":"c2VjcmV0"}
+    ]}"#).unwrap();
+    assert!(set.find(Some("enc"), &ALGORITHM::HS256).is_none());
+    assert!(set.find(Some("hs512"), &ALGORITHM::HS256).is_none());
+    assert!(set.find(Some("hs512"), &ALGORITHM::HS512).is_some());
+    assert!(set.find(Some("hs512"), &ALGORITHM::RS256).is_none());
+}
+
+#[test]
+fn tokens_without_a_kid_need_an_unambiguous_key() {
+    let single = JwkSet::new(vec![Jwk::oct(b"the only key, 32 bytes or longer")]);
+    assert!(single.decode_jwt(token_with_kid(None, b"the only key, 32 bytes or longer"), ALGORITHM::HS256).is_ok());
+
+    let double = JwkSet::new(vec![Jwk::oct(b"the first key, 32 bytes or longer"), Jwk::oct(b"the second key, 32 bytes or more")]);
+    assert_err!(double.decode_jwt(token_with_kid(None, b"the first key, 32 bytes or longer"), ALGORITHM::HS256), Error::UnknownKey { kid: None });
+    assert!(JwkSet::from_json(r#"{"kty":"oct"}"#).is_err());
+}
+
+#[test]
+fn kid_less_tokens_are_matched_by_family_or_thumbprint() {
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+
+    let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut keys: Vec<Jwk> = (0..40).map(|index| Jwk::oct(format!("partner secret {:02}, 32 bytes long", index).as_bytes())).collect();
+    keys.push(Jwk::from_key(&rsa.public_key_to_pem().unwrap()).unwrap());
+    let set = JwkSet::new(keys);
+    let rsa_jwk = set.keys()[40].clone();
+    assert_eq!(set.find(None, &ALGORITHM::RS256), Some(&rsa_jwk));
+    assert!(set.find(None, &ALGORITHM::HS256).is_none());
+    assert!(set.find(None, &ALGORITHM::ES256).is_none());
+
+    let thumbprint = rsa_jwk.thumbprint().unwrap();
+    assert_eq!(set.find_by_thumbprint(&thumbprint, &ALGORITHM::PS256), Some(&rsa_jwk));
+    assert!(set.find_by_thumbprint(&thumbprint, &ALGORITHM::HS256).is_none());
+    let hmac = set.keys()[7].thumbprint().unwrap();
+    assert_eq!(set.find_by_thumbprint(&hmac, &ALGORITHM::HS256), Some(&set.keys()[7]));
+
+    let embedded = |signer: &PKey<::openssl::pkey::Private>| {
+        let mut header = Header::new();
+        header.alg = ALGORITHM::RS256;
+        header.set("jwk", Jwk::from_key(&signer.public_key_to_pem().unwrap()).unwrap().to_value());
+        JWS::from_claims(header, Claims::new()).encode(&signer.private_key_to_pem_pkcs8().unwrap(), ALGORITHM::RS256).unwrap()
+    };
+    assert!(set.decode_jwt(embedded(&rsa), ALGORITHM::RS256).is_ok());
+    let attacker = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    assert_err!(set.decode_jwt(embedded(&attacker), ALGORITHM::RS256), Error::UnknownKey { kid: None });
+}
+
+#[test]
+fn key_sets_stream_from_a_reader() {
+    let json = r#" { "issuer": {"name": "x", "tags": ["a]", "}"]}, "count": 3,
+        "keys": [
+            {"kty":"oct","kid":"a","k":"c2VjcmV0IGtleSBhIGZvciB0aGUgSFMyNTYgdGVzdHM"},
+            {"kty":"OKP","kid":"x25519","crv":"X25519","x":"AQAB"},
+            {"kty":"oct","kid":"broken"},
+            {"kty":"oct","kid":"b","alg":"HS256","k":"c2VjcmV0IGtleSBiIGZvciB0aGUgSFMyNTYgdGVzdHM","note":"has \"quotes\""}
+        ] } "#;
+    let (set, skipped) = JwkSet::from_reader(json.as_bytes()).unwrap();
+    assert_eq!(set, JwkSet::from_json(json).unwrap());
+    assert_eq!(set.keys().len(), 2);
+    assert!(set.get("a").is_some() && set.get("b").is_some());
+    assert_eq!(skipped.iter().map(|key| (key.index, key.kid.clone())).collect::<Vec<_>>(),
+               vec![(1, Some("x25519".to_owned())), (2, Some("broken".to_owned()))]);
+    match skipped[1].error {
+        Error::InvalidJwk(_) => {},
+        ref other => panic!("expected InvalidJwk, got {:?}", other)
+    }
+
+    let (empty, skipped) = JwkSet::from_reader(&b"{\"keys\":[]}"[..]).unwrap();
+    assert!(empty.keys().is_empty() && skipped.is_empty());
+    assert!(JwkSet::from_reader(&b"{\"keys\":[{\"kty\":\"oct\""[..]).is_err());
+    assert!(JwkSet::from_reader(&b"{\"other\":1}"[..]).is_err());
+    assert!(JwkSet::from_reader(&b"[]"[..]).is_err());
+}
+
+#[test]
+fn key_sets_are_bounded_by_the_limits() {
+    let json = r#"{"keys":[
+        {"kty":"oct","kid":"a","k":"c2VjcmV0IGtleSBhIGZvciB0aGUgSFMyNTYgdGVzdHM"},
+        {"kty":"oct","kid":"b","k":"c2VjcmV0IGtleSBiIGZvciB0aGUgSFMyNTYgdGVzdHM","x5c":["MA","MA","MA"]}
+    ]}"#;
+    let limits = Limits { max_x5c_chain: 2, ..Limits::new() };
+    let set = JwkSet::from_json_with_limits(json, &limits).unwrap();
+    assert!(set.get("a").is_some() && set.get("b").is_none());
+    let (set, skipped) = JwkSet::from_reader_with_limits(json.as_bytes(), &limits).unwrap();
+    assert_eq!(set.keys().len(), 1);
+    match skipped[0].error {
+        Error::ChainTooLong { length: 3, limit: 2 } => {},
+        ref other => panic!("expected ChainTooLong, got {:?}", other)
+    }
+
+    let limits = Limits { max_jwks_size: 64, ..Limits::new() };
+    assert_err!(JwkSet::from_json_with_limits(json, &limits), Error::PayloadTooLarge { limit: 64, .. });
+    assert_err!(JwkSet::from_reader_with_limits(json.as_bytes(), &limits), Error::PayloadTooLarge { size: 65, limit: 64 });
+}
+
+#[test]
+fn oversized_keys_are_skipped_without_being_buffered() {
+    let padding = "x".repeat(4096);
+    let json = format!(r#"{{"keys":[{{"kty":"oct","kid":"big","note":"{}"}},{{"kty":"oct","kid":"a","k":"c2VjcmV0IGtleSBhIGZvciB0aGUgSFMyNTYgdGVzdHM"}}]}}"#, padding);
+    let limits = Limits { max_jwk_size: 1024, ..Limits::new() };
+    let (set, skipped) = JwkSet::from_reader_with_limits(json.as_bytes(), &limits).unwrap();
+    assert!(set.get("a").is_some() && set.get("big").is_none());
+    assert_eq!(skipped.len(), 1);
+    match skipped[0].error {
+        Error::PayloadTooLarge { limit: 1024, .. } => {},
+        ref other => panic!("expected PayloadTooLarge, got {:?}", other)
+    }
+    assert!(JwkSet::from_reader(&b"{\"keys\":[1, true, {}] , \"n\" : -1.5e3 }"[..]).unwrap().1.len() == 3);
+}
+
+#[cfg(feature = "jwks-async")]
+#[test]
+fn key_sets_stream_from_an_async_reader() {
+    use std::task::Waker;
+
+    let json = r#"{"keys":[{"kty":"oct","kid":"a","k":"c2VjcmV0IGtleSBhIGZvciB0aGUgSFMyNTYgdGVzdHM"},{"kty":"oct","kid":"broken"}]}"#;
+    let mut read = JwkSet::from_async_reader(json.as_bytes());
+    match Pin::new(&mut read).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(Ok((set, skipped))) => {
+            assert!(set.get("a").is_some());
+            assert_eq!(skipped.len(), 1);
+        },
+        Poll::Ready(Err(err)) => panic!("expected a key set, got {:?}", err),
+        Poll::Pending => panic!("a slice is always ready")
+    }
+}
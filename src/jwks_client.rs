@@ -0,0 +1,553 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::Read;
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use arc_swap::ArcSwapOption;
+use native_tls::TlsConnector;
+use ureq::{self, AgentBuilder};
+use claims::Claims;
+use clock::{Clock, SystemClock};
+use jwk::Jwk;
+use jwk_set::JwkSet;
+use jws::{JWS, ParsedToken};
+use jws_header::{Header, ALGORITHM};
+use issuer_registry::IssuerRegistry;
+use limits::Limits;
+use validation::allowed_algorithm;
+use error::{Error, Result};
+#[cfg(test)]
+use testing::StepClock;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct JwksClient {
+    url: String,
+    // Applies to connecting and to each read or write, not to the whole fetch.
+    pub timeout: Duration,
+    pub limits: Limits,
+}
+
+fn fetch_failed<E: Display>(err: E) -> Error {
+    Error::FetchFailed(err.to_string())
+}
+
+// Only https URLs are accepted, so keys are never fetched in the clear.
+fn check_https(url: &str) -> Result<()> {
+    match url.find("://") {
+        Some(index) if url[..index].eq_ignore_ascii_case("https") => Ok(()),
+        _ => Err(Error::InsecureUrl(url.to_owned()))
+    }
+}
+
+// The server certificate and host name are verified against the system trust
+// store, and redirects are only followed to other https URLs. `max_size` bounds
+// the body once any gzip encoding has been removed.
+pub fn get(url: &str, timeout: Duration, max_size: usize) -> Result<Vec<u8>> {
+    try!(check_https(url));
+    let tls = try!(TlsConnector::new().map_err(fetch_failed));
+    let agent = AgentBuilder::new()
+        .tls_connector(Arc::new(tls))
+        .https_only(true)
+        .try_proxy_from_env(true)
+        .timeout_connect(timeout)
+        .timeout_read(timeout)
+        .timeout_write(timeout)
+        .build();
+    let response = match agent.get(url).set("Accept", "application/json").call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, _)) => return Err(fetch_failed(format!("the server answered with status {}", status))),
+        Err(err) => return Err(fetch_failed(err))
+    };
+    let mut body = Vec::new();
+    try!(response.into_reader().take(max_size as u64 + 1).read_to_end(&mut body).map_err(fetch_failed));
+    if body.len() > max_size {
+        return Err(Error::PayloadTooLarge { size: body.len(), limit: max_size });
+    }
+    Ok(body)
+}
+
+impl JwksClient {
+    pub fn new(url: &str) -> Result<JwksClient> {
+        try!(check_https(url));
+        Ok(JwksClient {
+            url: url.to_owned(),
+            timeout: Duration::from_secs(10),
+            limits: Limits::new(),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn fetch(&self) -> Result<JwkSet> {
+        let body = try!(get(&self.url, self.timeout, self.limits.max_decompressed_size));
+        JwkSet::from_json_with_limits(try!(str::from_utf8(&body)), &self.limits)
+    }
+}
+
+pub trait JwksSource {
+    fn fetch(&self) -> Result<JwkSet>;
+}
+
+impl JwksSource for JwksClient {
+    fn fetch(&self) -> Result<JwkSet> {
+        JwksClient::fetch(self)
+    }
+}
+
+#[derive(Debug)]
+struct CachedSet {
+    set: Arc<JwkSet>,
+    fetched_at: u64,
+    generation: u64,
+    // When a refresh of this set last failed.
+    failed_at: Option<u64>,
+}
+
+// What CachedJwks does once its keys are past the ttl and the source cannot be reached.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RefreshFailure {
+    // The fetch error is returned, so nothing verifies against expired keys.
+    FailClosed,
+    // The last key set keeps verifying for up to max_staleness seconds past the ttl.
+    ServeStale { max_staleness: u64 },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KeyFreshness {
+    Fresh,
+    // Served under RefreshFailure::ServeStale; age is the seconds since the set was fetched.
+    Stale { age: u64 },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct JwksStats {
+    pub fetches: u64,
+    pub fetch_failures: u64,
+    pub served_stale: u64,
+    pub failed_closed: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    fetches: AtomicU64,
+    fetch_failures: AtomicU64,
+    served_stale: AtomicU64,
+    failed_closed: AtomicU64,
+}
+
+fn bump(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+#[derive(Debug)]
+pub struct CachedJwks<S = JwksClient, C = SystemClock> {
+    source: S,
+    clock: C,
+    pub ttl: u64,
+    // An unknown kid triggers at most one refresh per cooldown, so random kids cannot
+    // hammer the provider. While serving stale keys, a failed source is retried as often.
+    pub refresh_cooldown: u64,
+    pub on_refresh_failure: RefreshFailure,
+    // Readers load the current set without locking; a refresh publishes a new
+    // immutable CachedSet in its place.
+    cached: ArcSwapOption<CachedSet>,
+    refreshing: Mutex<()>,
+    counters: Counters,
+}
+
+impl CachedJwks {
+    pub fn new(client: JwksClient) -> CachedJwks {
+        CachedJwks::with_sources(client, SystemClock)
+    }
+}
+
+impl<S: JwksSource, C: Clock> CachedJwks<S, C> {
+    pub fn with_sources(source: S, clock: C) -> CachedJwks<S, C> {
+        CachedJwks {
+            source: source,
+            clock: clock,
+            ttl: 3600,
+            refresh_cooldown: 30,
+            on_refresh_failure: RefreshFailure::FailClosed,
+            cached: ArcSwapOption::empty(),
+            refreshing: Mutex::new(()),
+            counters: Counters::default(),
+        }
+    }
+
+    pub fn stats(&self) -> JwksStats {
+        JwksStats {
+            fetches: self.counters.fetches.load(Ordering::Relaxed),
+            fetch_failures: self.counters.fetch_failures.load(Ordering::Relaxed),
+            served_stale: self.counters.served_stale.load(Ordering::Relaxed),
+            failed_closed: self.counters.failed_closed.load(Ordering::Relaxed),
+        }
+    }
+
+    fn cached(&self) -> Option<Arc<CachedSet>> {
+        self.cached.load_full()
+    }
+
+    // The key set last fetched, whatever its age. Never fetches and never
+    // blocks, even while a refresh is under way.
+    pub fn snapshot(&self) -> Option<Arc<JwkSet>> {
+        self.cached().map(|cached| cached.set.clone())
+    }
+
+    // Callers that find a refresh already under way wait for it and share its result.
+    fn refresh(&self, seen_generation: u64) -> Result<Arc<JwkSet>> {
+        let _refreshing = self.refreshing.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(cached) = self.cached() {
+            if cached.generation != seen_generation {
+                return Ok(cached.set.clone());
+            }
+        }
+        bump(&self.counters.fetches);
+        let set = match self.source.fetch() {
+            Ok(set) => Arc::new(set),
+            Err(err) => {
+                bump(&self.counters.fetch_failures);
+                // Only the holder of `refreshing` replaces the cached set, so nothing is lost here.
+                if let Some(cached) = self.cached() {
+                    self.cached.store(Some(Arc::new(CachedSet {
+                        set: cached.set.clone(),
+                        fetched_at: cached.fetched_at,
+                        generation: cached.generation,
+                        failed_at: Some(self.clock.now()),
+                    })));
+                }
+                return Err(err);
+            }
+        };
+        self.cached.store(Some(Arc::new(CachedSet {
+            set: set.clone(),
+            fetched_at: self.clock.now(),
+            generation: seen_generation + 1,
+            failed_at: None,
+        })));
+        Ok(set)
+    }
+
+    pub fn key_set(&self) -> Result<Arc<JwkSet>> {
+        self.key_set_with_freshness().map(|(set, _)| set)
+    }
+
+    pub fn key_set_with_freshness(&self) -> Result<(Arc<JwkSet>, KeyFreshness)> {
+        let cached = match self.cached() {
+            Some(cached) => cached,
+            None => return self.refresh(0).map(|set| (set, KeyFreshness::Fresh))
+        };
+        let now = self.clock.now();
+        let expires = cached.fetched_at.saturating_add(self.ttl);
+        if now < expires {
+            return Ok((cached.set.clone(), KeyFreshness::Fresh));
+        }
+        let serve_stale = match self.on_refresh_failure {
+            RefreshFailure::ServeStale { max_staleness } => now < expires.saturating_add(max_staleness),
+            RefreshFailure::FailClosed => false
+        };
+        let stale = KeyFreshness::Stale { age: now - cached.fetched_at };
+        let retry_at = cached.failed_at.map_or(0, |failed_at| failed_at.saturating_add(self.refresh_cooldown));
+        if serve_stale && now < retry_at {
+            bump(&self.counters.served_stale);
+            return Ok((cached.set.clone(), stale));
+        }
+        match self.refresh(cached.generation) {
+            Ok(set) => Ok((set, KeyFreshness::Fresh)),
+            Err(_) if serve_stale => {
+                bump(&self.counters.served_stale);
+                Ok((cached.set.clone(), stale))
+            },
+            Err(err) => {
+                bump(&self.counters.failed_closed);
+                Err(err)
+            }
+        }
+    }
+
+    pub fn find(&self, kid: Option<&str>, alg: &ALGORITHM) -> Result<Jwk> {
+        self.find_with_freshness(kid, alg).map(|(jwk, _)| jwk)
+    }
+
+    pub fn find_with_freshness(&self, kid: Option<&str>, alg: &ALGORITHM) -> Result<(Jwk, KeyFreshness)> {
+        let (set, freshness) = try!(self.key_set_with_freshness());
+        if let Some(jwk) = set.find(kid, alg) {
+            return Ok((jwk.clone(), freshness));
+        }
+        if let (Some(_), Some(cached)) = (kid, self.cached()) {
+            if self.clock.now() >= cached.fetched_at.saturating_add(self.refresh_cooldown) {
+                if let Some(jwk) = try!(self.refresh(cached.generation)).find(kid, alg) {
+                    return Ok((jwk.clone(), KeyFreshness::Fresh));
+                }
+            }
+        }
+        Err(Error::UnknownKey { kid: kid.map(|kid| kid.to_owned()) })
+    }
+
+    // The algorithm comes from the caller, never from the token.
+    pub fn decode_jwt(&self, token: String, algorithm: ALGORITHM) -> Result<(Header, Claims)> {
+        self.decode_jwt_with_freshness(token, algorithm).map(|(header, claims, _)| (header, claims))
+    }
+
+    // Also says whether the key came from a stale set.
+    pub fn decode_jwt_with_freshness(&self, token: String, algorithm: ALGORITHM) -> Result<(Header, Claims, KeyFreshness)> {
+        let (key, freshness) = {
+            let parsed = try!(ParsedToken::parse(&token));
+            let kid = parsed.header().kid.as_ref().map(|kid| kid.as_str());
+            let (jwk, freshness) = try!(self.find_with_freshness(kid, &algorithm));
+            (try!(jwk.to_key()), freshness)
+        };
+        let (header, claims) = try!(JWS::decode_jwt(token, &key, algorithm));
+        Ok((header, claims, freshness))
+    }
+}
+
+// An IssuerRegistry whose issuers are verified against the keys at their jwks_uri.
+#[derive(Debug)]
+pub struct JwksRegistry<S = JwksClient, C = SystemClock> {
+    registry: IssuerRegistry,
+    keys: HashMap<String, CachedJwks<S, C>>,
+}
+
+impl JwksRegistry {
+    pub fn prefetch(registry: IssuerRegistry) -> Result<JwksRegistry> {
+        let mut keys = HashMap::new();
+        for (issuer, policy) in registry.issuers() {
+            let uri = try!(policy.jwks_uri.as_ref().ok_or_else(|| Error::InvalidConfig(format!("{} has no jwks_uri", issuer))));
+            keys.insert(issuer.clone(), CachedJwks::new(try!(JwksClient::new(uri))));
+        }
+        JwksRegistry::with_keys(registry, keys)
+    }
+}
+
+impl<S: JwksSource, C: Clock> JwksRegistry<S, C> {
+    // Every key set is fetched up front, so a bad jwks_uri fails at startup rather than on the first token.
+    pub fn with_keys(registry: IssuerRegistry, keys: HashMap<String, CachedJwks<S, C>>) -> Result<JwksRegistry<S, C>> {
+        for (issuer, _) in registry.issuers() {
+            match keys.get(issuer) {
+                Some(cached) => { try!(cached.key_set()); },
+                None => return Err(Error::InvalidConfig(format!("{} has no key set", issuer)))
+            }
+        }
+        Ok(JwksRegistry {
+            registry: registry,
+            keys: keys,
+        })
+    }
+
+    pub fn registry(&self) -> &IssuerRegistry {
+        &self.registry
+    }
+
+    pub fn keys(&self, issuer: &str) -> Option<&CachedJwks<S, C>> {
+        self.keys.get(issuer)
+    }
+
+    pub fn decode(&self, token: &str) -> Result<(Header, Claims)> {
+        self.decode_at(token, SystemClock.now())
+    }
+
+    // Issuers share no keys, and the header's alg must be on the issuer's own allow-list.
+    pub fn decode_at(&self, token: &str, now: u64) -> Result<(Header, Claims)> {
+        let options = try!(self.registry.options_for_token(token));
+        let keys = match options.issuer.as_ref().and_then(|issuer| self.keys.get(issuer)) {
+            Some(keys) => keys,
+            None => return Err(Error::UnknownIssuer(options.issuer.clone()))
+        };
+        let (key, algorithm) = {
+            let parsed = try!(ParsedToken::parse_with_validation(token, &options));
+            let algorithm = try!(allowed_algorithm(parsed.header(), &options.algorithms));
+            let kid = parsed.header().kid.as_ref().map(|kid| kid.as_str());
+            (try!(try!(keys.find(kid, &algorithm)).to_key()), algorithm)
+        };
+        options.decode_at(token, &key, algorithm, now)
+    }
+}
+
+#[cfg(test)]
+struct CountingSource {
+    fetches: ::std::sync::atomic::AtomicUsize,
+    keys: Vec<(&'static str, &'static [u8])>,
+    failing: ::std::sync::atomic::AtomicBool,
+}
+
+#[cfg(test)]
+impl CountingSource {
+    fn new(keys: Vec<(&'static str, &'static [u8])>) -> CountingSource {
+        CountingSource {
+            fetches: ::std::sync::atomic::AtomicUsize::new(0),
+            keys: keys,
+            failing: ::std::sync::atomic::AtomicBool::new(false),
+        }
+    }
+
+    fn fetches(&self) -> usize {
+        self.fetches.load(::std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+// Each fetch publishes one more of the keys, as a provider rotating keys would.
+#[cfg(test)]
+impl<'a> JwksSource for &'a CountingSource {
+    fn fetch(&self) -> Result<JwkSet> {
+        if self.failing.load(::std::sync::atomic::Ordering::SeqCst) {
+            return Err(fetch_failed("provider unreachable"));
+        }
+        let fetched = self.fetches.fetch_add(1, ::std::sync::atomic::Ordering::SeqCst) + 1;
+        ::std::thread::sleep(Duration::from_millis(20));
+        Ok(JwkSet::new(self.keys.iter().take(fetched).map(|&(kid, secret)| {
+            let mut jwk = Jwk::oct(secret);
+            jwk.kid = Some(kid.to_owned());
+            jwk
+        }).collect()))
+    }
+}
+
+#[test]
+fn cached_key_sets_expire_after_the_ttl() {
+    let source = CountingSource::new(vec![("a", b"secret-a"), ("b", b"secret-b")]);
+    let clock = StepClock(::std::sync::atomic::AtomicUsize::new(1000));
+    let mut cache = CachedJwks::with_sources(&source, &clock);
+    cache.ttl = 60;
+    assert_eq!(cache.key_set().unwrap().keys().len(), 1);
+    clock.0.store(1059, ::std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(cache.key_set().unwrap().keys().len(), 1);
+    assert_eq!(source.fetches(), 1);
+    clock.0.store(1060, ::std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(cache.key_set().unwrap().keys().len(), 2);
+    assert_eq!(source.fetches(), 2);
+}
+
+#[test]
+fn snapshots_are_read_while_a_refresh_is_under_way() {
+    let source = CountingSource::new(vec![("a", b"secret-a"), ("b", b"secret-b")]);
+    let clock = StepClock(::std::sync::atomic::AtomicUsize::new(1000));
+    let mut cache = CachedJwks::with_sources(&source, &clock);
+    cache.ttl = 60;
+    assert!(cache.snapshot().is_none());
+    let first = cache.key_set().unwrap();
+
+    clock.0.store(1060, ::std::sync::atomic::Ordering::SeqCst);
+    ::std::thread::scope(|scope| {
+        let refreshing = cache.refreshing.lock().unwrap();
+        let reader = scope.spawn(|| cache.snapshot().unwrap());
+        assert!(Arc::ptr_eq(&reader.join().unwrap(), &first));
+        drop(refreshing);
+    });
+    let rotated = cache.key_set().unwrap();
+    assert_eq!(rotated.keys().len(), 2);
+    assert!(Arc::ptr_eq(&cache.snapshot().unwrap(), &rotated));
+    assert_eq!(first.keys().len(), 1);
+}
+
+#[test]
+fn unknown_kids_refresh_once_within_the_cooldown() {
+    let source = CountingSource::new(vec![("a", b"secret-a"), ("b", b"secret-b")]);
+    let clock = StepClock(::std::sync::atomic::AtomicUsize::new(1000));
+    let cache = CachedJwks::with_sources(&source, &clock);
+    assert!(cache.find(Some("a"), &ALGORITHM::HS256).is_ok());
+    assert_err!(cache.find(Some("b"), &ALGORITHM::HS256), Error::UnknownKey { kid } => assert_eq!(kid, Some("b".to_owned())));
+    assert_eq!(source.fetches(), 1);
+
+    clock.0.store(1030, ::std::sync::atomic::Ordering::SeqCst);
+    ::std::thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| assert!(cache.find(Some("b"), &ALGORITHM::HS256).is_ok()));
+        }
+    });
+    assert_eq!(source.fetches(), 2);
+    assert!(cache.find(Some("missing"), &ALGORITHM::HS256).is_err());
+    assert_eq!(source.fetches(), 2);
+}
+
+#[test]
+fn stale_keys_are_served_only_within_the_max_staleness() {
+    let source = CountingSource::new(vec![("a", b"secret-a")]);
+    let clock = StepClock(::std::sync::atomic::AtomicUsize::new(1000));
+    let mut cache = CachedJwks::with_sources(&source, &clock);
+    cache.ttl = 60;
+    cache.on_refresh_failure = RefreshFailure::ServeStale { max_staleness: 300 };
+    assert_eq!(cache.find_with_freshness(Some("a"), &ALGORITHM::HS256).unwrap().1, KeyFreshness::Fresh);
+
+    source.failing.store(true, ::std::sync::atomic::Ordering::SeqCst);
+    clock.0.store(1100, ::std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(cache.find_with_freshness(Some("a"), &ALGORITHM::HS256).unwrap().1, KeyFreshness::Stale { age: 100 });
+    // Within the cooldown the failed source is not asked again.
+    clock.0.store(1110, ::std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(cache.key_set_with_freshness().unwrap().1, KeyFreshness::Stale { age: 110 });
+    assert_eq!(cache.stats(), JwksStats { fetches: 2, fetch_failures: 1, served_stale: 2, failed_closed: 0 });
+
+    clock.0.store(1360, ::std::sync::atomic::Ordering::SeqCst);
+    assert!(cache.key_set().is_err());
+    assert_eq!(cache.stats(), JwksStats { fetches: 3, fetch_failures: 2, served_stale: 2, failed_closed: 1 });
+
+    source.failing.store(false, ::std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(cache.key_set_with_freshness().unwrap().1, KeyFreshness::Fresh);
+}
+
+#[test]
+fn expired_keys_fail_closed_by_default() {
+    let source = CountingSource::new(vec![("a", b"secret-a")]);
+    let clock = StepClock(::std::sync::atomic::AtomicUsize::new(1000));
+    let mut cache = CachedJwks::with_sources(&source, &clock);
+    cache.ttl = 60;
+    assert!(cache.key_set().is_ok());
+    source.failing.store(true, ::std::sync::atomic::Ordering::SeqCst);
+    clock.0.store(1060, ::std::sync::atomic::Ordering::SeqCst);
+    assert_err!(cache.key_set(), Error::FetchFailed(_));
+    assert_eq!(cache.stats().failed_closed, 1);
+}
+
+#[test]
+fn configured_issuers_verify_against_their_own_key_sets() {
+    use validation::Validation;
+
+    let config = r#"{"issuers": [
+        {"issuer": "https://a", "jwks_uri": "https://a/jwks.json", "algorithms": ["HS256"]},
+        {"issuer": "https://b", "jwks_uri": "https://b/jwks.json", "algorithms": ["HS256"]}
+    ]}"#;
+    let registry = IssuerRegistry::from_config(config, Validation::new()).unwrap();
+    let a = CountingSource::new(vec![("a", b"a 32-byte secret for issuer a...")]);
+    let b = CountingSource::new(vec![("b", b"a 32-byte secret for issuer b...")]);
+    let clock = StepClock(::std::sync::atomic::AtomicUsize::new(1000));
+    let partial = CountingSource::new(vec![("a", b"a 32-byte secret for issuer a...")]);
+    let mut keys = HashMap::new();
+    keys.insert("https://a".to_owned(), CachedJwks::with_sources(&partial, &clock));
+    match JwksRegistry::with_keys(registry.clone(), keys).err() {
+        Some(Error::InvalidConfig(reason)) => assert!(reason.contains("https://b")),
+        other => panic!("expected InvalidConfig, got {:?}", other)
+    }
+
+    let mut keys = HashMap::new();
+    keys.insert("https://a".to_owned(), CachedJwks::with_sources(&a, &clock));
+    keys.insert("https://b".to_owned(), CachedJwks::with_sources(&b, &clock));
+    let verifier = JwksRegistry::with_keys(registry, keys).unwrap();
+    assert_eq!((a.fetches(), b.fetches()), (1, 1));
+
+    let token = |issuer: &str, kid: &str, secret: &[u8]| {
+        let mut header = Header::new();
+        header.kid = Some(kid.to_owned());
+        let mut claims = Claims::new();
+        claims.iss = Some(issuer.to_owned());
+        JWS::from_claims(header, claims).encode(secret, ALGORITHM::HS256).unwrap()
+    };
+    assert!(verifier.decode_at(&token("https://a", "a", b"a 32-byte secret for issuer a..."), 1000).is_ok());
+    // Issuer b cannot vouch for a token with a key from issuer a's set.
+    assert_err!(verifier.decode_at(&token("https://b", "a", b"a 32-byte secret for issuer a..."), 1000), Error::UnknownKey { kid } => assert_eq!(kid, Some("a".to_owned())));
+    assert_err!(verifier.decode_at(&token("https://c", "a", b"a 32-byte secret for issuer a..."), 1000), Error::UnknownIssuer(iss) => assert_eq!(iss, Some("https://c".to_owned())));
+}
+
+#[test]
+fn only_https_urls_are_accepted() {
+    for url in &["http://example.com/jwks.json", "example.com/jwks.json", "ftp://example.com/jwks.json"] {
+        assert_err!(JwksClient::new(url), Error::InsecureUrl(ref found) => assert_eq!(found, url));
+        assert!(get(url, Duration::from_secs(1), 1024).is_err());
+    }
+    assert!(JwksClient::new("HTTPS://login.example.com/.well-known/jwks.json").is_ok());
+}
@@ -4,14 +4,27 @@
 use jws_header::Header;
 use jws_header::ALGORITHM;
 use claims::Claims;
+use limits::{Base64Mode, Limits};
 use signing;
+use unsigned::UnsignedJws;
+use typ::Typ;
+use profile::Profile;
+use validation::{Validation, allowed_algorithm, check_before_signature, check_hmac_key};
+use simple::{self, DecodingKey, EncodingKey};
+use jwk_set::JwkSet;
+use clock::{Clock, SystemClock};
 
 
 use rustc_serialize::base64;
 use rustc_serialize::base64::FromBase64;
 use rustc_serialize::base64::ToBase64;
 
-use openssl::crypto::pkey::PKey;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{HasParams, PKeyRef};
+#[cfg(test)]
+use openssl::pkey::PKey;
+use serde::Deserialize;
 use serde_json;
 use error::{Error, Result};
 
@@ -29,92 +42,781 @@ fn base64_url_encode(value: String) -> String {
 }
 
 
-fn base64_url_encode_bytes(bytes: &[u8]) -> String {
+pub fn base64_url_encode_bytes(bytes: &[u8]) -> String {
     bytes.to_base64(BASE64_CONFIG)
 }
 
+// Names the segment that failed, which a bare base64 error cannot.
+fn decode_part(value: &str, part: &'static str) -> Result<Vec<u8>> {
+    value.from_base64().map_err(|_| Error::MalformedPart { part: part })
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum JWSBody {
     Custom {
         value: Vec<u8>,
         typ: Option<String>
     },
+    // Boxed, since the inline claim map makes Claims far larger than a custom body.
     JWT {
-        claims: Claims
+        claims: Box<Claims>
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PayloadKind {
+    JsonObject,
+    Raw
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TokenShape {
+    pub header_len: usize,
+    pub payload_len: usize,
+    pub signature_len: usize,
+    pub alg: ALGORITHM,
+    pub kid: Option<String>,
+    pub typ: Option<String>,
+    pub payload_kind: PayloadKind
+}
+
+pub fn is_well_formed(token: &str) -> Result<TokenShape> {
+    let parsed = try!(ParsedToken::parse(token));
+    let payload = try!(decode_part(&parsed.payload, "payload"));
+    try!(decode_part(&parsed.signature, "signature"));
+    let payload_kind = match serde_json::from_slice::<serde_json::Value>(payload.as_slice()) {
+        Ok(serde_json::Value::Object(_)) => PayloadKind::JsonObject,
+        _ => PayloadKind::Raw
+    };
+    Ok(TokenShape {
+        header_len: parsed.signing_input.len() - parsed.payload.len() - 1,
+        payload_len: parsed.payload.len(),
+        signature_len: parsed.signature.len(),
+        alg: parsed.header.alg.clone(),
+        kid: parsed.header.kid.clone(),
+        typ: parsed.header.typ.clone(),
+        payload_kind: payload_kind
+    })
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TokenFormat {
+    Compact,
+    FlattenedJson,
+    GeneralJson,
+    Unknown
+}
+
+// Only the shape is looked at; nothing is decoded or verified.
+pub fn detect_format(input: &str) -> TokenFormat {
+    let input = input.trim();
+    if input.starts_with('{') {
+        let object = match serde_json::from_str::<serde_json::Value>(input) {
+            Ok(serde_json::Value::Object(object)) => object,
+            _ => return TokenFormat::Unknown
+        };
+        let is_string = |name: &str| object.get(name).map_or(false, |value| value.as_str().is_some());
+        return match object.get("signatures") {
+            Some(&serde_json::Value::Array(_)) if is_string("payload") => TokenFormat::GeneralJson,
+            None if is_string("payload") && is_string("signature") => TokenFormat::FlattenedJson,
+            _ => TokenFormat::Unknown
+        };
+    }
+    let is_compact = input.split('.').count() == 3
+        && input.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.');
+    if is_compact { TokenFormat::Compact } else { TokenFormat::Unknown }
+}
+
+fn json_member<'a>(value: &'a serde_json::Value, name: &str) -> Result<&'a str> {
+    value.find(name).and_then(|member| member.as_str()).ok_or(Error::MalformedToken)
+}
+
+/// Verifies a token in any of the three JWS serializations. Unprotected
+/// header members of the JSON forms are ignored; only the protected header
+/// is trusted. A general JSON token verifies if any of its signatures does.
+/// Claims in the payload are checked as in `JWS::verify_jws`.
+pub fn decode_any(input: &str, secret: &[u8], algorithm: ALGORITHM) -> Result<VerifiedJws> {
+    let input = input.trim();
+    try!(Limits::new().check_token(input));
+    match detect_format(input) {
+        TokenFormat::Compact => JWS::verify_jws(input, secret, algorithm),
+        TokenFormat::FlattenedJson => {
+            let value: serde_json::Value = try!(serde_json::from_str(input));
+            JWS::verify_raw_parts(try!(json_member(&value, "protected")), try!(json_member(&value, "payload")),
+                                  try!(json_member(&value, "signature")), secret, algorithm)
+        },
+        TokenFormat::GeneralJson => {
+            let value: serde_json::Value = try!(serde_json::from_str(input));
+            let payload = try!(json_member(&value, "payload"));
+            let signatures = match value.find("signatures") {
+                Some(&serde_json::Value::Array(ref signatures)) => signatures,
+                _ => return Err(Error::MalformedToken)
+            };
+            let mut last_error = Error::JWSInvalidSignature;
+            for entry in signatures {
+                let verified = json_member(entry, "protected").and_then(|protected| {
+                    json_member(entry, "signature").and_then(|signature| {
+                        JWS::verify_raw_parts(protected, payload, signature, secret, algorithm.clone())
+                    })
+                });
+                match verified {
+                    Ok(verified) => return Ok(verified),
+                    Err(err) => last_error = err
+                }
+            }
+            Err(last_error)
+        },
+        TokenFormat::Unknown => Err(Error::MalformedToken)
+    }
+}
+
+// How the signatures of a general JSON token combine. Signatures are told
+// apart by the key that verified them, so a signature repeated under the same
+// key counts once and AtLeast(n) always means n distinct keys.
+#[derive(Debug, PartialEq, Clone)]
+pub enum SignaturePolicy {
+    AnyValid,
+    // Every signature must verify, and there must be at least one.
+    AllValid,
+    AtLeast(usize),
+    // The signature whose protected header names this kid must verify; the
+    // others are checked and reported but do not decide the outcome.
+    SpecificKid(String),
+}
+
+#[derive(Debug)]
+pub enum SignatureStatus {
+    Valid,
+    // Verified, but by the key of the earlier signature at `of`.
+    Duplicate { of: usize },
+    Invalid(Error),
+}
+
+#[derive(Debug)]
+pub struct SignatureCheck {
+    pub index: usize,
+    pub kid: Option<String>,
+    pub alg: Option<ALGORITHM>,
+    pub status: SignatureStatus,
+}
+
+impl SignatureCheck {
+    pub fn is_verified(&self) -> bool {
+        match self.status {
+            SignatureStatus::Invalid(_) => false,
+            _ => true
+        }
+    }
+}
+
+// The outcome of every signature of a general JSON token, whether or not the
+// policy was met.
+#[derive(Debug)]
+pub struct GeneralVerification {
+    signatures: Vec<SignatureCheck>,
+    verified: Option<VerifiedJws>,
+}
+
+impl GeneralVerification {
+    pub fn signatures(&self) -> &[SignatureCheck] {
+        &self.signatures
+    }
+
+    pub fn is_satisfied(&self) -> bool {
+        self.verified.is_some()
+    }
+
+    // Set only when the policy was met and the claims passed the Validation.
+    pub fn verified(&self) -> Option<&VerifiedJws> {
+        self.verified.as_ref()
+    }
+
+    pub fn into_verified(self) -> Result<VerifiedJws> {
+        match self.verified {
+            Some(verified) => Ok(verified),
+            None => Err(Error::SignaturePolicyNotMet {
+                valid: self.signatures.iter().filter(|check| check.is_verified()).count(),
+                total: self.signatures.len(),
+            })
+        }
+    }
+}
+
+/// Verifies each signature of a general JSON token against the key its
+/// protected header selects from `keys`, then applies `policy`. The header's
+/// alg must be on `validation.algorithms`. Signatures are always checked in
+/// order and all of them are reported. The returned header is that of the
+/// first signature counting towards the policy, or of the matching kid for
+/// `SpecificKid`. Claims are checked once, after the policy is met.
+pub fn verify_general(input: &str, keys: &JwkSet, policy: &SignaturePolicy, validation: &Validation) -> Result<GeneralVerification> {
+    verify_general_at(input, keys, policy, validation, SystemClock.now())
+}
+
+pub fn verify_general_at(input: &str, keys: &JwkSet, policy: &SignaturePolicy, validation: &Validation, now: u64) -> Result<GeneralVerification> {
+    let input = input.trim();
+    try!(validation.limits.check_token(input));
+    if detect_format(input) != TokenFormat::GeneralJson {
+        return Err(Error::MalformedToken);
+    }
+    let value: serde_json::Value = try!(serde_json::from_str(input));
+    let payload = try!(json_member(&value, "payload"));
+    let entries = match value.find("signatures") {
+        Some(&serde_json::Value::Array(ref entries)) => entries,
+        _ => return Err(Error::MalformedToken)
+    };
+
+    let mut signatures = Vec::new();
+    // The key, header and payload of each signature that verified, by index.
+    let mut outcomes: Vec<Option<(Vec<u8>, Header, Vec<u8>)>> = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let parsed = json_member(entry, "protected").and_then(|protected| {
+            json_member(entry, "signature").and_then(|signature| JWS::from_raw_parts(protected, payload, signature))
+        });
+        let (kid, alg) = match parsed {
+            Ok(ref parsed) => (parsed.header.kid.clone(), Some(parsed.header.alg.clone())),
+            Err(_) => (None, None)
+        };
+        let verified = parsed.and_then(|parsed| {
+            let algorithm = try!(allowed_algorithm(&parsed.header, &validation.algorithms));
+            let key = try!(keys.key_for(&parsed.header, &algorithm));
+            let payload = try!(parsed.validated_payload(&key, algorithm, validation));
+            Ok((key, parsed.header, payload))
+        });
+        let status = match verified {
+            Ok(outcome) => {
+                let earlier = outcomes.iter().position(|earlier| earlier.as_ref().map_or(false, |earlier| earlier.0 == outcome.0));
+                outcomes.push(Some(outcome));
+                match earlier {
+                    Some(of) => SignatureStatus::Duplicate { of: of },
+                    None => SignatureStatus::Valid
+                }
+            },
+            Err(err) => {
+                outcomes.push(None);
+                SignatureStatus::Invalid(err)
+            }
+        };
+        signatures.push(SignatureCheck { index: index, kid: kid, alg: alg, status: status });
+    }
+
+    let distinct = signatures.iter().filter(|check| match check.status {
+        SignatureStatus::Valid => true,
+        _ => false
+    }).count();
+    let first_verified = signatures.iter().position(|check| check.is_verified());
+    let chosen = match *policy {
+        SignaturePolicy::AnyValid => first_verified,
+        SignaturePolicy::AllValid if signatures.iter().all(|check| check.is_verified()) => first_verified,
+        SignaturePolicy::AllValid => None,
+        SignaturePolicy::AtLeast(required) if distinct >= ::std::cmp::max(required, 1) => first_verified,
+        SignaturePolicy::AtLeast(_) => None,
+        SignaturePolicy::SpecificKid(ref kid) => signatures.iter()
+            .position(|check| check.kid.as_ref() == Some(kid) && check.is_verified()),
+    };
+    let verified = match chosen.and_then(|index| outcomes[index].as_ref()) {
+        Some(&(_, ref header, ref payload)) => {
+            try!(validation.check(&try!(payload_claims(payload, &validation.limits)), now));
+            Some(VerifiedJws { header: header.clone(), payload: payload.clone() })
+        },
+        None => None
+    };
+    Ok(GeneralVerification { signatures: signatures, verified: verified })
+}
+
+// ES256K keeps its own pin; "ES256 with secp256k1" is only ever accepted on
+// verification, and only when the caller opts in.
+pub fn ecdsa_params<T: HasParams>(key: &PKeyRef<T>, algorithm: &ALGORITHM, allow_es256_secp256k1: bool) -> Result<(MessageDigest, usize)> {
+    let (expected, digest, coordinate_len) = match *algorithm {
+        ALGORITHM::ES256 => (Nid::X9_62_PRIME256V1, MessageDigest::sha256(), 32),
+        ALGORITHM::ES384 => (Nid::SECP384R1, MessageDigest::sha384(), 48),
+        ALGORITHM::ES512 => (Nid::SECP521R1, MessageDigest::sha512(), 66),
+        _ => (Nid::SECP256K1, MessageDigest::sha256(), 32)
+    };
+    match signing::curve(key) {
+        Some(found) if found == expected => Ok((digest, coordinate_len)),
+        Some(found) if found == Nid::SECP256K1 && *algorithm == ALGORITHM::ES256 && allow_es256_secp256k1 => Ok((digest, coordinate_len)),
+        Some(found) => Err(Error::CurveMismatch {
+            alg: algorithm.clone(),
+            expected: signing::curve_name(expected),
+            found: signing::curve_name(found),
+        }),
+        None => Err(Error::KeyAlgorithmMismatch { alg: algorithm.clone() })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParsedToken {
+    header: Header,
+    signing_input: String,
+    payload: String,
+    signature: String,
+}
+
+impl ParsedToken {
+    pub fn parse(value: &str) -> Result<ParsedToken> {
+        ParsedToken::parse_with_limits(value, &Limits::new())
+    }
+
+    pub fn parse_with_limits(value: &str, limits: &Limits) -> Result<ParsedToken> {
+        ParsedToken::parse_token(value, limits, &[])
+    }
+
+    // Also accepts the custom algorithm names the validation allows.
+    pub fn parse_with_validation(value: &str, validation: &Validation) -> Result<ParsedToken> {
+        ParsedToken::parse_token(value, &validation.limits, &validation.custom_algorithms)
+    }
+
+    fn parse_token(value: &str, limits: &Limits, custom_algorithms: &[String]) -> Result<ParsedToken> {
+        try!(limits.check_token(value));
+        let parts: Vec<&str> = value.split('.').collect();
+        if parts.len() != 3 {
+            return Err(Error::MalformedToken);
+        }
+        let header = try!(parse_header(parts[0], limits, custom_algorithms));
+        let signature = match limits.base64 {
+            Base64Mode::Strict => {
+                for (part, name) in parts[1..].iter().zip(&["payload", "signature"]) {
+                    if signing::base64url_decode_ct(part).is_none() {
+                        return Err(Error::MalformedPart { part: *name });
+                    }
+                }
+                parts[2].to_owned()
+            },
+            // HMAC verification only reads the canonical spelling.
+            Base64Mode::Lenient => base64_url_encode_bytes(&try!(decode_part(parts[2], "signature")))
+        };
+        Ok(ParsedToken {
+            header: header,
+            signing_input: format!("{}.{}", parts[0], parts[1]),
+            payload: parts[1].to_owned(),
+            signature: signature,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    // The original segments joined back together, byte for byte; a lenient
+    // parse re-spells the signature canonically.
+    pub fn to_compact(&self) -> String {
+        format!("{}.{}", self.signing_input, self.signature)
+    }
+
+    // The claims are checked with Validation::new(): exp and nbf when present.
+    pub fn verify(&self, secret: &[u8], algorithm: ALGORITHM) -> Result<JWS> {
+        self.verify_with_limits(secret, algorithm, &Limits::new())
+    }
+
+    pub fn verify_with_limits(&self, secret: &[u8], algorithm: ALGORITHM, limits: &Limits) -> Result<JWS> {
+        let mut validation = Validation::new();
+        validation.limits = limits.clone();
+        self.verify_with_validation(secret, algorithm, &validation)
+    }
+
+    // Nothing in the body is checked; it need not even be claims.
+    pub fn verify_raw(&self, secret: &[u8], algorithm: ALGORITHM) -> Result<JWS> {
+        let body = try!(self.verified_payload(secret, algorithm));
+        Ok(JWS::from_custom(self.header.clone(), body))
+    }
+
+    // Checks the algorithm, key and header against `validation`, then the claims.
+    pub fn verify_with_validation(&self, secret: &[u8], algorithm: ALGORITHM, validation: &Validation) -> Result<JWS> {
+        self.verify_with_validation_at(secret, algorithm, validation, SystemClock.now())
+    }
+
+    pub fn verify_with_validation_at(&self, secret: &[u8], algorithm: ALGORITHM, validation: &Validation, now: u64) -> Result<JWS> {
+        let body = try!(self.validated_payload(secret, algorithm, validation));
+        self.checked(body, validation, now)
+    }
+
+    pub fn verify_with_key(&self, key: &DecodingKey, validation: &Validation) -> Result<JWS> {
+        self.verify_with_key_at(key, validation, SystemClock.now())
+    }
+
+    pub fn verify_with_key_at(&self, key: &DecodingKey, validation: &Validation, now: u64) -> Result<JWS> {
+        try!(check_before_signature(validation, &self.header, key.as_bytes(), key.algorithm()));
+        let body = try!(self.verified_payload_with(key, validation));
+        self.checked(body, validation, now)
+    }
+
+    fn checked(&self, body: Vec<u8>, validation: &Validation, now: u64) -> Result<JWS> {
+        let claims = try!(decode_claims(body, &validation.limits));
+        try!(validation.check(&claims, now));
+        Ok(JWS::from_claims(self.header.clone(), claims))
+    }
+
+    // Only for routing, such as picking a policy by issuer; nothing here is trustworthy yet.
+    pub fn claims_unverified(&self, limits: &Limits) -> Result<Claims> {
+        decode_claims(try!(decode_part(&self.payload, "payload")), limits)
+    }
+
+    // The only way a VerifiedJws is built: signature first, then the claims.
+    fn validated(self, secret: &[u8], algorithm: ALGORITHM, validation: &Validation, now: u64) -> Result<VerifiedJws> {
+        let payload = try!(self.validated_payload(secret, algorithm, validation));
+        try!(validation.check(&try!(payload_claims(&payload, &validation.limits)), now));
+        Ok(VerifiedJws { header: self.header, payload: payload })
+    }
+
+    fn verified_payload(&self, secret: &[u8], algorithm: ALGORITHM) -> Result<Vec<u8>> {
+        try!(self.check_signed(&algorithm));
+        self.verified_payload_with(&try!(simple::decoding_key(secret, algorithm)), &Validation::new())
+    }
+
+    fn validated_payload(&self, secret: &[u8], algorithm: ALGORITHM, validation: &Validation) -> Result<Vec<u8>> {
+        try!(check_before_signature(validation, &self.header, secret, &algorithm));
+        try!(self.check_signed(&algorithm));
+        self.verified_payload_with(&try!(simple::decoding_key(secret, algorithm)), validation)
+    }
+
+    // Checked before the key is loaded, so these errors do not depend on the key.
+    fn check_signed(&self, algorithm: &ALGORITHM) -> Result<()> {
+        if self.signature.is_empty() {
+            return Err(Error::MissingSignature);
+        }
+        if self.header.alg != *algorithm {
+            return Err(Error::WrongAlgorithm { expected: algorithm.clone(), found: self.header.alg.clone() });
+        }
+        Ok(())
+    }
+
+    fn verified_payload_with(&self, key: &DecodingKey, validation: &Validation) -> Result<Vec<u8>> {
+        try!(self.check_signed(key.algorithm()));
+        if !try!(JWS::verify_signature(self.signing_input.as_str(), self.signature.as_str(), key, validation)) {
+            return Err(Error::JWSInvalidSignature);
+        }
+        decode_part(&self.payload, "payload")
+    }
+}
+
+fn parse_header(part: &str, limits: &Limits, custom_algorithms: &[String]) -> Result<Header> {
+    let header = match limits.base64 {
+        Base64Mode::Strict => try!(signing::base64url_decode_ct(part).ok_or(Error::MalformedPart { part: "header" })),
+        Base64Mode::Lenient => try!(decode_part(part, "header"))
+    };
+    try!(limits.check_json(header.as_slice()));
+    let header = try!(str::from_utf8(header.as_slice()).map_err(|err| Error::InvalidHeader(err.to_string())));
+    let value: serde_json::Value = try!(serde_json::from_str(&header).map_err(|err| Error::InvalidHeader(err.to_string())));
+    if !value.is_object() {
+        return Err(Error::InvalidHeader("the header is not a JSON object".to_owned()));
+    }
+    let custom = match value.find("alg").and_then(|alg| alg.as_str()) {
+        Some(alg) if ALGORITHM::from_name(alg).is_none() => {
+            if !custom_algorithms.iter().any(|name| name == alg) {
+                return Err(Error::UnsupportedAlgorithm(alg.to_owned()));
+            }
+            Some(alg.to_owned())
+        },
+        _ => None
+    };
+    let name = match custom {
+        Some(name) => name,
+        None => return serde_json::from_str(&header).map_err(|err| Error::InvalidHeader(err.to_string()))
+    };
+    // Header only deserializes registered names, so an allowed custom one is
+    // read in place of "none" and put back afterwards.
+    let mut value = value;
+    if let serde_json::Value::Object(ref mut object) = value {
+        object.insert("alg".to_owned(), serde_json::Value::String("none".to_owned()));
+    }
+    let mut header: Header = try!(serde_json::from_value(value).map_err(|err| Error::InvalidHeader(err.to_string())));
+    header.alg = ALGORITHM::Custom(name);
+    Ok(header)
+}
+
+fn decode_claims(body: Vec<u8>, limits: &Limits) -> Result<Claims> {
+    try!(limits.check_json(body.as_slice()));
+    let claims: Option<Claims> = str::from_utf8(body.as_slice()).ok()
+        .and_then(|body| serde_json::from_str(body).ok());
+    match claims {
+        Some(claims) => Ok(claims),
+        None => Err(Error::NonJsonPayload { bytes: body })
+    }
+}
+
+// Only a JSON object payload can carry claims; any other payload is checked as
+// an empty claim set, so required claims and audience still fail for it.
+fn payload_claims(payload: &[u8], limits: &Limits) -> Result<Claims> {
+    if payload.first() != Some(&b'{') {
+        return Ok(Claims::new());
+    }
+    match decode_claims(payload.to_vec(), limits) {
+        Err(Error::NonJsonPayload { .. }) => Ok(Claims::new()),
+        result => result
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct VerifiedJwt<T = Claims> {
+    header: Header,
+    claims: T,
+}
+
+impl<T> VerifiedJwt<T> {
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn claims(&self) -> &T {
+        &self.claims
+    }
+
+    pub fn into_parts(self) -> (Header, T) {
+        (self.header, self.claims)
     }
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct VerifiedJws {
+    header: Header,
+    payload: Vec<u8>,
+}
+
+impl VerifiedJws {
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        self.payload.as_slice()
+    }
+
+    pub fn into_parts(self) -> (Header, Vec<u8>) {
+        (self.header, self.payload)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum JwtTyp {
+    Default,
+    Custom(String),
+    Omitted
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct JWS {
     header: Header,
-    body: JWSBody
+    body: JWSBody,
+    jwt_typ: JwtTyp
 }
 
 impl JWS {
-    fn from_claims(header: Header, claims: Claims) -> JWS {
+    /// Builds a JWT whose payload is the JSON serialization of `claims`.
+    pub fn from_claims(header: Header, claims: Claims) -> JWS {
         JWS {
             header: header,
-            body: JWSBody::JWT { claims: claims }
+            body: JWSBody::JWT { claims: Box::new(claims) },
+            jwt_typ: JwtTyp::Default
         }
     }
 
-    fn from_custom(header: Header, value: Vec<u8>) -> JWS {
+    pub fn from_profile(profile: Profile, mut header: Header, mut claims: Claims) -> Result<JWS> {
+        try!(profile.apply(&mut header, &mut claims));
+        Ok(JWS::from_claims(header, claims))
+    }
+
+    /// Builds a JWS over an arbitrary payload.
+    pub fn from_custom(header: Header, value: Vec<u8>) -> JWS {
         let typ = header.typ.clone();
         JWS {
             header: header,
-            body: JWSBody::Custom { value: value, typ: typ }
+            body: JWSBody::Custom { value: value, typ: typ },
+            jwt_typ: JwtTyp::Default
         }
     }
 
-    fn decode(value: String, secret: &[u8], algorithm: ALGORITHM, decode_claims: bool) -> Result<JWS> {
-        let parts: Vec<&str> = value.split('.').collect();
-        let header = try!(parts[0].from_base64());
-        let header = try!(str::from_utf8(header.as_slice()));
-        let payload: String = format!("{}.{}", parts[0], parts[1]);
-        let signature = parts[2];
+    /// Parses only the protected header of a compact token, so `kid` and `alg`
+    /// can pick the verification key. Nothing is verified; use
+    /// `ParsedToken::claims_unverified` to route on `iss`.
+    pub fn decode_header(value: &str) -> Result<Header> {
+        JWS::decode_header_with_limits(value, &Limits::new())
+    }
 
-        let header: Header = try!(serde_json::from_str(&header));
+    pub fn decode_header_with_limits(value: &str, limits: &Limits) -> Result<Header> {
+        JWS::header_of(value, limits, &[])
+    }
 
-        if header.alg != algorithm || !try!(JWS::verify_signature(payload.as_str(), signature, secret, algorithm)) {
-            return Err(Error::JWSInvalidSignature);
+    /// Like `decode_header_with_limits`, also accepting the validation's custom algorithms.
+    pub fn decode_header_with_validation(value: &str, validation: &Validation) -> Result<Header> {
+        JWS::header_of(value, &validation.limits, &validation.custom_algorithms)
+    }
+
+    fn header_of(value: &str, limits: &Limits, custom_algorithms: &[String]) -> Result<Header> {
+        try!(limits.check_token(value));
+        let mut parts = value.split('.');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(_), Some(_), None) => parse_header(header, limits, custom_algorithms),
+            _ => Err(Error::MalformedToken)
         }
+    }
+
+    /// Verifies a compact JWT and returns its header and claims. `exp` and `nbf`
+    /// are checked against the system clock with the default `Validation`.
+    ///
+    /// For asymmetric algorithms `secret` may be the issuer's SPKI public key or
+    /// X.509 certificate, PEM or DER.
+    pub fn decode_jwt(value: String, secret: &[u8], algorithm: ALGORITHM) -> Result<(Header, Claims)> {
+        JWS::decode_jwt_with_validation(value, secret, algorithm, &Validation::new())
+    }
+
+    /// Verifies a compact JWT and applies the given claim checks and size limits.
+    pub fn decode_jwt_with_validation(value: String, secret: &[u8], algorithm: ALGORITHM, validation: &Validation) -> Result<(Header, Claims)> {
+        JWS::decode_jwt_with_clock(value, secret, algorithm, validation, &SystemClock)
+    }
 
-        let body = try!(parts[1].from_base64());
-        if decode_claims {
-            let body = try!(str::from_utf8(body.as_slice()));
-            let claims: Claims = try!(serde_json::from_str(&body));
-            Ok(JWS::from_claims(header, claims))
-        } else {
-            Ok(JWS::from_custom(header, body))
+    /// Verifies a compact JWT signed with any of `algorithms`, so a verifier can
+    /// accept, say, RS256 and PS256 during a migration.
+    pub fn decode_jwt_with_algorithms(value: String, secret: &[u8], algorithms: &[ALGORITHM], validation: &Validation) -> Result<(Header, Claims)> {
+        let algorithm = try!(allowed_algorithm(try!(ParsedToken::parse(value.as_str())).header(), algorithms));
+        JWS::decode_jwt_with_validation(value, secret, algorithm, validation)
+    }
+
+    /// Verifies a compact JWT with a key bound to its algorithm, such as one built
+    /// with `DecodingKey::from_key`.
+    pub fn decode_jwt_with_key(value: String, key: &DecodingKey, validation: &Validation) -> Result<(Header, Claims)> {
+        validation.decode_with_key_at(&value, key, SystemClock.now())
+    }
+
+    /// Like `decode_jwt_with_validation`, reading the time from `clock`.
+    pub fn decode_jwt_with_clock<C: Clock>(value: String, secret: &[u8], algorithm: ALGORITHM, validation: &Validation, clock: &C) -> Result<(Header, Claims)> {
+        JWS::decode_jwt_at(value, secret, algorithm, validation, clock.now())
+    }
+
+    /// Like `decode_jwt_with_validation`, with the current time passed in.
+    pub fn decode_jwt_at(value: String, secret: &[u8], algorithm: ALGORITHM, validation: &Validation, now: u64) -> Result<(Header, Claims)> {
+        validation.decode_at(&value, secret, algorithm, now)
+    }
+
+    /// Like `decode_jwt_with_validation` with `Validation::new()` enforcing `limits`.
+    pub fn decode_jwt_with_limits(value: String, secret: &[u8], algorithm: ALGORITHM, limits: &Limits) -> Result<(Header, Claims)> {
+        let mut validation = Validation::new();
+        validation.limits = limits.clone();
+        validation.decode(&value, secret, algorithm)
+    }
+
+    /// Verifies a compact JWS and returns its header and raw payload bytes.
+    pub fn decode_raw(value: String, secret: &[u8], algorithm: ALGORITHM) -> Result<(Header, Vec<u8>)> {
+        let parsed = try!(ParsedToken::parse(value.as_str()));
+        let body = try!(parsed.verified_payload(secret, algorithm));
+        Ok((parsed.header, body))
+    }
+
+    /// Assembles a token from its three base64url segments without re-serializing
+    /// the protected header, so its exact bytes are kept.
+    pub fn from_raw_parts(protected: &str, payload: &str, signature: &str) -> Result<ParsedToken> {
+        let is_base64url = |part: &str| part.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !is_base64url(protected) || !is_base64url(payload) || !is_base64url(signature) {
+            return Err(Error::MalformedToken);
         }
+        let parsed = try!(ParsedToken::parse(&format!("{}.{}.{}", protected, payload, signature)));
+        try!(decode_part(payload, "payload"));
+        try!(decode_part(signature, "signature"));
+        Ok(parsed)
     }
 
-    fn decode_jwt(value: String, secret: &[u8], algorithm: ALGORITHM) -> Result<JWS> {
-        JWS::decode(value, secret, algorithm, true)
+    /// Verifies a token given as raw segments and returns its header and payload,
+    /// checked like `verify_jws`.
+    pub fn verify_raw_parts(protected: &str, payload: &str, signature: &str, secret: &[u8], algorithm: ALGORITHM) -> Result<VerifiedJws> {
+        let parsed = try!(JWS::from_raw_parts(protected, payload, signature));
+        parsed.validated(secret, algorithm, &Validation::new(), SystemClock.now())
     }
 
-    fn verify_signature(payload: &str, signature: &str, mut secret: &[u8], algorithm: ALGORITHM) -> Result<bool> {
-        let sig_matches = match algorithm {
-            ALGORITHM::RS256 => {
-                let key = try!(PKey::private_key_from_pem(&mut secret));
-                signing::verify_pk256(key, signature.as_bytes(), payload.as_bytes())
-            },
-            ALGORITHM::RS384 => {
-                let key = try!(PKey::private_key_from_pem(&mut secret));
-                signing::verify_pk384(key, signature.as_bytes(), payload.as_bytes())
+    /// Verifies a compact JWT and checks its claims with the default `Validation`
+    /// against the system clock.
+    pub fn verify_jwt<T: Deserialize>(value: &str, secret: &[u8], algorithm: ALGORITHM) -> Result<VerifiedJwt<T>> {
+        JWS::verify_jwt_with_validation(value, secret, algorithm, &Validation::new())
+    }
+
+    pub fn verify_jwt_with_validation<T: Deserialize>(value: &str, secret: &[u8], algorithm: ALGORITHM, validation: &Validation) -> Result<VerifiedJwt<T>> {
+        JWS::verify_jwt_at(value, secret, algorithm, validation, SystemClock.now())
+    }
+
+    /// Like `verify_jwt_with_validation`, with the current time passed in. The
+    /// registered claims are validated before the body is read as `T`.
+    pub fn verify_jwt_at<T: Deserialize>(value: &str, secret: &[u8], algorithm: ALGORITHM, validation: &Validation, now: u64) -> Result<VerifiedJwt<T>> {
+        let parsed = try!(ParsedToken::parse_with_validation(value, validation));
+        let body = try!(parsed.validated_payload(secret, algorithm, validation));
+        try!(validation.check(&try!(decode_claims(body.clone(), &validation.limits)), now));
+        let claims = match str::from_utf8(body.as_slice()) {
+            Ok(body) => try!(serde_json::from_str(body)),
+            Err(_) => return Err(Error::NonJsonPayload { bytes: body })
+        };
+        Ok(VerifiedJwt { header: parsed.header, claims: claims })
+    }
+
+    /// Verifies a compact JWS. A JSON object payload is checked with the default
+    /// `Validation` against the system clock; any other payload carries no claims.
+    pub fn verify_jws(value: &str, secret: &[u8], algorithm: ALGORITHM) -> Result<VerifiedJws> {
+        JWS::verify_jws_with_validation(value, secret, algorithm, &Validation::new())
+    }
+
+    pub fn verify_jws_with_validation(value: &str, secret: &[u8], algorithm: ALGORITHM, validation: &Validation) -> Result<VerifiedJws> {
+        JWS::verify_jws_at(value, secret, algorithm, validation, SystemClock.now())
+    }
+
+    pub fn verify_jws_at(value: &str, secret: &[u8], algorithm: ALGORITHM, validation: &Validation, now: u64) -> Result<VerifiedJws> {
+        try!(ParsedToken::parse_with_validation(value, validation)).validated(secret, algorithm, validation, now)
+    }
+
+    fn verify_signature(payload: &str, signature: &str, key: &DecodingKey, validation: &Validation) -> Result<bool> {
+        let secret = key.as_bytes();
+        let algorithm = key.algorithm().clone();
+        let allow_es256_secp256k1 = validation.allow_es256_secp256k1;
+        if !validation.allow_short_hmac_keys {
+            try!(check_hmac_key(secret, &algorithm));
+        }
+        let sig_matches = match (algorithm.clone(), key.public_key()) {
+            (ALGORITHM::ES256, Some(key)) | (ALGORITHM::ES384, Some(key)) |
+            (ALGORITHM::ES512, Some(key)) | (ALGORITHM::ES256K, Some(key)) => {
+                let (digest, coordinate_len) = try!(ecdsa_params(key, &algorithm, allow_es256_secp256k1));
+                let signature = try!(decode_part(signature, "signature"));
+                try!(signing::verify_ecdsa(digest, coordinate_len, key, signature.as_slice(), payload.as_bytes()))
             },
-            ALGORITHM::RS512 => {
-                let key = try!(PKey::private_key_from_pem(&mut secret));
-                signing::verify_pk512(key, signature.as_bytes(), payload.as_bytes())
+            (ALGORITHM::RS256, Some(key)) | (ALGORITHM::RS384, Some(key)) | (ALGORITHM::RS512, Some(key)) |
+            (ALGORITHM::PS256, Some(key)) | (ALGORITHM::PS384, Some(key)) | (ALGORITHM::PS512, Some(key)) |
+            (ALGORITHM::EdDSA, Some(key)) => {
+                let signature = try!(decode_part(signature, "signature"));
+                let verify = match algorithm {
+                    ALGORITHM::RS256 => signing::verify_pk256,
+                    ALGORITHM::RS384 => signing::verify_pk384,
+                    ALGORITHM::RS512 => signing::verify_pk512,
+                    ALGORITHM::PS256 => signing::verify_ps256,
+                    ALGORITHM::PS384 => signing::verify_ps384,
+                    ALGORITHM::PS512 => signing::verify_ps512,
+                    _ => signing::verify_eddsa
+                };
+                try!(verify(key, signature.as_slice(), payload.as_bytes()))
             },
-            ALGORITHM::HS256 => base64_url_encode_bytes(signing::hmac_256(&mut secret, payload.as_bytes()).as_slice()) == signature,
-            ALGORITHM::HS384 => base64_url_encode_bytes(signing::hmac_384(&mut secret, payload.as_bytes()).as_slice()) == signature,
-            ALGORITHM::HS512 => base64_url_encode_bytes(signing::hmac_512(&mut secret, payload.as_bytes()).as_slice()) == signature,
+            (ALGORITHM::HS256, _) => try!(signing::verify_hmac(MessageDigest::sha256(), secret, payload.as_bytes(), signature)),
+            (ALGORITHM::HS384, _) => try!(signing::verify_hmac(MessageDigest::sha384(), secret, payload.as_bytes(), signature)),
+            (ALGORITHM::HS512, _) => try!(signing::verify_hmac(MessageDigest::sha512(), secret, payload.as_bytes(), signature)),
             _ => false
         };
         Ok(sig_matches)
     }
 
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn claims(&self) -> Option<&Claims> {
+        match self.body {
+            JWSBody::JWT { ref claims } => Some(&**claims),
+            JWSBody::Custom { .. } => None
+        }
+    }
+
+    pub fn claims_mut(&mut self) -> Option<&mut Claims> {
+        match self.body {
+            JWSBody::JWT { ref mut claims } => Some(&mut **claims),
+            JWSBody::Custom { .. } => None
+        }
+    }
+
+    pub fn set_jwt_typ(&mut self, jwt_typ: JwtTyp) {
+        self.jwt_typ = jwt_typ;
+    }
+
     fn get_body_bytes(&self) -> Result<Vec<u8>> {
         match self.body {
             JWSBody::Custom { ref value, .. } => Ok(value.clone()),
@@ -125,10 +827,14 @@ impl JWS {
     fn get_body_typ(&self) -> Option<String> {
         match self.body {
             JWSBody::Custom { ref typ, .. } => typ.clone(),
-            JWSBody::JWT { .. } => Some("JWT".to_owned())
+            JWSBody::JWT { .. } => match self.jwt_typ {
+                JwtTyp::Default => self.header.typ.clone().or(Some(Typ::JWT.as_str().to_owned())),
+                JwtTyp::Custom(ref typ) => Some(typ.clone()),
+                JwtTyp::Omitted => None
+            }
         }
     }
-    fn serialize_payload(&self) -> Result<String> {
+    pub fn signing_input(&self) -> Result<String> {
         let mut final_header = self.header.clone();
         final_header.typ = self.get_body_typ();
         let header_json = try!(final_header.to_json());
@@ -136,25 +842,100 @@ impl JWS {
         Ok(format!("{}.{}", base64_url_encode(header_json), base64_url_encode_bytes(claims_json.as_slice())))
     }
 
-    fn encode(&self, mut secret: &[u8], alg: ALGORITHM) -> Result<String> {
-        let payload = try!(self.serialize_payload());
+    /// Produces an `alg: none` token with an empty signature. Only for test
+    /// harnesses and pipelines that never accept tokens from outside.
+    pub fn encode_unsecured(&self) -> Result<String> {
+        let mut unsecured = self.clone();
+        unsecured.header.alg = ALGORITHM::None;
+        Ok(format!("{}.", try!(unsecured.signing_input())))
+    }
+
+    /// Accepts only `alg: none` tokens with an empty signature. Nothing here is
+    /// authenticated; never use it on untrusted input.
+    pub fn decode_unsecured_dangerous(value: String) -> Result<(Header, Claims)> {
+        let limits = Limits::new();
+        let parsed = try!(ParsedToken::parse_with_limits(value.as_str(), &limits));
+        if parsed.header.alg != ALGORITHM::None || !parsed.signature.is_empty() {
+            return Err(Error::UnsupportedAlgorithm(parsed.header.alg.name().to_owned()));
+        }
+        let body = try!(parsed.payload.from_base64());
+        let claims = try!(decode_claims(body, &limits));
+        Ok((parsed.header, claims))
+    }
+
+    pub fn prepare(&self) -> Result<UnsignedJws> {
+        UnsignedJws::from_signing_input(try!(self.signing_input()))
+    }
+
+    pub fn attach_signature(&self, signature: &[u8]) -> Result<String> {
+        let payload = try!(self.signing_input());
+        Ok(format!("{}.{}", payload, base64_url_encode_bytes(signature)))
+    }
+
+    /// Loads a PKCS#1 or PKCS#8 private key, PEM or DER, decrypting it with
+    /// `passphrase` if it is encrypted. Returns unencrypted PKCS#8 DER that can
+    /// be passed to `encode`.
+    pub fn load_private_key(key: &[u8], passphrase: Option<&[u8]>) -> Result<Vec<u8>> {
+        let pkey = try!(signing::private_key_with_passphrase(key, passphrase).map_err(|err| Error::private_key_parse(key, err)));
+        Ok(try!(pkey.private_key_to_pkcs8()))
+    }
+
+    /// Extracts the subject public key of a PEM or DER X.509 certificate as SPKI
+    /// DER. The certificate itself is not validated.
+    pub fn public_key_from_certificate(cert: &[u8]) -> Result<Vec<u8>> {
+        let public = try!(signing::certificate_key(cert).map_err(|err| Error::public_key_parse(cert, err)));
+        Ok(try!(public.public_key_to_der()))
+    }
+
+    /// Signs the token with `secret` and returns the compact serialization.
+    ///
+    /// `secret` is the shared key for HS*, a PEM or DER private key for RS* and PS*,
+    /// a PEM or DER EC private key on the algorithm's curve for ES*, or a PEM or
+    /// raw (32-byte Ed25519, 57-byte Ed448) private key for EdDSA. A key of
+    /// another family, such as a PEM key used as an HMAC secret, is rejected.
+    pub fn encode(&self, secret: &[u8], alg: ALGORITHM) -> Result<String> {
+        self.encode_with_key(&try!(simple::encoding_key(secret, alg)))
+    }
+
+    /// Signs the token with a key bound to its algorithm.
+    pub fn encode_with_key(&self, key: &EncodingKey) -> Result<String> {
+        let secret = key.as_bytes();
+        let alg = key.algorithm().clone();
+        let min_rsa_bits = key.min_rsa_bits();
+        if !key.allows_short_hmac_key() {
+            try!(check_hmac_key(secret, &alg));
+        }
+        let payload = try!(self.signing_input());
         let signature = match alg {
-            ALGORITHM::RS256 => {
-                let key = try!(PKey::private_key_from_pem(&mut secret));
-                signing::sign_pk256(key, payload.as_bytes())
+            ALGORITHM::RS256 | ALGORITHM::RS384 | ALGORITHM::RS512 |
+            ALGORITHM::PS256 | ALGORITHM::PS384 | ALGORITHM::PS512 => {
+                let key = try!(signing::private_key(secret).map_err(|err| Error::private_key_parse(secret, err)));
+                if (key.bits() as usize) < min_rsa_bits {
+                    return Err(Error::WeakKey { alg: alg, bits: key.bits() as usize, required: min_rsa_bits });
+                }
+                let sign = match alg {
+                    ALGORITHM::RS256 => signing::sign_pk256,
+                    ALGORITHM::RS384 => signing::sign_pk384,
+                    ALGORITHM::RS512 => signing::sign_pk512,
+                    ALGORITHM::PS256 => signing::sign_ps256,
+                    ALGORITHM::PS384 => signing::sign_ps384,
+                    _ => signing::sign_ps512
+                };
+                try!(sign(&key, payload.as_bytes()))
             },
-            ALGORITHM::RS384 => {
-                let key = try!(PKey::private_key_from_pem(&mut secret));
-                signing::sign_pk384(key, payload.as_bytes())
+            ALGORITHM::ES256 | ALGORITHM::ES384 | ALGORITHM::ES512 | ALGORITHM::ES256K => {
+                let key = try!(signing::private_key(secret).map_err(|err| Error::private_key_parse(secret, err)));
+                let (digest, coordinate_len) = try!(ecdsa_params(&key, &alg, false));
+                try!(signing::sign_ecdsa(digest, coordinate_len, &key, payload.as_bytes()))
             },
-            ALGORITHM::RS512 => {
-                let key = try!(PKey::private_key_from_pem(&mut secret));
-                signing::sign_pk512(key, payload.as_bytes())
+            ALGORITHM::EdDSA => {
+                let key = try!(signing::eddsa_key(secret).map_err(|err| Error::private_key_parse(secret, err)));
+                try!(signing::sign_eddsa(&key, payload.as_bytes()))
             },
-            ALGORITHM::HS256 => signing::hmac_256(secret, payload.as_bytes()),
-            ALGORITHM::HS384 => signing::hmac_384(secret, payload.as_bytes()),
-            ALGORITHM::HS512 => signing::hmac_512(secret, payload.as_bytes()),
-            _ => signing::hmac_256(secret, payload.as_bytes())
+            ALGORITHM::HS256 => try!(signing::hmac_256(secret, payload.as_bytes())),
+            ALGORITHM::HS384 => try!(signing::hmac_384(secret, payload.as_bytes())),
+            ALGORITHM::HS512 => try!(signing::hmac_512(secret, payload.as_bytes())),
+            _ => return Err(Error::UnsupportedAlgorithm(alg.name().to_owned()))
         };
         let b64_sig = base64_url_encode_bytes(signature.as_slice());
         Ok(format!("{}.{}", payload, b64_sig))
@@ -178,9 +959,747 @@ fn test_serialize() {
     header.alg = ALGORITHM::HS256;
     let t = JWS::from_claims(header, claims);
 
-    let key = "secret";
-    let encoded = t.encode(key.as_bytes(), ALGORITHM::HS256).unwrap();
-    println!("{}", encoded);
-    let decoded = JWS::decode_jwt(encoded, key.as_bytes(), ALGORITHM::HS256).unwrap();
-    println!("{}", decoded.header.get::<String>("iss").unwrap());
+    let key = ::TEST_HS256_SECRET;
+    let encoded = t.encode(key, ALGORITHM::HS256).unwrap();
+    let (header, _) = JWS::decode_jwt_with_validation(encoded, key, ALGORITHM::HS256, &Validation::signature_only()).unwrap();
+    assert_eq!(header.get::<String>("iss"), Some("DISPOSITION".to_owned()));
+}
+
+#[test]
+fn externally_produced_signatures_can_be_attached() {
+    let mut claims = Claims::new();
+    claims.iss = Some("HSM".to_owned());
+    let t = JWS::from_claims(Header::new(), claims);
+    let key = ::TEST_HS256_SECRET;
+
+    let signing_input = t.signing_input().unwrap();
+    let signature = signing::hmac_256(key, signing_input.as_bytes()).unwrap();
+    let token = t.attach_signature(signature.as_slice()).unwrap();
+
+    assert_eq!(token, t.encode(key, ALGORITHM::HS256).unwrap());
+    assert!(JWS::decode_jwt(token, key, ALGORITHM::HS256).is_ok());
+}
+
+#[test]
+fn prepared_tokens_can_be_shipped_and_completed_later() {
+    let mut header = Header::new();
+    header.alg = ALGORITHM::HS384;
+    let t = JWS::from_claims(header, Claims::new());
+    let key = "a 48-byte secret, long enough for the HS384 test";
+
+    let shipped = serde_json::to_string(&t.prepare().unwrap()).unwrap();
+    let unsigned: UnsignedJws = serde_json::from_str(&shipped).unwrap();
+    assert_eq!(unsigned.alg(), &ALGORITHM::HS384);
+
+    let signature = signing::hmac_384(key.as_bytes(), unsigned.signing_input().as_bytes()).unwrap();
+    let token = unsigned.complete(signature.as_slice()).unwrap();
+    assert!(JWS::decode_jwt(token, key.as_bytes(), ALGORITHM::HS384).is_ok());
+
+    let wrong_length = signing::hmac_256(key.as_bytes(), unsigned.signing_input().as_bytes()).unwrap();
+    assert_err!(unsigned.complete(wrong_length.as_slice()), Error::SignatureLengthMismatch { found: 32 });
+}
+
+#[test]
+fn prepared_tokens_with_a_mismatched_alg_are_rejected() {
+    let t = JWS::from_claims(Header::new(), Claims::new());
+    let shipped = serde_json::to_string(&t.prepare().unwrap()).unwrap().replace("HS256", "HS512");
+    assert!(serde_json::from_str::<UnsignedJws>(&shipped).is_err());
+}
+
+#[test]
+fn tokens_can_be_parsed_before_the_signature_is_checked() {
+    let mut header = Header::new();
+    header.kid = Some("key-1".to_owned());
+    let key = ::TEST_HS256_SECRET;
+    let encoded = JWS::from_claims(header, Claims::new()).encode(key, ALGORITHM::HS256).unwrap();
+
+    let parsed = ParsedToken::parse(&encoded).unwrap();
+    assert_eq!(parsed.header().kid, Some("key-1".to_owned()));
+    assert!(parsed.verify(key, ALGORITHM::HS256).is_ok());
+    assert_err!(parsed.verify(b"another 32-byte secret for HS256", ALGORITHM::HS256), Error::JWSInvalidSignature);
+}
+
+#[test]
+fn claims_returning_verify_paths_reject_expired_tokens() {
+    let key = ::TEST_HS256_SECRET;
+    let mut claims = Claims::new();
+    claims.exp = Some(1000);
+    let encoded = JWS::from_claims(Header::new(), claims).encode(key, ALGORITHM::HS256).unwrap();
+    let parsed = ParsedToken::parse(&encoded).unwrap();
+    let decoding = simple::decoding_key(key, ALGORITHM::HS256).unwrap();
+
+    let results = vec![
+        parsed.verify(key, ALGORITHM::HS256).map(|_| ()),
+        parsed.verify_with_limits(key, ALGORITHM::HS256, &Limits::new()).map(|_| ()),
+        parsed.verify_with_validation(key, ALGORITHM::HS256, &Validation::new()).map(|_| ()),
+        parsed.verify_with_key(&decoding, &Validation::new()).map(|_| ()),
+        JWS::decode_jwt_with_limits(encoded.clone(), key, ALGORITHM::HS256, &Limits::new()).map(|_| ()),
+    ];
+    for result in results {
+        assert_err!(result, Error::TokenExpired { exp: 1000, .. });
+    }
+    assert!(parsed.verify_with_validation_at(key, ALGORITHM::HS256, &Validation::new(), 900).is_ok());
+    assert!(parsed.verify_with_key_at(&decoding, &Validation::new(), 900).is_ok());
+    assert!(parsed.verify_raw(key, ALGORITHM::HS256).is_ok());
+}
+
+#[test]
+fn tokens_without_three_segments_fail_to_parse() {
+    assert_err!(ParsedToken::parse("abc.def"), Error::MalformedToken);
+}
+
+#[test]
+fn decode_header_reads_only_the_first_segment() {
+    let mut header = Header::new();
+    header.alg = ALGORITHM::HS384;
+    header.kid = Some("key-2".to_owned());
+    let token = JWS::from_claims(header, Claims::new()).encode(b"a 48-byte secret, long enough for the HS384 test", ALGORITHM::HS384).unwrap();
+    let protected = token.split('.').next().unwrap();
+
+    let decoded = JWS::decode_header(&format!("{}.not json!.not base64!", protected)).unwrap();
+    assert_eq!(decoded.alg, ALGORITHM::HS384);
+    assert_eq!(decoded.kid, Some("key-2".to_owned()));
+    assert_eq!(JWS::decode_header(&token).unwrap(), ParsedToken::parse(&token).unwrap().header().clone());
+
+    for malformed in &[protected.to_owned(), format!("{}.a.b.c", protected), format!("{}=.a.b", protected)] {
+        assert!(JWS::decode_header(malformed).is_err());
+    }
+    let limits = Limits { max_token_size: 16, ..Limits::new() };
+    assert_err!(JWS::decode_header_with_limits(&token, &limits), Error::TokenTooLarge { limit: 16, .. });
+}
+
+#[test]
+fn decode_failures_name_what_went_wrong() {
+    let token = JWS::from_claims(Header::new(), Claims::new()).encode(::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap();
+    let parts: Vec<&str> = token.split('.').collect();
+
+    assert_err!(ParsedToken::parse(&format!("{}!.{}.{}", parts[0], parts[1], parts[2])), Error::MalformedPart { part: "header" });
+    assert_err!(ParsedToken::parse(&format!("{}.{}.{}", base64_url_encode_bytes(b"[1]"), parts[1], parts[2])), Error::InvalidHeader(_));
+    assert_err!(JWS::decode_jwt_with_limits(format!("{}.{}!.{}", parts[0], parts[1], parts[2]), ::TEST_HS256_SECRET, ALGORITHM::HS256, &Limits::new()), Error::MalformedPart { part: "payload" });
+    let lenient = Limits { base64: Base64Mode::Lenient, ..Limits::new() };
+    assert_err!(JWS::decode_jwt_with_limits(format!("{}.{}!.{}", parts[0], parts[1], parts[2]), ::TEST_HS256_SECRET, ALGORITHM::HS256, &lenient), Error::JWSInvalidSignature);
+    assert_err!(ParsedToken::parse(&token).unwrap().verify(::TEST_HS256_SECRET, ALGORITHM::HS512), Error::WrongAlgorithm { expected: ALGORITHM::HS512, found: ALGORITHM::HS256 });
+    assert_err!(JWS::from_raw_parts(parts[0], parts[1], "A"), Error::MalformedPart { part: "signature" });
+}
+
+#[test]
+fn profile_tokens_carry_the_profile_typ() {
+    let mut claims = Claims::new();
+    claims.iss = Some("https://issuer.example.com".to_owned());
+    claims.sub = Some("user".to_owned());
+    claims.aud = Some("api".into());
+    claims.exp = Some(4102444800);
+    claims.set("client_id", "client");
+    let key = ::TEST_HS256_SECRET;
+    let t = JWS::from_profile(Profile::AccessToken, Header::new(), claims).unwrap();
+    let encoded = t.encode(key, ALGORITHM::HS256).unwrap();
+    let (header, _) = JWS::decode_jwt(encoded, key, ALGORITHM::HS256).unwrap();
+    assert!(header.typ_matches(&Typ::AT_JWT));
+}
+
+#[test]
+fn jwt_typ_can_be_overridden_or_omitted() {
+    let key = ::TEST_HS256_SECRET;
+    let mut t = JWS::from_claims(Header::new(), Claims::new());
+    let (header, _) = JWS::decode_jwt(t.encode(key, ALGORITHM::HS256).unwrap(), key, ALGORITHM::HS256).unwrap();
+    assert_eq!(header.typ, Some("JWT".to_owned()));
+
+    t.set_jwt_typ(JwtTyp::Custom("secevent+jwt".to_owned()));
+    let (header, _) = JWS::decode_jwt(t.encode(key, ALGORITHM::HS256).unwrap(), key, ALGORITHM::HS256).unwrap();
+    assert_eq!(header.typ, Some("secevent+jwt".to_owned()));
+
+    t.set_jwt_typ(JwtTyp::Omitted);
+    let encoded = t.encode(key, ALGORITHM::HS256).unwrap();
+    let (header, _) = JWS::decode_jwt(encoded, key, ALGORITHM::HS256).unwrap();
+    assert_eq!(header.typ, None);
+}
+
+#[test]
+fn unknown_header_fields_survive_decode_modify_and_resign() {
+    let key = ::TEST_HS256_SECRET;
+    let header_json = r#"{"alg":"HS256","typ":"JWT","zz":{"a":[1,{"b":null}],"c":"d"},"crit":["zz"],"aa":true}"#;
+    let header_b64 = base64_url_encode(header_json.to_owned());
+    let payload_b64 = base64_url_encode(r#"{"iss":"partner"}"#.to_owned());
+    let signing_input = format!("{}.{}", header_b64, payload_b64);
+    let signature = signing::hmac_256(key, signing_input.as_bytes()).unwrap();
+    let token = format!("{}.{}", signing_input, base64_url_encode_bytes(signature.as_slice()));
+
+    let (header, mut claims) = JWS::decode_jwt(token, key, ALGORITHM::HS256).unwrap();
+    claims.sub = Some("rewritten".to_owned());
+    let resigned = JWS::from_claims(header.clone(), claims).encode(key, ALGORITHM::HS256).unwrap();
+
+    assert_eq!(resigned.split('.').next().unwrap(), header_b64);
+    let (reheader, reclaims) = JWS::decode_jwt(resigned, key, ALGORITHM::HS256).unwrap();
+    assert_eq!(reheader, header);
+    assert_eq!(reclaims.sub, Some("rewritten".to_owned()));
+}
+
+#[test]
+fn well_formed_tokens_report_their_shape() {
+    let mut header = Header::new();
+    header.kid = Some("key-1".to_owned());
+    let key = ::TEST_HS256_SECRET;
+    let encoded = JWS::from_claims(header.clone(), Claims::new()).encode(key, ALGORITHM::HS256).unwrap();
+    let shape = is_well_formed(&encoded).unwrap();
+    let segments: Vec<&str> = encoded.split('.').collect();
+    assert_eq!(shape.header_len, segments[0].len());
+    assert_eq!(shape.payload_len, segments[1].len());
+    assert_eq!(shape.signature_len, 43);
+    assert_eq!(shape.alg, ALGORITHM::HS256);
+    assert_eq!(shape.kid, Some("key-1".to_owned()));
+    assert_eq!(shape.typ, Some("JWT".to_owned()));
+    assert_eq!(shape.payload_kind, PayloadKind::JsonObject);
+
+    let raw = JWS::from_custom(header, b"hello".to_vec()).encode(key, ALGORITHM::HS256).unwrap();
+    assert_eq!(is_well_formed(&raw).unwrap().payload_kind, PayloadKind::Raw);
+}
+
+#[test]
+fn junk_is_not_well_formed() {
+    assert!(is_well_formed("junk").is_err());
+    assert!(is_well_formed("a.b.c").is_err());
+    assert!(is_well_formed("eyJhbGciOiJIUzI1NiJ9.e30.!!!").is_err());
+}
+
+#[test]
+fn tokens_with_an_empty_signature_are_rejected() {
+    let key = ::TEST_HS256_SECRET;
+    let t = JWS::from_claims(Header::new(), Claims::new());
+    let unsigned = format!("{}.", t.signing_input().unwrap());
+    assert_err!(JWS::decode_jwt(unsigned, key, ALGORITHM::HS256), Error::MissingSignature);
+}
+
+#[test]
+fn empty_hmac_secrets_are_errors_not_panics() {
+    let token = JWS::from_claims(Header::new(), Claims::new()).encode(::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap();
+    assert!(JWS::from_claims(Header::new(), Claims::new()).encode(b"", ALGORITHM::HS256).is_err());
+    assert!(JWS::decode_jwt(token, b"", ALGORITHM::HS256).is_err());
+}
+
+#[test]
+fn hmac_signatures_must_match_byte_for_byte() {
+    const ALPHABET: &'static str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    for alg in &[ALGORITHM::HS256, ALGORITHM::HS384, ALGORITHM::HS512] {
+        let mut header = Header::new();
+        header.alg = alg.clone();
+        let token = JWS::from_claims(header, Claims::new()).encode(b"a 64-byte secret, long enough for the HS384 and HS512 tests, too", alg.clone()).unwrap();
+        let (signing_input, signature) = token.split_at(token.rfind('.').unwrap() + 1);
+        let last = (ALPHABET.find(&signature[signature.len() - 1..]).unwrap() + 1) % 64;
+        // For HS256 and HS512 this is the same MAC with different unused trailing bits.
+        let respelled = format!("{}{}", &signature[..signature.len() - 1], &ALPHABET[last..last + 1]);
+        for forged in &[respelled, format!("{}=", signature), signature[1..].to_owned(), format!("{}A", signature)] {
+            match JWS::decode_jwt(format!("{}{}", signing_input, forged), b"a 64-byte secret, long enough for the HS384 and HS512 tests, too", alg.clone()) {
+                Err(Error::JWSInvalidSignature) | Err(Error::MalformedPart { part: "signature" }) => {},
+                other => panic!("expected a rejected signature for {}, got {:?}", forged, other)
+            }
+        }
+        assert!(JWS::decode_jwt(token.clone(), b"a 64-byte secret, long enough for the HS384 and HS512 tests, too", alg.clone()).is_ok());
+    }
+}
+
+#[test]
+fn non_ascii_claim_values_survive_the_round_trip() {
+    let long: String = ::std::iter::repeat("\u{1F600}ü").take(100000).collect();
+    let mut claims = Claims::new();
+    claims.sub = Some("\u{1D11E} \u{10FFFF} 日本".to_owned());
+    claims.set("nul", "before\u{0}after");
+    claims.set("long", long.as_str());
+    claims.set("\u{1F511}", "key");
+    let key = ::TEST_HS256_SECRET;
+    let encoded = JWS::from_claims(Header::new(), claims.clone()).encode(key, ALGORITHM::HS256).unwrap();
+    let limits = Limits { max_token_size: 2 * 1024 * 1024, max_payload_size: 1024 * 1024, ..Limits::new() };
+    let (_, decoded_claims) = JWS::decode_jwt_with_limits(encoded, key, ALGORITHM::HS256, &limits).unwrap();
+    assert_eq!(decoded_claims, claims);
+    let decoded_long: String = decoded_claims.get("long").unwrap();
+    assert_eq!(decoded_long.len(), long.len());
+}
+
+#[test]
+fn decode_jwt_enforces_limits() {
+    let mut claims = Claims::new();
+    claims.set("blob", ::std::iter::repeat("x").take(100).collect::<String>());
+    let key = ::TEST_HS256_SECRET;
+    let encoded = JWS::from_claims(Header::new(), claims).encode(key, ALGORITHM::HS256).unwrap();
+    let limits = Limits { max_token_size: 1024, max_payload_size: 64, ..Limits::new() };
+    assert_err!(JWS::decode_jwt_with_limits(encoded.clone(), key, ALGORITHM::HS256, &limits), Error::PayloadTooLarge { .. });
+    let limits = Limits { max_token_size: 64, max_payload_size: 1024, ..Limits::new() };
+    assert_err!(JWS::decode_jwt_with_limits(encoded.clone(), key, ALGORITHM::HS256, &limits), Error::TokenTooLarge { .. });
+    assert!(JWS::decode_jwt(encoded, key, ALGORITHM::HS256).is_ok());
+}
+
+#[test]
+fn strict_base64_rejects_non_canonical_segments() {
+    let encoded = JWS::from_claims(Header::new(), Claims::new()).encode(::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap();
+    let parts: Vec<&str> = encoded.split('.').collect();
+    let strict = Limits::new();
+    assert!(JWS::decode_jwt_with_limits(encoded.clone(), ::TEST_HS256_SECRET, ALGORITHM::HS256, &strict).is_ok());
+
+    let padded_header = format!("{}==", parts[0].trim_end_matches('='));
+    let standard_payload = format!("{}/", parts[1]);
+    let cases = vec![
+        (format!("{}.{}.{}", padded_header, parts[1], parts[2]), "header"),
+        (format!("{}.{}\n.{}", parts[0], parts[1], parts[2]), "payload"),
+        (format!("{}.{}.{}", parts[0], standard_payload, parts[2]), "payload"),
+        (format!("{}.{}.{}=", parts[0], parts[1], parts[2]), "signature"),
+    ];
+    for (token, name) in cases {
+        assert_err!(JWS::decode_jwt_with_limits(token, ::TEST_HS256_SECRET, ALGORITHM::HS256, &strict), Error::MalformedPart { part } => assert_eq!(part, name));
+    }
+}
+
+#[test]
+fn lenient_base64_accepts_padded_and_standard_alphabet_tokens() {
+    use rustc_serialize::base64::STANDARD;
+
+    let mut claims = Claims::new();
+    claims.set("data", "\u{fb}\u{ff}?>");
+    let mut header = Header::new();
+    header.alg = ALGORITHM::HS256;
+    let signing_input = format!("{}.{}",
+        header.to_json().unwrap().as_bytes().to_base64(STANDARD),
+        claims.to_json().unwrap().as_bytes().to_base64(STANDARD));
+    let mac = ::openssl::sign::Signer::new(MessageDigest::sha256(), &::openssl::pkey::PKey::hmac(::TEST_HS256_SECRET).unwrap())
+        .and_then(|mut signer| { try!(signer.update(signing_input.as_bytes())); signer.sign_to_vec() })
+        .unwrap();
+    let token = format!("{}.{}", signing_input, mac.to_base64(STANDARD));
+    assert!(token.contains('=') || token.contains('+') || token.contains('/'));
+
+    assert_err!(JWS::decode_jwt_with_limits(token.clone(), ::TEST_HS256_SECRET, ALGORITHM::HS256, &Limits::new()), Error::MalformedPart { .. });
+    let lenient = Limits { base64: Base64Mode::Lenient, ..Limits::new() };
+    let (_, decoded) = JWS::decode_jwt_with_limits(token.clone(), ::TEST_HS256_SECRET, ALGORITHM::HS256, &lenient).unwrap();
+    assert_eq!(decoded, claims);
+    assert!(JWS::decode_jwt_with_limits(token, b"other", ALGORITHM::HS256, &lenient).is_err());
+}
+
+#[test]
+fn invalid_utf8_payload_in_jwt_mode_returns_the_raw_bytes() {
+    let key = ::TEST_HS256_SECRET;
+    let payload = vec![b'{', 0xff, 0xfe, b'}'];
+    let encoded = JWS::from_custom(Header::new(), payload.clone()).encode(key, ALGORITHM::HS256).unwrap();
+    assert_err!(JWS::decode_jwt(encoded, key, ALGORITHM::HS256), Error::NonJsonPayload { bytes } => assert_eq!(bytes, payload));
+}
+
+#[test]
+fn non_json_payload_in_jwt_mode_returns_the_raw_bytes() {
+    let key = ::TEST_HS256_SECRET;
+    let encoded = JWS::from_custom(Header::new(), b"plain text".to_vec()).encode(key, ALGORITHM::HS256).unwrap();
+    assert_err!(JWS::decode_jwt(encoded, key, ALGORITHM::HS256), Error::NonJsonPayload { bytes } => assert_eq!(bytes, b"plain text".to_vec()));
+}
+
+#[test]
+fn decode_raw_returns_the_payload_bytes_untouched() {
+    let key = ::TEST_HS256_SECRET;
+    let encoded = JWS::from_custom(Header::new(), b"plain text".to_vec()).encode(key, ALGORITHM::HS256).unwrap();
+    let (header, payload) = JWS::decode_raw(encoded, key, ALGORITHM::HS256).unwrap();
+    assert_eq!(header.alg, ALGORITHM::HS256);
+    assert_eq!(payload, b"plain text".to_vec());
+
+    let jwt = JWS::from_claims(Header::new(), Claims::new()).encode(key, ALGORITHM::HS256).unwrap();
+    let (_, payload) = JWS::decode_raw(jwt.clone(), key, ALGORITHM::HS256).unwrap();
+    assert_eq!(payload, b"{}".to_vec());
+    assert!(JWS::decode_raw(jwt, b"other", ALGORITHM::HS256).is_err());
+}
+
+#[test]
+fn verified_wrappers_are_only_produced_by_verification() {
+    fn authorize(token: &VerifiedJwt) -> bool {
+        token.claims().sub == Some("admin".to_owned())
+    }
+
+    let key = ::TEST_HS256_SECRET;
+    let mut claims = Claims::new();
+    claims.sub = Some("admin".to_owned());
+    claims.set("scope", "read");
+    let encoded = JWS::from_claims(Header::new(), claims).encode(key, ALGORITHM::HS256).unwrap();
+
+    let verified: VerifiedJwt = JWS::verify_jwt(&encoded, key, ALGORITHM::HS256).unwrap();
+    assert!(authorize(&verified));
+    let typed: VerifiedJwt<::std::collections::BTreeMap<String, String>> = JWS::verify_jwt(&encoded, key, ALGORITHM::HS256).unwrap();
+    assert_eq!(typed.claims().get("scope"), Some(&"read".to_owned()));
+    assert!(JWS::verify_jwt::<Claims>(&encoded, b"other", ALGORITHM::HS256).is_err());
+
+    let raw = JWS::verify_jws(&encoded, key, ALGORITHM::HS256).unwrap();
+    assert_eq!(raw.header().alg, ALGORITHM::HS256);
+    assert!(raw.payload().starts_with(b"{"));
+}
+
+#[test]
+fn verified_wrappers_require_valid_claims() {
+    let key = ::TEST_HS256_SECRET;
+    let mut claims = Claims::new();
+    claims.exp = Some(1000);
+    let encoded = JWS::from_claims(Header::new(), claims).encode(key, ALGORITHM::HS256).unwrap();
+
+    assert_err!(JWS::verify_jwt::<Claims>(&encoded, key, ALGORITHM::HS256), Error::TokenExpired { exp: 1000, .. });
+    assert_err!(JWS::verify_jws(&encoded, key, ALGORITHM::HS256), Error::TokenExpired { exp: 1000, .. });
+    assert!(JWS::verify_jwt_at::<Claims>(&encoded, key, ALGORITHM::HS256, &Validation::new(), 900).is_ok());
+    assert!(JWS::verify_jws_at(&encoded, key, ALGORITHM::HS256, &Validation::new(), 900).is_ok());
+
+    let parts: Vec<&str> = encoded.split('.').collect();
+    let flattened = format!(r#"{{"protected":"{}","payload":"{}","signature":"{}"}}"#, parts[0], parts[1], parts[2]);
+    for input in &[encoded.clone(), flattened] {
+        assert_err!(decode_any(input, key, ALGORITHM::HS256), Error::TokenExpired { exp: 1000, .. });
+    }
+
+    let raw = JWS::from_custom(Header::new(), b"plain text".to_vec()).encode(key, ALGORITHM::HS256).unwrap();
+    assert!(JWS::verify_jws(&raw, key, ALGORITHM::HS256).is_ok());
+    assert_err!(JWS::verify_jws_with_validation(&raw, key, ALGORITHM::HS256, &Validation::new().require(&["sub"])), Error::MissingClaims(missing) => assert_eq!(missing, vec!["sub".to_owned()]));
+}
+
+#[test]
+fn rsa_tokens_round_trip_with_pkcs1_and_pss() {
+    let pem = PKey::from_rsa(::openssl::rsa::Rsa::generate(2048).unwrap()).unwrap().private_key_to_pem_pkcs8().unwrap();
+    for alg in vec![ALGORITHM::RS256, ALGORITHM::RS512, ALGORITHM::PS256, ALGORITHM::PS384, ALGORITHM::PS512] {
+        let mut header = Header::new();
+        header.alg = alg.clone();
+        let encoded = JWS::from_claims(header, Claims::new()).encode(pem.as_slice(), alg.clone()).unwrap();
+        let (decoded, _) = JWS::decode_jwt(encoded.clone(), pem.as_slice(), alg.clone()).unwrap();
+        assert_eq!(decoded.alg, alg);
+    }
+}
+
+#[test]
+fn eddsa_tokens_round_trip() {
+    let key = [3u8; 32];
+    let mut header = Header::new();
+    header.alg = ALGORITHM::EdDSA;
+    let encoded = JWS::from_claims(header, Claims::new()).encode(&key, ALGORITHM::EdDSA).unwrap();
+    assert_eq!(is_well_formed(&encoded).unwrap().signature_len, 86);
+    let public = signing::eddsa_key(&key).unwrap().raw_public_key().unwrap();
+    assert!(JWS::decode_jwt(encoded.clone(), &public, ALGORITHM::EdDSA).is_ok());
+    assert!(JWS::decode_jwt(encoded.clone(), &key, ALGORITHM::EdDSA).is_err());
+    assert!(JWS::decode_jwt(encoded, &[4u8; 32], ALGORITHM::EdDSA).is_err());
+}
+
+#[test]
+fn es256k_tokens_round_trip_and_reject_other_curves() {
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+
+    let pem = |nid| {
+        let group = EcGroup::from_curve_name(nid).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap().private_key_to_pem_pkcs8().unwrap()
+    };
+    let key = pem(Nid::SECP256K1);
+    let mut header = Header::new();
+    header.alg = ALGORITHM::ES256K;
+    let t = JWS::from_claims(header, Claims::new());
+    let encoded = t.encode(key.as_slice(), ALGORITHM::ES256K).unwrap();
+    assert!(JWS::decode_jwt(encoded, key.as_slice(), ALGORITHM::ES256K).is_ok());
+
+    assert_err!(t.encode(pem(Nid::X9_62_PRIME256V1).as_slice(), ALGORITHM::ES256K), Error::CurveMismatch { alg: ALGORITHM::ES256K, ref expected, ref found } => {
+        assert_eq!((expected.as_str(), found.as_str()), ("secp256k1", "P-256"));
+    });
+}
+
+#[test]
+fn es_algorithms_are_pinned_to_their_curves() {
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+
+    let key = |nid| PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(nid).unwrap()).unwrap()).unwrap();
+    let cases = [(ALGORITHM::ES256, Nid::X9_62_PRIME256V1, 64), (ALGORITHM::ES384, Nid::SECP384R1, 96), (ALGORITHM::ES512, Nid::SECP521R1, 132)];
+    for &(ref alg, nid, signature_len) in cases.iter() {
+        let key = key(nid);
+        let mut header = Header::new();
+        header.alg = alg.clone();
+        let token = JWS::from_claims(header, Claims::new()).encode(&key.private_key_to_pem_pkcs8().unwrap(), alg.clone()).unwrap();
+        assert_eq!(token.rsplit('.').next().unwrap().from_base64().unwrap().len(), signature_len);
+        assert!(JWS::decode_jwt(token, &key.public_key_to_pem().unwrap(), alg.clone()).is_ok());
+    }
+
+    let p384 = key(Nid::SECP384R1).private_key_to_pem_pkcs8().unwrap();
+    assert_err!(JWS::from_claims(Header::new(), Claims::new()).encode(&p384, ALGORITHM::ES256), Error::CurveMismatch { ref expected, ref found, .. } => assert_eq!((expected.as_str(), found.as_str()), ("P-256", "P-384")));
+}
+
+#[test]
+fn es256_with_secp256k1_is_reported_unless_allowed() {
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+
+    // Tokens like these come from issuers that label secp256k1 signatures ES256.
+    let k1 = PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::SECP256K1).unwrap()).unwrap()).unwrap();
+    let mut header = Header::new();
+    header.alg = ALGORITHM::ES256;
+    let t = JWS::from_claims(header, Claims::new());
+    let signature = signing::sign_es256k(&k1, t.signing_input().unwrap().as_bytes()).unwrap();
+    let token = t.attach_signature(&signature).unwrap();
+    let public = k1.public_key_to_pem().unwrap();
+
+    assert_err!(JWS::decode_jwt(token.clone(), &public, ALGORITHM::ES256), Error::CurveMismatch { alg: ALGORITHM::ES256, ref found, .. } => assert_eq!(found, "secp256k1"));
+    let mut options = Validation::new();
+    options.allow_es256_secp256k1 = true;
+    assert!(ParsedToken::parse(&token).unwrap().verify_with_validation(&public, ALGORITHM::ES256, &options).is_ok());
+    assert!(options.decode(&token, &public, ALGORITHM::ES256).is_ok());
+
+    let es256k = JWS::from_claims(Header::new(), Claims::new());
+    assert!(es256k.encode(&k1.private_key_to_pem_pkcs8().unwrap(), ALGORITHM::ES256).is_err());
+}
+
+#[test]
+fn unsecured_tokens_only_pass_the_dangerous_decoder() {
+    let mut claims = Claims::new();
+    claims.sub = Some("pipeline".to_owned());
+    let t = JWS::from_claims(Header::new(), claims);
+    let unsecured = t.encode_unsecured().unwrap();
+    assert!(unsecured.ends_with("."));
+
+    let (header, claims) = JWS::decode_unsecured_dangerous(unsecured.clone()).unwrap();
+    assert_eq!(header.alg, ALGORITHM::None);
+    assert_eq!(claims.sub, Some("pipeline".to_owned()));
+
+    for alg in vec![ALGORITHM::None, ALGORITHM::HS256] {
+        assert!(JWS::decode_jwt(unsecured.clone(), b"", alg.clone()).is_err());
+        assert!(JWS::decode_raw(unsecured.clone(), b"", alg).is_err());
+    }
+    assert!(t.encode(b"", ALGORITHM::None).is_err());
+
+    let signed = t.encode(::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap();
+    assert!(JWS::decode_unsecured_dangerous(signed).is_err());
+}
+
+#[test]
+fn tokens_with_unknown_algorithms_fail_to_parse() {
+    let header_b64 = base64_url_encode(r#"{"alg":"HS1024"}"#.to_owned());
+    let token = format!("{}.{}.c2ln", header_b64, base64_url_encode("{}".to_owned()));
+    assert_err!(ParsedToken::parse(&token), Error::UnsupportedAlgorithm(alg) => assert_eq!(alg, "HS1024"));
+    assert!(JWS::from_claims(Header::new(), Claims::new()).encode(::TEST_HS256_SECRET, ALGORITHM::ES256).is_err());
+}
+
+#[test]
+fn asymmetric_tokens_verify_with_only_the_public_key() {
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+
+    let rsa = PKey::from_rsa(::openssl::rsa::Rsa::generate(2048).unwrap()).unwrap();
+    let k1 = PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::SECP256K1).unwrap()).unwrap()).unwrap();
+    let ed = signing::eddsa_key(&[6u8; 32]).unwrap();
+    let keys = vec![
+        (ALGORITHM::RS256, rsa.private_key_to_pem_pkcs8().unwrap(), rsa.public_key_to_pem().unwrap()),
+        (ALGORITHM::PS512, rsa.private_key_to_pem_pkcs8().unwrap(), rsa.public_key_to_pem().unwrap()),
+        (ALGORITHM::ES256K, k1.private_key_to_pem_pkcs8().unwrap(), k1.public_key_to_pem().unwrap()),
+        (ALGORITHM::EdDSA, vec![6u8; 32], ed.public_key_to_pem().unwrap()),
+    ];
+    for (alg, private, public) in keys {
+        let mut header = Header::new();
+        header.alg = alg.clone();
+        let encoded = JWS::from_claims(header, Claims::new()).encode(private.as_slice(), alg.clone()).unwrap();
+        assert!(JWS::decode_jwt(encoded.clone(), public.as_slice(), alg.clone()).is_ok());
+        assert!(JWS::from_claims(Header::new(), Claims::new()).encode(public.as_slice(), alg).is_err());
+    }
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn jwt_round_trips_for_arbitrary_headers_and_claims(header in ::arbitrary::arb_header(),
+                                                        claims in ::arbitrary::arb_claims(),
+                                                        alg in ::arbitrary::arb_hmac_algorithm(),
+                                                        secret in ::proptest::collection::vec(::proptest::prelude::any::<u8>(), 64..128)) {
+        let mut header = header;
+        header.alg = alg.clone();
+        let encoded = JWS::from_claims(header.clone(), claims.clone()).encode(&secret, alg.clone()).unwrap();
+        let (decoded_header, decoded_claims) = JWS::decode_jwt_with_validation(encoded, &secret, alg, &Validation::signature_only()).unwrap();
+        header.typ = header.typ.or(Some("JWT".to_owned()));
+        prop_assert_eq!(decoded_header, header);
+        prop_assert_eq!(decoded_claims, claims);
+    }
+}
+
+// Key generation is slow, so the keys are made once and the runner drives the cases.
+#[test]
+fn jwt_round_trips_for_every_algorithm() {
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::rsa::Rsa;
+    use proptest::test_runner::TestRunner;
+
+    let ec = |nid| PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(nid).unwrap()).unwrap()).unwrap();
+    let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let (p256, p384, p521, k1) = (ec(Nid::X9_62_PRIME256V1), ec(Nid::SECP384R1), ec(Nid::SECP521R1), ec(Nid::SECP256K1));
+    let ed = PKey::generate_ed25519().unwrap();
+    let secret = vec![7u8; 64];
+    let pair = |key: &PKey<::openssl::pkey::Private>| (key.private_key_to_pkcs8().unwrap(), key.public_key_to_der().unwrap());
+    let keys = |alg: &ALGORITHM| match *alg {
+        ALGORITHM::HS256 | ALGORITHM::HS384 | ALGORITHM::HS512 => (secret.clone(), secret.clone()),
+        ALGORITHM::RS256 | ALGORITHM::RS384 | ALGORITHM::RS512 | ALGORITHM::PS256 | ALGORITHM::PS384 | ALGORITHM::PS512 => pair(&rsa),
+        ALGORITHM::ES256 => pair(&p256),
+        ALGORITHM::ES384 => pair(&p384),
+        ALGORITHM::ES512 => pair(&p521),
+        ALGORITHM::ES256K => pair(&k1),
+        ALGORITHM::EdDSA => pair(&ed),
+        ref other => panic!("no test key for {:?}", other)
+    };
+
+    TestRunner::default().run(&(::arbitrary::arb_header(), ::arbitrary::arb_claims(), ::arbitrary::arb_algorithm()), |(header, claims, alg)| {
+        let (private, public) = keys(&alg);
+        let mut header = header;
+        header.alg = alg.clone();
+        let encoded = JWS::from_claims(header.clone(), claims.clone()).encode(&private, alg.clone()).unwrap();
+        let (decoded_header, decoded_claims) = JWS::decode_jwt_with_validation(encoded, &public, alg, &Validation::signature_only()).unwrap();
+        header.typ = header.typ.or(Some("JWT".to_owned()));
+        prop_assert_eq!(decoded_header, header);
+        prop_assert_eq!(decoded_claims, claims);
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn encrypted_keys_sign_after_loading() {
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::symm::Cipher;
+
+    let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let encrypted = pkey.private_key_to_pem_pkcs8_passphrase(Cipher::aes_256_cbc(), b"hunter2").unwrap();
+    assert_err!(JWS::from_claims(Header::new(), Claims::new()).encode(&encrypted, ALGORITHM::RS256), Error::KeyParseError { hint: Some(hint), .. } => assert!(hint.contains("passphrase")));
+
+    let key = JWS::load_private_key(&encrypted, Some(b"hunter2")).unwrap();
+    let mut header = Header::new();
+    header.alg = ALGORITHM::RS256;
+    let token = JWS::from_claims(header, Claims::new()).encode(&key, ALGORITHM::RS256).unwrap();
+    assert!(JWS::decode_jwt(token, &pkey.public_key_to_pem().unwrap(), ALGORITHM::RS256).is_ok());
+    assert!(JWS::load_private_key(&encrypted, Some(b"wrong")).is_err());
+}
+
+#[test]
+fn certificates_verify_tokens_signed_by_their_key() {
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::x509::{X509Builder, X509NameBuilder};
+
+    let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "issuer.example").unwrap();
+    let name = name.build();
+    let mut builder = X509Builder::new().unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_issuer_name(&name).unwrap();
+    builder.set_pubkey(&pkey).unwrap();
+    builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+    builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+    builder.sign(&pkey, MessageDigest::sha256()).unwrap();
+    let cert = builder.build();
+
+    let mut header = Header::new();
+    header.alg = ALGORITHM::RS256;
+    let token = JWS::from_claims(header, Claims::new()).encode(&pkey.private_key_to_pem_pkcs8().unwrap(), ALGORITHM::RS256).unwrap();
+    assert!(JWS::decode_jwt(token.clone(), &cert.to_pem().unwrap(), ALGORITHM::RS256).is_ok());
+    assert!(JWS::decode_jwt(token.clone(), &cert.to_der().unwrap(), ALGORITHM::RS256).is_ok());
+
+    let spki = JWS::public_key_from_certificate(&cert.to_pem().unwrap()).unwrap();
+    assert_eq!(spki, pkey.public_key_to_der().unwrap());
+    assert!(JWS::decode_jwt(token, &spki, ALGORITHM::RS256).is_ok());
+    assert!(JWS::public_key_from_certificate(b"-----BEGIN CERTIFICATE-----\n").is_err());
+}
+
+#[test]
+fn raw_parts_keep_the_protected_header_bytes() {
+    // Whitespace and member order that re-serialization would not reproduce.
+    let protected = base64_url_encode_bytes(b"{ \"typ\" : \"JWT\",\r\n \"alg\" : \"HS256\" }");
+    let payload = base64_url_encode_bytes(b"{\"iss\":\"joe\"}");
+    let signature = base64_url_encode_bytes(&signing::hmac_256(::TEST_HS256_SECRET, format!("{}.{}", protected, payload).as_bytes()).unwrap());
+
+    let parsed = JWS::from_raw_parts(&protected, &payload, &signature).unwrap();
+    assert_eq!(parsed.to_compact(), format!("{}.{}.{}", protected, payload, signature));
+    assert_eq!(parsed.header().typ, Some("JWT".to_owned()));
+
+    let verified = JWS::verify_raw_parts(&protected, &payload, &signature, ::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap();
+    assert_eq!(verified.payload(), b"{\"iss\":\"joe\"}");
+    assert!(JWS::verify_raw_parts(&protected, &payload, &signature, b"other", ALGORITHM::HS256).is_err());
+}
+
+#[test]
+fn raw_parts_must_be_unpadded_base64url() {
+    let protected = base64_url_encode_bytes(b"{\"alg\":\"HS256\"}");
+    assert_err!(JWS::from_raw_parts(&format!("{}.e30", protected), "e30", ""), Error::MalformedToken);
+    assert!(JWS::from_raw_parts(&protected, "e30=", "").is_err());
+    assert!(JWS::from_raw_parts(&protected, "e30+", "").is_err());
+    assert!(JWS::from_raw_parts(&protected, "e30", "").is_ok());
+    assert!(JWS::from_raw_parts("e30", "e30", "").is_err());
+}
+
+#[test]
+fn every_serialization_is_detected_and_verified() {
+    let compact = JWS::from_claims(Header::new(), Claims::new()).encode(::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap();
+    let parts: Vec<&str> = compact.split('.').collect();
+    let flattened = format!(r#"{{"protected":"{}","header":{{"kid":"a"}},"payload":"{}","signature":"{}"}}"#,
+                            parts[0], parts[1], parts[2]);
+    let general = format!(r#"{{"payload":"{}","signatures":[{{"protected":"{}","signature":"AAAA"}},{{"protected":"{}","signature":"{}"}}]}}"#,
+                          parts[1], parts[0], parts[0], parts[2]);
+
+    assert_eq!(detect_format(&compact), TokenFormat::Compact);
+    assert_eq!(detect_format(&flattened), TokenFormat::FlattenedJson);
+    assert_eq!(detect_format(&general), TokenFormat::GeneralJson);
+    assert_eq!(detect_format("{\"payload\":\"e30\"}"), TokenFormat::Unknown);
+    assert_eq!(detect_format("not a token"), TokenFormat::Unknown);
+
+    let expected = JWS::verify_jws(&compact, ::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap();
+    for input in &[compact.clone(), flattened.clone(), general.clone()] {
+        assert_eq!(decode_any(input, ::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap(), expected);
+        assert!(decode_any(input, b"wrong", ALGORITHM::HS256).is_err());
+    }
+    assert_err!(decode_any("not a token", ::TEST_HS256_SECRET, ALGORITHM::HS256), Error::MalformedToken);
+}
+
+#[test]
+fn general_json_signatures_are_combined_by_policy() {
+    use jwk::Jwk;
+
+    let secrets: [&[u8]; 3] = [b"signer a's secret, 32 bytes long", b"signer b's secret, 32 bytes long", b"signer c's secret, 32 bytes long"];
+    let keys = JwkSet::new(["a", "b", "c"].iter().zip(secrets.iter()).map(|(kid, secret)| {
+        let mut jwk = Jwk::oct(secret);
+        jwk.kid = Some(kid.to_string());
+        jwk
+    }).collect());
+    let signed = |kid: &str, secret: &[u8]| -> Vec<String> {
+        let mut header = Header::new();
+        header.kid = Some(kid.to_owned());
+        let token = JWS::from_claims(header, Claims::new()).encode(secret, ALGORITHM::HS256).unwrap();
+        token.split('.').map(|part| part.to_owned()).collect()
+    };
+    let (a, b) = (signed("a", secrets[0]), signed("b", secrets[1]));
+    let forged = signed("c", b"not signer c's secret, but 32 b.");
+    let general = |parts: &[&Vec<String>]| format!(r#"{{"payload":"{}","signatures":[{}]}}"#, a[1],
+        parts.iter().map(|part| format!(r#"{{"protected":"{}","signature":"{}"}}"#, part[0], part[2])).collect::<Vec<_>>().join(","));
+    let mut validation = Validation::new();
+    validation.algorithms = vec![ALGORITHM::HS256];
+
+    let token = general(&[&a, &b, &forged]);
+    let report = verify_general(&token, &keys, &SignaturePolicy::AtLeast(2), &validation).unwrap();
+    assert_eq!(report.verified().unwrap().header().kid, Some("a".to_owned()));
+    assert_eq!(report.signatures().iter().map(|check| check.is_verified()).collect::<Vec<_>>(), vec![true, true, false]);
+    match report.signatures()[2].status {
+        SignatureStatus::Invalid(Error::JWSInvalidSignature) => {},
+        ref other => panic!("expected an invalid signature, got {:?}", other)
+    }
+    assert!(!verify_general(&token, &keys, &SignaturePolicy::AtLeast(3), &validation).unwrap().is_satisfied());
+    assert!(!verify_general(&token, &keys, &SignaturePolicy::AllValid, &validation).unwrap().is_satisfied());
+    assert!(!verify_general(&token, &keys, &SignaturePolicy::SpecificKid("c".to_owned()), &validation).unwrap().is_satisfied());
+    let b_only = verify_general(&token, &keys, &SignaturePolicy::SpecificKid("b".to_owned()), &validation).unwrap();
+    assert_eq!(b_only.into_verified().unwrap().header().kid, Some("b".to_owned()));
+
+    // A signature repeated under the same key counts once.
+    let repeated = general(&[&a, &a]);
+    let report = verify_general(&repeated, &keys, &SignaturePolicy::AtLeast(2), &validation).unwrap();
+    match report.signatures()[1].status {
+        SignatureStatus::Duplicate { of: 0 } => {},
+        ref other => panic!("expected a duplicate, got {:?}", other)
+    }
+    assert_err!(report.into_verified(), Error::SignaturePolicyNotMet { valid: 2, total: 2 });
+    assert!(verify_general(&repeated, &keys, &SignaturePolicy::AllValid, &validation).unwrap().is_satisfied());
+    assert!(verify_general(&a.join("."), &keys, &SignaturePolicy::AnyValid, &validation).is_err());
+}
+
+#[test]
+fn decode_jwt_rejects_expired_and_premature_tokens() {
+    let mut claims = Claims::new();
+    claims.exp = Some(2000);
+    claims.nbf = Some(1000);
+    let token = JWS::from_claims(Header::new(), claims).encode(::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap();
+    assert!(JWS::decode_jwt_at(token.clone(), ::TEST_HS256_SECRET, ALGORITHM::HS256, &Validation::new(), 1500).is_ok());
+    assert_err!(JWS::decode_jwt(token.clone(), ::TEST_HS256_SECRET, ALGORITHM::HS256), Error::TokenExpired { exp: 2000, .. });
+    assert_err!(JWS::decode_jwt_at(token.clone(), ::TEST_HS256_SECRET, ALGORITHM::HS256, &Validation::new(), 900), Error::TokenNotYetValid { nbf: 1000, now: 900 });
+    assert!(JWS::decode_jwt_with_validation(token, ::TEST_HS256_SECRET, ALGORITHM::HS256, &Validation::signature_only()).is_ok());
 }
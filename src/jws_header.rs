@@ -3,10 +3,14 @@
 extern crate serde;
 extern crate serde_json;
 
-use std::collections::BTreeMap;
 use self::serde::Serialize;
 use self::serde_json::{Value, to_value, from_value};
+use std::collections::BTreeMap;
 use std::result;
+use jws::base64_url_encode_bytes;
+use signing;
+use value_map::ValueMap;
+use typ::Typ;
 use error::Result;
 
 #[derive(Debug, PartialEq, Clone)]
@@ -17,27 +21,67 @@ pub enum ALGORITHM {
     RS256,
     RS384,
     RS512,
+    PS256,
+    PS384,
+    PS512,
+    EdDSA,
     ES256,
     ES384,
     ES512,
+    ES256K,
+    None,
+    Custom(String),
+}
+
+impl ALGORITHM {
+    pub fn name(&self) -> &str {
+        match *self {
+            ALGORITHM::HS256 => "HS256",
+            ALGORITHM::HS384 => "HS384",
+            ALGORITHM::HS512 => "HS512",
+            ALGORITHM::RS256 => "RS256",
+            ALGORITHM::RS384 => "RS384",
+            ALGORITHM::RS512 => "RS512",
+            ALGORITHM::PS256 => "PS256",
+            ALGORITHM::PS384 => "PS384",
+            ALGORITHM::PS512 => "PS512",
+            ALGORITHM::EdDSA => "EdDSA",
+            ALGORITHM::ES256 => "ES256",
+            ALGORITHM::ES384 => "ES384",
+            ALGORITHM::ES512 => "ES512",
+            ALGORITHM::ES256K => "ES256K",
+            ALGORITHM::None => "none",
+            ALGORITHM::Custom(ref name) => name.as_str()
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ALGORITHM> {
+        match name {
+            "HS256" => Some(ALGORITHM::HS256),
+            "HS384" => Some(ALGORITHM::HS384),
+            "HS512" => Some(ALGORITHM::HS512),
+            "RS256" => Some(ALGORITHM::RS256),
+            "RS384" => Some(ALGORITHM::RS384),
+            "RS512" => Some(ALGORITHM::RS512),
+            "PS256" => Some(ALGORITHM::PS256),
+            "PS384" => Some(ALGORITHM::PS384),
+            "PS512" => Some(ALGORITHM::PS512),
+            "EdDSA" => Some(ALGORITHM::EdDSA),
+            "ES256" => Some(ALGORITHM::ES256),
+            "ES384" => Some(ALGORITHM::ES384),
+            "ES512" => Some(ALGORITHM::ES512),
+            "ES256K" => Some(ALGORITHM::ES256K),
+            "none" => Some(ALGORITHM::None),
+            _ => None
+        }
+    }
 }
 
 impl Serialize for ALGORITHM {
     fn serialize<S>(&self, serializer: &mut S) -> result::Result<(), S::Error>
         where S: serde::Serializer
     {
-            let string_value = match self {
-                &ALGORITHM::HS256 => "HS256",
-                &ALGORITHM::HS384 => "HS384",
-                &ALGORITHM::HS512 => "HS512",
-                &ALGORITHM::RS256 => "RS256",
-                &ALGORITHM::RS384 => "RS384",
-                &ALGORITHM::RS512 => "RS512",
-                &ALGORITHM::ES256 => "ES256",
-                &ALGORITHM::ES384 => "ES384",
-                &ALGORITHM::ES512 => "ES512"
-            };
-            try!(serializer.serialize_str(string_value));
+            try!(serializer.serialize_str(self.name()));
             Ok(())
     }
 }
@@ -51,21 +95,12 @@ impl serde::Deserialize for ALGORITHM {
         impl serde::de::Visitor for AlgVisitor {
             type Value = ALGORITHM;
 
+            // Only registered names deserialize. A custom algorithm is read by
+            // parse_header, and only when its Validation lists it in custom_algorithms.
             fn visit_str<E>(&mut self, value: &str) -> result::Result<ALGORITHM, E>
                 where E: serde::de::Error
             {
-                match value {
-                    "HS256" => Ok(ALGORITHM::HS256),
-                    "HS384" => Ok(ALGORITHM::HS384),
-                    "HS512" => Ok(ALGORITHM::HS512),
-                    "RS256" => Ok(ALGORITHM::RS256),
-                    "RS384" => Ok(ALGORITHM::RS384),
-                    "RS512" => Ok(ALGORITHM::RS512),
-                    "ES256" => Ok(ALGORITHM::ES256),
-                    "ES384" => Ok(ALGORITHM::ES384),
-                    "ES512" => Ok(ALGORITHM::ES512),
-                    _ => Ok(ALGORITHM::HS256) //@TODO return an error
-                }
+                ALGORITHM::from_name(value).ok_or_else(|| E::invalid_value(&format!("unknown algorithm {}", value)))
             }
         }
         deserializer.deserialize_str(AlgVisitor)
@@ -80,10 +115,13 @@ pub struct Header {
     pub x5u: Option<String>,
     pub x5t: Option<String>,
     pub typ: Option<String>,
-    values: BTreeMap<String, Value>,
+    pub epk: Option<BTreeMap<String, Value>>,
+    pub apu: Option<Vec<u8>>,
+    pub apv: Option<Vec<u8>>,
+    values: ValueMap,
 }
 
-const RESERVED_HEADERS: [&'static str; 6] = ["typ", "alg", "jku", "kid", "x5u", "x5t"];
+const RESERVED_HEADERS: [&'static str; 9] = ["typ", "alg", "jku", "kid", "x5u", "x5t", "epk", "apu", "apv"];
 
 impl Serialize for Header {
     fn serialize<S>(&self, serializer: &mut S) -> result::Result<(), S::Error>
@@ -112,6 +150,18 @@ impl Serialize for Header {
             try!(serializer.serialize_map_key(&mut state, "x5t"));
             try!(serializer.serialize_map_value(&mut state, x5t.as_str()));
         }
+        if let Some(ref epk) = self.epk {
+            try!(serializer.serialize_map_key(&mut state, "epk"));
+            try!(serializer.serialize_map_value(&mut state, epk));
+        }
+        if let Some(ref apu) = self.apu {
+            try!(serializer.serialize_map_key(&mut state, "apu"));
+            try!(serializer.serialize_map_value(&mut state, base64_url_encode_bytes(apu.as_slice())));
+        }
+        if let Some(ref apv) = self.apv {
+            try!(serializer.serialize_map_key(&mut state, "apv"));
+            try!(serializer.serialize_map_value(&mut state, base64_url_encode_bytes(apv.as_slice())));
+        }
         for (key, value) in self.values
             .iter()
             .filter(|&(key, _)| !RESERVED_HEADERS.contains(&key.as_str())) {
@@ -122,7 +172,7 @@ impl Serialize for Header {
     }
 }
 
-enum HeaderField { TYP, ALG, JKU, KID, X5U, X5T, Custom(String) }
+enum HeaderField { TYP, ALG, JKU, KID, X5U, X5T, EPK, APU, APV, Custom(String) }
 
 impl serde::Deserialize for HeaderField {
     fn deserialize<D>(deserializer: &mut D) -> result::Result<HeaderField, D::Error>
@@ -143,6 +193,9 @@ impl serde::Deserialize for HeaderField {
                     "kid" => Ok(HeaderField::KID),
                     "x5u" => Ok(HeaderField::X5U),
                     "x5t" => Ok(HeaderField::X5T),
+                    "epk" => Ok(HeaderField::EPK),
+                    "apu" => Ok(HeaderField::APU),
+                    "apv" => Ok(HeaderField::APV),
                     _ => {
                         Ok(HeaderField::Custom(value.to_owned()))
                     }
@@ -154,6 +207,34 @@ impl serde::Deserialize for HeaderField {
     }
 }
 
+// Strict base64url, as for the token parts: no padding and no '+' or '/'.
+fn decode_party_info<E: serde::de::Error>(field: &str, value: String) -> result::Result<Vec<u8>, E> {
+    signing::base64url_decode_ct(&value).ok_or_else(|| E::custom(format!("{} is not base64url", field)))
+}
+
+fn check_epk<E: serde::de::Error>(epk: Value) -> result::Result<BTreeMap<String, Value>, E> {
+    match epk {
+        Value::Object(jwk) => {
+            match jwk.get("kty") {
+                Some(&Value::String(_)) => {},
+                _ => return Err(E::custom("epk is missing kty"))
+            }
+            if jwk.contains_key("d") {
+                return Err(E::custom("epk must not contain private key material"));
+            }
+            for coordinate in &["x", "y"] {
+                match jwk.get(*coordinate) {
+                    Some(&Value::String(ref value)) if signing::base64url_decode_ct(value).is_some() => {},
+                    None => {},
+                    _ => return Err(E::custom(format!("epk {} is not base64url", coordinate)))
+                }
+            }
+            Ok(jwk)
+        },
+        _ => Err(E::custom("epk is not a JWK object"))
+    }
+}
+
 struct HeaderVisitor;
 
 impl serde::de::Visitor for HeaderVisitor {
@@ -168,7 +249,10 @@ impl serde::de::Visitor for HeaderVisitor {
         let mut kid = None;
         let mut x5u = None;
         let mut x5t = None;
-        let mut values = BTreeMap::new();
+        let mut epk = None;
+        let mut apu = None;
+        let mut apv = None;
+        let mut values = ValueMap::with_insertion_order();
 
         while let Some(key) = try!(visitor.visit_key()) {
             match key {
@@ -178,6 +262,9 @@ impl serde::de::Visitor for HeaderVisitor {
                 HeaderField::KID => kid = Some(try!(visitor.visit_value())),
                 HeaderField::X5U => x5u = Some(try!(visitor.visit_value())),
                 HeaderField::X5T => x5t = Some(try!(visitor.visit_value())),
+                HeaderField::EPK => epk = Some(try!(check_epk(try!(visitor.visit_value())))),
+                HeaderField::APU => apu = Some(try!(decode_party_info("apu", try!(visitor.visit_value())))),
+                HeaderField::APV => apv = Some(try!(decode_party_info("apv", try!(visitor.visit_value())))),
                 HeaderField::Custom(k) => {
                     let value: Value = try!(visitor.visit_value());
                     values.insert(k, value);
@@ -201,6 +288,9 @@ impl serde::de::Visitor for HeaderVisitor {
             kid: kid,
             x5u: x5u,
             x5t: x5t,
+            epk: epk,
+            apu: apu,
+            apv: apv,
             values: values,
         })
     }
@@ -224,10 +314,32 @@ impl Header {
             kid: None,
             x5u: None,
             x5t: None,
-            values: BTreeMap::new(),
+            epk: None,
+            apu: None,
+            apv: None,
+            values: ValueMap::new(),
+        }
+    }
+
+    pub fn with_insertion_order() -> Header {
+        Header {
+            alg: ALGORITHM::HS256,
+            typ: None,
+            jku: None,
+            kid: None,
+            x5u: None,
+            x5t: None,
+            epk: None,
+            apu: None,
+            apv: None,
+            values: ValueMap::with_insertion_order(),
         }
     }
 
+    pub fn typ_matches(&self, typ: &Typ) -> bool {
+        self.typ.as_ref().map_or(false, |value| typ.matches(value))
+    }
+
     pub fn set<T: Serialize>(&mut self, key: &str, value: T) {
         if !RESERVED_HEADERS.contains(&key) {
             self.values.insert(key.to_owned(), to_value(&value));
@@ -238,6 +350,51 @@ impl Header {
        self.values.get(key).and_then(|v| from_value(v.clone()).ok())
     }
 
+    pub fn extract<T: serde::de::Deserialize>(&self) -> Result<T> {
+        Ok(try!(from_value(Value::Object(self.values.to_object()))))
+    }
+
+    pub fn inject<T: Serialize>(&mut self, value: &T) -> Result<()> {
+        match to_value(value) {
+            Value::Object(values) => {
+                for (key, value) in values.into_iter()
+                    .filter(|&(ref key, _)| !RESERVED_HEADERS.contains(&key.as_str())) {
+                    self.values.insert(key, value);
+                }
+                Ok(())
+            },
+            _ => {
+                let err: serde_json::Error = serde::de::Error::invalid_type(serde::de::Type::Map);
+                Err(err.into())
+            }
+        }
+    }
+
+    // Registered parameters come first, in the order they are serialized,
+    // followed by custom ones.
+    pub fn iter_all(&self) -> ::std::vec::IntoIter<(String, Value)> {
+        let mut all = vec![("alg".to_owned(), to_value(&self.alg))];
+        {
+            let mut push = |name: &str, value: Option<Value>| {
+                if let Some(value) = value {
+                    all.push((name.to_owned(), value));
+                }
+            };
+            push("typ", self.typ.as_ref().map(to_value));
+            push("jku", self.jku.as_ref().map(to_value));
+            push("kid", self.kid.as_ref().map(to_value));
+            push("x5u", self.x5u.as_ref().map(to_value));
+            push("x5t", self.x5t.as_ref().map(to_value));
+            push("epk", self.epk.as_ref().map(to_value));
+            push("apu", self.apu.as_ref().map(|apu| Value::String(base64_url_encode_bytes(apu))));
+            push("apv", self.apv.as_ref().map(|apv| Value::String(base64_url_encode_bytes(apv))));
+        }
+        all.extend(self.values.iter()
+            .filter(|&(key, _)| !RESERVED_HEADERS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone())));
+        all.into_iter()
+    }
+
     pub fn to_json(&self) -> Result<String> {
         Ok(try!(serde_json::to_string(self)))
     }
@@ -268,6 +425,38 @@ fn retrieving_a_custom_header_that_is_not_set_returns_none() {
     assert!(result.is_none());
 }
 
+#[test]
+fn custom_headers_can_be_extracted_and_injected_in_bulk() {
+    let mut h = Header::new();
+    let mut ext = ::std::collections::BTreeMap::new();
+    ext.insert("url".to_owned(), "https://acme.example.com/new-order".to_owned());
+    ext.insert("nonce".to_owned(), "6S8IqOGY7eL2lsGoTZYifg".to_owned());
+    ext.insert("kid".to_owned(), "ignored".to_owned());
+    h.inject(&ext).unwrap();
+
+    assert!(h.kid.is_none());
+    let extracted: ::std::collections::BTreeMap<String, String> = h.extract().unwrap();
+    assert_eq!(extracted.len(), 2);
+    assert_eq!(extracted.get("nonce").unwrap(), "6S8IqOGY7eL2lsGoTZYifg");
+}
+
+#[test]
+fn decoded_custom_headers_keep_their_original_order() {
+    let decoded: Header = serde_json::from_str(r#"{"alg":"HS256","nonce":"n","url":"u"}"#).unwrap();
+    assert_eq!(decoded.to_json().unwrap(), r#"{"alg":"HS256","nonce":"n","url":"u"}"#);
+    let decoded: Header = serde_json::from_str(r#"{"alg":"HS256","url":"u","nonce":"n"}"#).unwrap();
+    assert_eq!(decoded.to_json().unwrap(), r#"{"alg":"HS256","url":"u","nonce":"n"}"#);
+}
+
+#[test]
+fn header_typ_is_compared_as_a_media_type() {
+    let mut h = Header::new();
+    assert!(!h.typ_matches(&Typ::JWT));
+    h.typ = Some("application/AT+JWT".to_owned());
+    assert!(h.typ_matches(&Typ::AT_JWT));
+    assert!(!h.typ_matches(&Typ::JWT));
+}
+
 #[test]
 fn headers_can_be_serialized_to_and_from_json_preserving_all_fields() {
     let mut h = Header::new();
@@ -296,3 +485,74 @@ fn headers_can_be_serialized_to_and_from_json_preserving_all_fields() {
     assert_eq!(new_h.x5u.unwrap(), h.x5u.unwrap());
     assert_eq!(new_h.x5t.unwrap(), h.x5t.unwrap());
 }
+
+#[test]
+fn key_agreement_parameters_are_typed_and_base64url_encoded() {
+    let mut h = Header::new();
+    h.apu = Some(b"Alice".to_vec());
+    h.apv = Some(b"Bob".to_vec());
+    let mut epk = ::std::collections::BTreeMap::new();
+    epk.insert("kty".to_owned(), Value::String("EC".to_owned()));
+    epk.insert("crv".to_owned(), Value::String("P-256".to_owned()));
+    h.epk = Some(epk);
+    let json = serde_json::to_string(&h).unwrap();
+    assert!(json.contains(r#""apu":"QWxpY2U""#));
+    assert!(json.contains(r#""apv":"Qm9i""#));
+    let new_h: Header = serde_json::from_str(&json).unwrap();
+    assert_eq!(new_h, h);
+}
+
+#[test]
+fn invalid_key_agreement_parameters_are_rejected() {
+    assert!(serde_json::from_str::<Header>(r#"{"alg":"HS256","apu":"!!"}"#).is_err());
+    assert!(serde_json::from_str::<Header>(r#"{"alg":"HS256","epk":"EC"}"#).is_err());
+    assert!(serde_json::from_str::<Header>(r#"{"alg":"HS256","epk":{"crv":"P-256"}}"#).is_err());
+    assert!(serde_json::from_str::<Header>(r#"{"alg":"HS256","epk":{"kty":"EC","d":"c2VjcmV0"}}"#).is_err());
+}
+
+#[test]
+fn key_agreement_parameters_must_be_strict_base64url() {
+    for encoded in &["QWxp+2U", "QWxp/2U", "QWxpY2U="] {
+        assert!(serde_json::from_str::<Header>(&format!(r#"{{"alg":"HS256","apu":"{}"}}"#, encoded)).is_err());
+        assert!(serde_json::from_str::<Header>(&format!(r#"{{"alg":"HS256","apv":"{}"}}"#, encoded)).is_err());
+        assert!(serde_json::from_str::<Header>(&format!(r#"{{"alg":"HS256","epk":{{"kty":"OKP","x":"{}"}}}}"#, encoded)).is_err());
+    }
+    let header: Header = serde_json::from_str(r#"{"alg":"HS256","apu":"QWxp-2U","epk":{"kty":"OKP","x":"QWxp_2U"}}"#).unwrap();
+    assert_eq!(header.apu, Some(vec![0x41, 0x6c, 0x69, 0xfb, 0x65]));
+}
+
+#[test]
+fn unknown_algorithms_are_rejected_unless_allowed() {
+    use jws::JWS;
+    use validation::Validation;
+    use error::Error;
+
+    let token = |alg: &str| format!("{}.e30.", base64_url_encode_bytes(format!(r#"{{"alg":"{}"}}"#, alg).as_bytes()));
+    for alg in &["HS257", "hs256", "X-VENDOR-MAC"] {
+        assert_err!(JWS::decode_header(&token(alg)), Error::UnsupportedAlgorithm(name) => assert_eq!(name, *alg));
+    }
+    assert_eq!(ALGORITHM::from_name("X-VENDOR-MAC"), None);
+
+    let validation = Validation::new().allow_custom_algorithm("X-VENDOR-MAC");
+    let header = JWS::decode_header_with_validation(&token("X-VENDOR-MAC"), &validation).unwrap();
+    assert_eq!(header.alg, ALGORITHM::Custom("X-VENDOR-MAC".to_owned()));
+    assert_eq!(header.to_json().unwrap(), r#"{"alg":"X-VENDOR-MAC"}"#);
+    assert!(JWS::decode_header_with_validation(&token("HS257"), &validation).is_err());
+    assert!(JWS::decode_header(&token("X-VENDOR-MAC")).is_err());
+    assert!(serde_json::from_str::<Header>(r#"{"alg":"X-VENDOR-MAC"}"#).is_err());
+    assert!(serde_json::from_str::<ALGORITHM>(r#""HS257""#).is_err());
+}
+
+#[test]
+fn iter_all_walks_registered_and_custom_parameters() {
+    let mut h = Header::with_insertion_order();
+    h.kid = Some("key-1".to_owned());
+    h.apu = Some(b"Alice".to_vec());
+    h.set("crit_ext", true);
+    let all: Vec<(String, Value)> = h.iter_all().collect();
+    let names: Vec<&str> = all.iter().map(|&(ref name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["alg", "kid", "apu", "crit_ext"]);
+    assert_eq!(all[0].1, Value::String("HS256".to_owned()));
+    assert_eq!(all[2].1, Value::String("QWxpY2U".to_owned()));
+    assert_eq!(all[3].1, Value::Bool(true));
+}
@@ -1,3 +1,5 @@
+#![recursion_limit = "256"]
+
 #[macro_use] extern crate quick_error;
 
 extern crate serde;
@@ -5,9 +7,118 @@ extern crate serde_json;
 extern crate rustc_serialize;
 extern crate openssl;
 extern crate rand;
+extern crate time;
+extern crate smallvec;
+#[cfg(feature = "jwks-client")]
+extern crate ureq;
+#[cfg(feature = "jwks-client")]
+extern crate native_tls;
+#[cfg(feature = "jwks-client")]
+extern crate arc_swap;
+#[cfg(feature = "jwks-async")]
+extern crate futures_io;
+#[cfg(any(test, feature = "proptest"))]
+#[macro_use]
+extern crate proptest;
+
+#[cfg(test)]
+const TEST_HS256_SECRET: &'static [u8] = b"a 32-byte secret for HS256 tests";
+
+// Matches `result` against the expected errors, panicking with what was found
+// instead. The optional body runs on a match, to check the error's fields.
+#[cfg(test)]
+macro_rules! assert_err {
+    ($result:expr, $($pattern:pat)|+ => $body:expr) => {
+        match $result {
+            $(Err($pattern))|+ => $body,
+            other => panic!("expected {}, got {:?}", stringify!($($pattern)|+), other)
+        }
+    };
+    ($result:expr, $($pattern:pat)|+) => {
+        assert_err!($result, $($pattern)|+ => {})
+    };
+}
 
 mod jws_header;
 mod claims;
 mod jws;
 mod signing;
 mod error;
+mod pairwise;
+mod value_map;
+mod unsigned;
+mod typ;
+mod profile;
+mod clock;
+mod entropy;
+mod limits;
+mod trace;
+mod webhooks;
+mod magic_link;
+mod replay;
+mod secret_token;
+mod header_template;
+pub mod compat;
+mod transcript;
+mod nonce;
+mod ceremony;
+mod sealed_claims;
+mod validation;
+mod jwk;
+mod jwk_set;
+mod issuer_registry;
+mod id_token;
+mod token_cache;
+mod provenance;
+mod digest;
+mod simple;
+mod license;
+mod security_report;
+#[cfg(feature = "jwks-client")]
+mod jwks_client;
+#[cfg(feature = "jwks-client")]
+mod oidc;
+#[cfg(any(test, feature = "proptest"))]
+pub mod arbitrary;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub use jws::{JWS, ParsedToken, VerifiedJwt, VerifiedJws, TokenFormat, detect_format, decode_any};
+pub use jws::{GeneralVerification, SignatureCheck, SignaturePolicy, SignatureStatus, verify_general, verify_general_at};
+pub use jws_header::{Header, ALGORITHM};
+pub use typ::Typ;
+pub use claims::{Audience, Claims, ClaimError, ClaimsWarning, LintOptions};
+pub use jwk::{Jwk, JwkParams, RsaPrivateParams};
+pub use jwk_set::{JwkSet, SkippedKey};
+#[cfg(feature = "jwks-async")]
+pub use jwk_set::ReadJwkSet;
+#[cfg(feature = "jwks-client")]
+pub use jwks_client::{CachedJwks, JwksClient, JwksRegistry, JwksSource, JwksStats, KeyFreshness, RefreshFailure};
+#[cfg(feature = "jwks-client")]
+pub use oidc::{OidcVerifier, ProviderMetadata};
+pub use replay::{ReplayStore, MemoryReplayStore};
+pub use clock::{Clock, SystemClock, FixedClock, OffsetClock};
+pub use limits::{Base64Mode, Limits};
+pub use validation::{ClaimValidator, Validation, ValidationError};
+pub use security_report::{Finding, SecurityReport};
+pub use issuer_registry::{IssuerPolicy, IssuerRegistry};
+pub use id_token::{AuthResponse, IdToken, IdTokenValidator, at_hash, c_hash};
+pub use token_cache::{CacheStats, VerifiedTokenCache};
+pub use provenance::{ClaimsMerge, Provenance};
+pub use digest::{Digestable, DigestAlgorithm, SigD, Streamed};
+pub use nonce::NonceChallenge;
+pub use entropy::{Entropy, SystemEntropy, SeededEntropy};
+pub use transcript::{VerificationTranscript, decode_jwt_with_transcript};
+pub use ceremony::{fingerprint, export_public_pem, keys_correspond, key_announcement};
+pub use sealed_claims::SealedClaims;
+pub use simple::{sign, verify, EncodingKey, DecodingKey, KeyFamily, HmacSecret, RsaKey, EcKey, EdKey};
+pub use license::{License, LicenseKeys, LicenseOptions, LicenseStatus, issue_license};
+pub use webhooks::{WebhookVerifier, sign_webhook};
+pub use magic_link::MagicLink;
+pub use secret_token::SecretToken;
+pub use unsigned::UnsignedJws;
+pub use profile::Profile;
+pub use trace::TraceContext;
+pub use pairwise::{pairwise_sub, sector_identifier};
+pub use header_template::{HeaderWarning, header_for_key, check_header};
+pub use error::{Error, RejectReason, Result};
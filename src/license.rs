@@ -0,0 +1,280 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use openssl::pkey::{Id, PKey};
+use rustc_serialize::base64::FromBase64;
+use claims::Claims;
+use clock::{Clock, SystemClock};
+use jws::{JWS, ParsedToken, base64_url_encode_bytes};
+use jws_header::{Header, ALGORITHM};
+use profile::Profile;
+use signing;
+use simple::{DecodingKey, EdKey, EncodingKey, KeyFamily};
+use typ::Typ;
+use validation::Validation;
+use error::{Error, Result};
+
+const ED25519_PUBLIC_KEY_LEN: usize = 32;
+
+/// Signs an entitlement token for `claims` with an Ed25519 key. The header
+/// names the key's `kid`, and iat and jti are filled in if missing.
+pub fn issue_license(claims: Claims, key: &EdKey, kid: &str) -> Result<String> {
+    let mut header = Header::new();
+    header.alg = ALGORITHM::EdDSA;
+    header.kid = Some(kid.to_owned());
+    let jws = try!(JWS::from_profile(Profile::License, header, claims));
+    jws.encode_with_key(&try!(EncodingKey::from_key(key, ALGORITHM::EdDSA)))
+}
+
+fn invalid_bundle(reason: String) -> Error {
+    Error::InvalidKeyBundle(reason)
+}
+
+// The Ed25519 public keys a shipped binary verifies licenses with. The bundle
+// is plain text, one "<kid> <base64url public key>" line per key, so it can be
+// embedded with include_str!. Blank lines and lines starting with # are skipped.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LicenseKeys {
+    keys: BTreeMap<String, DecodingKey>,
+}
+
+impl LicenseKeys {
+    pub fn new() -> LicenseKeys {
+        LicenseKeys::default()
+    }
+
+    pub fn from_bundle(bundle: &str) -> Result<LicenseKeys> {
+        let mut keys = LicenseKeys::new();
+        for (number, line) in bundle.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 2 {
+                return Err(invalid_bundle(format!("line {} is not \"<kid> <key>\"", number + 1)));
+            }
+            let raw = try!(fields[1].from_base64().map_err(|_| invalid_bundle(format!("line {} is not base64url", number + 1))));
+            if raw.len() != ED25519_PUBLIC_KEY_LEN {
+                return Err(invalid_bundle(format!("line {} is not a 32-byte Ed25519 key", number + 1)));
+            }
+            let public = try!(PKey::public_key_from_raw_bytes(&raw, Id::ED25519));
+            try!(keys.insert(fields[0], try!(public.public_key_to_der())));
+        }
+        Ok(keys)
+    }
+
+    // Only the public half of `key` is kept, so the bundle never carries a signing key.
+    // Raw bytes are read as the signing seed, as when signing.
+    pub fn add(&mut self, kid: &str, key: &EdKey) -> Result<()> {
+        let public_der = match signing::eddsa_key(key.as_bytes()) {
+            Ok(private) => try!(private.public_key_to_der()),
+            Err(_) => try!(try!(signing::public_key(key.as_bytes()).map_err(|err| Error::public_key_parse(key.as_bytes(), err))).public_key_to_der())
+        };
+        let public = try!(PKey::public_key_from_der(&public_der));
+        if public.id() != Id::ED25519 {
+            return Err(Error::KeyAlgorithmMismatch { alg: ALGORITHM::EdDSA });
+        }
+        self.insert(kid, public_der)
+    }
+
+    fn insert(&mut self, kid: &str, public_der: Vec<u8>) -> Result<()> {
+        if kid.is_empty() || kid.contains(char::is_whitespace) || kid.starts_with('#') {
+            return Err(invalid_bundle(format!("{:?} is not a usable kid", kid)));
+        }
+        if self.keys.contains_key(kid) {
+            return Err(invalid_bundle(format!("{} is listed twice", kid)));
+        }
+        let key = try!(DecodingKey::from_key(&try!(EdKey::new(&public_der)), ALGORITHM::EdDSA));
+        self.keys.insert(kid.to_owned(), key);
+        Ok(())
+    }
+
+    pub fn to_bundle(&self) -> Result<String> {
+        let mut bundle = String::new();
+        for (kid, key) in self.keys.iter() {
+            let raw = match key.public_key() {
+                Some(public) => try!(public.raw_public_key()),
+                None => return Err(invalid_bundle(format!("{} has no public key", kid)))
+            };
+            bundle.push_str(&format!("{} {}\n", kid, base64_url_encode_bytes(&raw)));
+        }
+        Ok(bundle)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn verify(&self, token: &str, options: &LicenseOptions) -> Result<License> {
+        self.verify_at(token, options, SystemClock.now())
+    }
+
+    pub fn verify_at(&self, token: &str, options: &LicenseOptions, now: u64) -> Result<License> {
+        let validation = options.validation();
+        let key = {
+            let parsed = try!(ParsedToken::parse_with_validation(token, &validation));
+            let kid = parsed.header().kid.clone();
+            match kid.as_ref().and_then(|kid| self.keys.get(kid)) {
+                Some(key) => key,
+                None => return Err(Error::UnknownKey { kid: kid })
+            }
+        };
+        let (_, claims) = try!(validation.decode_with_key_at(token, key, now));
+        let status = match claims.exp {
+            None => LicenseStatus::Perpetual,
+            Some(exp) if now < exp.saturating_add(options.leeway) => LicenseStatus::Active { expires: exp },
+            Some(exp) => {
+                let ends = exp.saturating_add(options.leeway).saturating_add(options.grace_period);
+                if now >= ends {
+                    return Err(Error::TokenExpired { exp: exp, now: now });
+                }
+                LicenseStatus::Grace { expired: exp, ends: ends }
+            }
+        };
+        Ok(License { claims: claims, status: status })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LicenseOptions {
+    pub issuer: Option<String>,
+    // Checked against the aud claim.
+    pub product: Option<String>,
+    // Seconds after exp during which a license still verifies, reported as LicenseStatus::Grace.
+    pub grace_period: u64,
+    pub leeway: u64,
+}
+
+impl LicenseOptions {
+    pub fn new() -> LicenseOptions {
+        LicenseOptions {
+            issuer: None,
+            product: None,
+            grace_period: 0,
+            leeway: 60,
+        }
+    }
+
+    pub fn with_grace_period(mut self, seconds: u64) -> LicenseOptions {
+        self.grace_period = seconds;
+        self
+    }
+
+    // exp is checked by verify_at, which applies the grace period.
+    fn validation(&self) -> Validation {
+        let mut validation = Validation::new();
+        validation.algorithms = vec![ALGORITHM::EdDSA];
+        validation.require_algorithms = true;
+        validation.typ = Some(Typ::LICENSE_JWT);
+        validation.require_typ = true;
+        validation.reject_embedded_keys = true;
+        validation.leeway = self.leeway;
+        validation.validate_exp = false;
+        validation.issuer = self.issuer.clone();
+        validation.audience = self.product.clone();
+        validation.required_claims = Profile::License.required_claims().iter().map(|name| name.to_string()).collect();
+        validation
+    }
+}
+
+impl Default for LicenseOptions {
+    fn default() -> LicenseOptions {
+        LicenseOptions::new()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LicenseStatus {
+    Perpetual,
+    Active { expires: u64 },
+    // Past exp but inside the grace period; the license stops verifying at `ends`.
+    Grace { expired: u64, ends: u64 },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct License {
+    pub claims: Claims,
+    pub status: LicenseStatus,
+}
+
+impl License {
+    // The features the license grants, from its entitlements claim.
+    pub fn entitlements(&self) -> Vec<String> {
+        self.claims.get("entitlements").unwrap_or_default()
+    }
+
+    pub fn is_entitled(&self, feature: &str) -> bool {
+        self.entitlements().iter().any(|granted| granted == feature)
+    }
+}
+
+#[cfg(test)]
+fn license_claims(exp: Option<u64>) -> Claims {
+    let mut claims = Claims::new();
+    claims.iss = Some("https://licensing.example".to_owned());
+    claims.sub = Some("customer-42".to_owned());
+    claims.aud = Some("editor".into());
+    claims.iat = Some(1000);
+    claims.exp = exp;
+    claims.set("entitlements", vec!["export", "plugins"]);
+    claims
+}
+
+#[test]
+fn licenses_verify_offline_against_a_bundle() {
+    let signing = EdKey::new(&PKey::generate_ed25519().unwrap().private_key_to_pem_pkcs8().unwrap()).unwrap();
+    let mut keys = LicenseKeys::new();
+    keys.add("2026-01", &signing).unwrap();
+    let bundle = keys.to_bundle().unwrap();
+    assert!(!bundle.contains("PRIVATE"));
+    let embedded = LicenseKeys::from_bundle(&format!("# shipped with 4.2\n\n{}", bundle)).unwrap();
+    assert_eq!(embedded, keys);
+
+    let mut options = LicenseOptions::new();
+    options.issuer = Some("https://licensing.example".to_owned());
+    options.product = Some("editor".to_owned());
+    let token = issue_license(license_claims(None), &signing, "2026-01").unwrap();
+    let license = embedded.verify_at(&token, &options, 1_000_000_000).unwrap();
+    assert_eq!(license.status, LicenseStatus::Perpetual);
+    assert!(license.is_entitled("export"));
+    assert!(!license.is_entitled("cloud-sync"));
+
+    options.product = Some("viewer".to_owned());
+    assert!(embedded.verify_at(&token, &options, 2000).is_err());
+
+    let other = EdKey::new(&PKey::generate_ed25519().unwrap().private_key_to_pem_pkcs8().unwrap()).unwrap();
+    let forged = issue_license(license_claims(None), &other, "2026-01").unwrap();
+    assert!(embedded.verify_at(&forged, &LicenseOptions::new(), 2000).is_err());
+    assert_err!(embedded.verify_at(&issue_license(license_claims(None), &other, "rogue").unwrap(), &LicenseOptions::new(), 2000), Error::UnknownKey { kid } => assert_eq!(kid, Some("rogue".to_owned())));
+}
+
+#[test]
+fn expired_licenses_verify_during_the_grace_period() {
+    let signing = EdKey::new(&PKey::generate_ed25519().unwrap().private_key_to_pem_pkcs8().unwrap()).unwrap();
+    let mut keys = LicenseKeys::new();
+    keys.add("k", &signing).unwrap();
+    let token = issue_license(license_claims(Some(10_000)), &signing, "k").unwrap();
+    let options = LicenseOptions::new().with_grace_period(7 * 86400);
+
+    assert_eq!(keys.verify_at(&token, &options, 5000).unwrap().status, LicenseStatus::Active { expires: 10_000 });
+    assert_eq!(keys.verify_at(&token, &options, 20_000).unwrap().status, LicenseStatus::Grace { expired: 10_000, ends: 10_060 + 7 * 86400 });
+    assert_err!(keys.verify_at(&token, &options, 10_060 + 7 * 86400), Error::TokenExpired { exp: 10_000, .. });
+    assert_err!(keys.verify_at(&token, &LicenseOptions::new(), 20_000), Error::TokenExpired { .. });
+}
+
+#[test]
+fn malformed_bundles_are_rejected() {
+    for bundle in &["k", "k not-base64!", "k AAAA", "k a b"] {
+        match LicenseKeys::from_bundle(bundle) {
+            Err(Error::InvalidKeyBundle(_)) => {},
+            other => panic!("expected InvalidKeyBundle for {:?}, got {:?}", bundle, other)
+        }
+    }
+    let ed448 = EdKey::new(&PKey::generate_ed448().unwrap().private_key_to_pem_pkcs8().unwrap()).unwrap();
+    assert!(LicenseKeys::new().add("k", &ed448).is_err());
+}
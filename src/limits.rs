@@ -0,0 +1,100 @@
+#![allow(dead_code)]
+
+use error::{Error, Result};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Base64Mode {
+    // Unpadded base64url only, with no line breaks or stray trailing bits, so
+    // every segment has exactly one accepted spelling.
+    Strict,
+    // Also padding, the standard alphabet and line breaks. Only for peers that
+    // cannot be fixed to emit RFC 7515 base64url.
+    Lenient
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Limits {
+    pub max_token_size: usize,
+    pub max_json_depth: usize,
+    pub max_payload_size: usize,
+    pub base64: Base64Mode,
+    // A JWK set, whether parsed from a string, a reader or a fetched response.
+    pub max_jwks_size: usize,
+    // A single key in a JWK set read from a stream; larger keys are skipped
+    // without being buffered.
+    pub max_jwk_size: usize,
+    // Certificates in a key's x5c chain; keys with longer chains are skipped.
+    pub max_x5c_chain: usize,
+    // What a compressed response body may expand to once decoded.
+    pub max_decompressed_size: usize,
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Limits {
+            max_token_size: 256 * 1024,
+            max_json_depth: 32,
+            max_payload_size: 64 * 1024,
+            base64: Base64Mode::Strict,
+            max_jwks_size: 16 * 1024 * 1024,
+            max_jwk_size: 64 * 1024,
+            max_x5c_chain: 10,
+            max_decompressed_size: 16 * 1024 * 1024,
+        }
+    }
+
+    pub fn check_token(&self, token: &str) -> Result<()> {
+        if token.len() > self.max_token_size {
+            return Err(Error::TokenTooLarge { size: token.len(), limit: self.max_token_size });
+        }
+        Ok(())
+    }
+
+    pub fn check_json(&self, json: &[u8]) -> Result<()> {
+        if json.len() > self.max_payload_size {
+            return Err(Error::PayloadTooLarge { size: json.len(), limit: self.max_payload_size });
+        }
+        let mut depth = 0;
+        let mut in_string = false;
+        let mut escaped = false;
+        for &byte in json {
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if byte == b'\\' {
+                    escaped = true;
+                } else if byte == b'"' {
+                    in_string = false;
+                }
+                continue;
+            }
+            match byte {
+                b'"' => in_string = true,
+                b'{' | b'[' => {
+                    depth += 1;
+                    if depth > self.max_json_depth {
+                        return Err(Error::JsonTooDeep { limit: self.max_json_depth });
+                    }
+                },
+                b'}' | b']' => depth = if depth > 0 { depth - 1 } else { 0 },
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn json_within_limits_passes_the_check() {
+    let limits = Limits::new();
+    assert!(limits.check_json(br#"{"a":[{"b":"[[[{{{"}]}"#).is_ok());
+    assert!(limits.check_token("a.b.c").is_ok());
+}
+
+#[test]
+fn deeply_nested_or_oversized_input_is_rejected() {
+    let limits = Limits { max_token_size: 8, max_json_depth: 3, max_payload_size: 32, ..Limits::new() };
+    assert_err!(limits.check_json(br#"{"a":{"b":{"c":{}}}}"#), Error::JsonTooDeep { limit: 3 });
+    assert_err!(limits.check_json(&[b' '; 33]), Error::PayloadTooLarge { size: 33, limit: 32 });
+    assert_err!(limits.check_token("aaaa.bbbb.cccc"), Error::TokenTooLarge { size: 14, limit: 8 });
+}
@@ -0,0 +1,105 @@
+#![allow(dead_code)]
+
+use claims::Claims;
+use clock::{Clock, SystemClock};
+use entropy::{Entropy, SystemEntropy};
+use jws::{JWS, base64_url_encode_bytes};
+use jws_header::{Header, ALGORITHM};
+use replay::ReplayStore;
+use validation::Validation;
+use error::{Error, Result};
+use secret_token::SecretToken;
+
+const PURPOSE_CLAIM: &'static str = "purpose";
+
+pub struct MagicLink<C = SystemClock, E = SystemEntropy> {
+    pub lifetime: u64,
+    secret: Vec<u8>,
+    clock: C,
+    entropy: E,
+}
+
+impl MagicLink {
+    pub fn new(secret: &[u8]) -> MagicLink {
+        MagicLink::with_sources(secret, SystemClock, SystemEntropy)
+    }
+}
+
+impl<C: Clock, E: Entropy> MagicLink<C, E> {
+    pub fn with_sources(secret: &[u8], clock: C, entropy: E) -> MagicLink<C, E> {
+        MagicLink {
+            lifetime: 900,
+            secret: secret.to_vec(),
+            clock: clock,
+            entropy: entropy,
+        }
+    }
+
+    pub fn mint(&self, purpose: &str, endpoint: &str, subject: &str) -> Result<SecretToken> {
+        let now = self.clock.now();
+        let mut claims = Claims::new();
+        claims.sub = Some(subject.to_owned());
+        claims.aud = Some(endpoint.into());
+        claims.iat = Some(now);
+        claims.exp = Some(now + self.lifetime);
+        claims.jti = Some(base64_url_encode_bytes(self.entropy.random_bytes(16).as_slice()));
+        claims.set(PURPOSE_CLAIM, purpose);
+        let mut header = Header::new();
+        header.alg = ALGORITHM::HS256;
+        JWS::from_claims(header, claims).encode(self.secret.as_slice(), ALGORITHM::HS256).map(SecretToken::new)
+    }
+
+    pub fn verify<R: ReplayStore>(&self, token: &str, purpose: &str, endpoint: &str, store: &mut R) -> Result<Claims> {
+        let (_, claims) = try!(JWS::decode_jwt_with_validation(token.to_owned(), self.secret.as_slice(), ALGORITHM::HS256, &Validation::signature_only()));
+        let (exp, jti) = match (claims.exp, claims.jti.clone(), claims.has(PURPOSE_CLAIM)) {
+            (Some(exp), Some(jti), true) => (exp, jti),
+            _ => return Err(Error::MissingClaims(vec!["exp".to_owned(), "jti".to_owned(), PURPOSE_CLAIM.to_owned()]))
+        };
+        if claims.get::<String>(PURPOSE_CLAIM).as_ref().map(String::as_str) != Some(purpose) {
+            return Err(Error::ClaimMismatch { claim: PURPOSE_CLAIM });
+        }
+        if claims.aud != Some(endpoint.into()) {
+            return Err(Error::ClaimMismatch { claim: "aud" });
+        }
+        let now = self.clock.now();
+        if exp <= now {
+            return Err(Error::TokenExpired { exp: exp, now: now });
+        }
+        if !store.check_and_record_at(&jti, exp, now) {
+            return Err(Error::TokenReplayed);
+        }
+        Ok(claims)
+    }
+}
+
+#[test]
+fn magic_links_verify_once() {
+    use clock::FixedClock;
+    use entropy::SeededEntropy;
+    use replay::MemoryReplayStore;
+
+    let link = MagicLink::with_sources(::TEST_HS256_SECRET, FixedClock(1000), SeededEntropy::new(b"seed"));
+    let token = link.mint("verify-email", "https://example.com/verify", "user-1").unwrap();
+    let mut store = MemoryReplayStore::new();
+
+    let claims = link.verify(token.expose(), "verify-email", "https://example.com/verify", &mut store).unwrap();
+    assert_eq!(claims.sub, Some("user-1".to_owned()));
+    assert_err!(link.verify(token.expose(), "verify-email", "https://example.com/verify", &mut store), Error::TokenReplayed);
+}
+
+#[test]
+fn magic_links_are_bound_to_purpose_endpoint_and_lifetime() {
+    use clock::FixedClock;
+    use entropy::SeededEntropy;
+    use replay::MemoryReplayStore;
+
+    let link = MagicLink::with_sources(::TEST_HS256_SECRET, FixedClock(1000), SeededEntropy::new(b"seed"));
+    let token = link.mint("verify-email", "https://example.com/verify", "user-1").unwrap();
+    let mut store = MemoryReplayStore::new();
+
+    assert!(link.verify(token.expose(), "reset-password", "https://example.com/verify", &mut store).is_err());
+    assert!(link.verify(token.expose(), "verify-email", "https://example.com/reset", &mut store).is_err());
+
+    let later = MagicLink::with_sources(::TEST_HS256_SECRET, FixedClock(1000 + 900), SeededEntropy::new(b"seed"));
+    assert_err!(later.verify(token.expose(), "verify-email", "https://example.com/verify", &mut store), Error::TokenExpired { exp: 1900, now: 1900 });
+}
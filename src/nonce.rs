@@ -0,0 +1,122 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use claims::Claims;
+use clock::{Clock, SystemClock};
+use entropy::{Entropy, SystemEntropy};
+use jws::{JWS, base64_url_encode_bytes};
+use jws_header::{Header, ALGORITHM};
+use validation::Validation;
+use error::{Error, Result};
+
+const NONCE_CLAIM: &'static str = "nonce";
+
+pub struct NonceChallenge<C = SystemClock, E = SystemEntropy> {
+    pub lifetime: u64,
+    issued: HashMap<String, u64>,
+    clock: C,
+    entropy: E,
+}
+
+impl NonceChallenge {
+    pub fn new() -> NonceChallenge {
+        NonceChallenge::with_sources(SystemClock, SystemEntropy)
+    }
+}
+
+impl<C: Clock, E: Entropy> NonceChallenge<C, E> {
+    pub fn with_sources(clock: C, entropy: E) -> NonceChallenge<C, E> {
+        NonceChallenge {
+            lifetime: 300,
+            issued: HashMap::new(),
+            clock: clock,
+            entropy: entropy,
+        }
+    }
+
+    pub fn issue(&mut self) -> String {
+        let now = self.clock.now();
+        self.issued.retain(|_, exp| *exp > now);
+        let nonce = base64_url_encode_bytes(self.entropy.random_bytes(16).as_slice());
+        self.issued.insert(nonce.clone(), now + self.lifetime);
+        nonce
+    }
+
+    pub fn consume(&mut self, nonce: &str) -> Result<()> {
+        let exp = match self.issued.remove(nonce) {
+            Some(exp) => exp,
+            None => return Err(Error::UnknownNonce)
+        };
+        let now = self.clock.now();
+        if exp <= now {
+            return Err(Error::TokenExpired { exp: exp, now: now });
+        }
+        Ok(())
+    }
+
+    pub fn verify(&mut self, token: String, secret: &[u8], algorithm: ALGORITHM) -> Result<(Header, Claims)> {
+        let (header, claims) = try!(JWS::decode_jwt_with_clock(token, secret, algorithm, &Validation::new(), &self.clock));
+        let nonce: String = match claims.get(NONCE_CLAIM) {
+            Some(nonce) => nonce,
+            None => return Err(Error::MissingClaims(vec![NONCE_CLAIM.to_owned()]))
+        };
+        try!(self.consume(&nonce));
+        Ok((header, claims))
+    }
+}
+
+#[test]
+fn nonces_are_consumed_once() {
+    use clock::FixedClock;
+    use entropy::SeededEntropy;
+
+    let mut challenge = NonceChallenge::with_sources(FixedClock(1000), SeededEntropy::new(b"seed"));
+    let nonce = challenge.issue();
+    let mut claims = Claims::new();
+    claims.set(NONCE_CLAIM, nonce.as_str());
+    let token = JWS::from_claims(Header::new(), claims).encode(b"a 32-byte client secret for HS256", ALGORITHM::HS256).unwrap();
+
+    assert!(challenge.verify(token.clone(), b"a 32-byte client secret for HS256", ALGORITHM::HS256).is_ok());
+    assert_err!(challenge.verify(token, b"a 32-byte client secret for HS256", ALGORITHM::HS256), Error::UnknownNonce);
+}
+
+#[test]
+fn tokens_without_an_issued_nonce_are_rejected() {
+    let mut challenge = NonceChallenge::new();
+    let token = JWS::from_claims(Header::new(), Claims::new()).encode(b"a 32-byte client secret for HS256", ALGORITHM::HS256).unwrap();
+    assert_err!(challenge.verify(token, b"a 32-byte client secret for HS256", ALGORITHM::HS256), Error::MissingClaims(claims) => assert_eq!(claims, vec!["nonce".to_owned()]));
+    assert!(challenge.consume("never-issued").is_err());
+}
+
+#[test]
+fn expired_nonces_are_rejected() {
+    use clock::FixedClock;
+    use entropy::SeededEntropy;
+
+    let mut challenge = NonceChallenge::with_sources(FixedClock(1000), SeededEntropy::new(b"seed"));
+    challenge.lifetime = 0;
+    let nonce = challenge.issue();
+    assert_err!(challenge.consume(&nonce), Error::TokenExpired { exp: 1000, now: 1000 });
+}
+
+#[test]
+fn token_times_are_checked_against_the_challenge_clock() {
+    use clock::FixedClock;
+    use entropy::SeededEntropy;
+
+    let mut challenge = NonceChallenge::with_sources(FixedClock(1000), SeededEntropy::new(b"seed"));
+    let nonce = challenge.issue();
+    let mut claims = Claims::new();
+    claims.set(NONCE_CLAIM, nonce.as_str());
+    claims.exp = Some(1100);
+    let token = JWS::from_claims(Header::new(), claims).encode(b"a 32-byte client secret for HS256", ALGORITHM::HS256).unwrap();
+    assert!(challenge.verify(token, b"a 32-byte client secret for HS256", ALGORITHM::HS256).is_ok());
+
+    let mut challenge = NonceChallenge::with_sources(FixedClock(5000), SeededEntropy::new(b"seed"));
+    let nonce = challenge.issue();
+    let mut claims = Claims::new();
+    claims.set(NONCE_CLAIM, nonce.as_str());
+    claims.exp = Some(1100);
+    let token = JWS::from_claims(Header::new(), claims).encode(b"a 32-byte client secret for HS256", ALGORITHM::HS256).unwrap();
+    assert_err!(challenge.verify(token, b"a 32-byte client secret for HS256", ALGORITHM::HS256), Error::TokenExpired { exp: 1100, now: 5000 });
+}
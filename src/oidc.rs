@@ -0,0 +1,177 @@
+#![allow(dead_code)]
+
+use std::str;
+use std::time::Duration;
+use serde_json::{self, Value};
+use claims::Claims;
+use clock::{Clock, SystemClock};
+use jws::ParsedToken;
+use jws_header::{Header, ALGORITHM};
+use jwks_client::{self, CachedJwks, JwksClient, JwksSource};
+use validation::Validation;
+use error::{Error, Result};
+
+const DISCOVERY_PATH: &'static str = "/.well-known/openid-configuration";
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub jwks_uri: String,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidDiscovery(reason.to_owned())
+}
+
+pub fn discovery_url(issuer: &str) -> String {
+    format!("{}{}", issuer.trim_end_matches('/'), DISCOVERY_PATH)
+}
+
+impl ProviderMetadata {
+    // The document must name the issuer it was fetched for (OpenID Connect Discovery, section 4.3).
+    pub fn from_json(json: &str, expected_issuer: &str) -> Result<ProviderMetadata> {
+        let value: Value = try!(serde_json::from_str(json));
+        let string = |name: &str| value.find(name).and_then(|member| member.as_str()).map(|member| member.to_owned());
+        let issuer = try!(string("issuer").ok_or_else(|| invalid("the issuer is missing")));
+        if issuer != expected_issuer {
+            return Err(Error::InvalidDiscovery(format!("the document is for {}, not {}", issuer, expected_issuer)));
+        }
+        let jwks_uri = try!(string("jwks_uri").ok_or_else(|| invalid("the jwks_uri is missing")));
+        let algorithms = match value.find("id_token_signing_alg_values_supported") {
+            Some(&Value::Array(ref algorithms)) => algorithms.iter()
+                .filter_map(|alg| alg.as_str().map(|alg| alg.to_owned()))
+                .collect(),
+            _ => return Err(invalid("id_token_signing_alg_values_supported is missing"))
+        };
+        Ok(ProviderMetadata {
+            issuer: issuer,
+            jwks_uri: jwks_uri,
+            id_token_signing_alg_values_supported: algorithms,
+        })
+    }
+
+    pub fn discover(issuer: &str) -> Result<ProviderMetadata> {
+        let body = try!(jwks_client::get(&discovery_url(issuer), Duration::from_secs(10), 256 * 1024));
+        ProviderMetadata::from_json(try!(str::from_utf8(&body)), issuer)
+    }
+
+    // Algorithms this crate does not implement are dropped, and "none" is never trusted.
+    pub fn algorithms(&self) -> Vec<ALGORITHM> {
+        self.id_token_signing_alg_values_supported.iter()
+            .filter_map(|name| ALGORITHM::from_name(name))
+            .filter(|alg| *alg != ALGORITHM::None)
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct OidcVerifier<S = JwksClient, C = SystemClock> {
+    metadata: ProviderMetadata,
+    keys: CachedJwks<S, C>,
+    pub options: Validation,
+}
+
+impl OidcVerifier {
+    pub fn discover(issuer: &str) -> Result<OidcVerifier> {
+        let metadata = try!(ProviderMetadata::discover(issuer));
+        let client = try!(JwksClient::new(&metadata.jwks_uri));
+        Ok(OidcVerifier::with_keys(metadata, CachedJwks::new(client)))
+    }
+}
+
+impl<S: JwksSource, C: Clock> OidcVerifier<S, C> {
+    pub fn with_keys(metadata: ProviderMetadata, keys: CachedJwks<S, C>) -> OidcVerifier<S, C> {
+        let mut options = Validation::new();
+        options.issuer = Some(metadata.issuer.clone());
+        options.algorithms = metadata.algorithms();
+        options.require_algorithms = true;
+        OidcVerifier {
+            metadata: metadata,
+            keys: keys,
+            options: options,
+        }
+    }
+
+    pub fn metadata(&self) -> &ProviderMetadata {
+        &self.metadata
+    }
+
+    pub fn decode(&self, token: &str, algorithm: ALGORITHM) -> Result<(Header, Claims)> {
+        self.decode_with_clock(token, algorithm, &SystemClock)
+    }
+
+    pub fn decode_with_clock<K: Clock>(&self, token: &str, algorithm: ALGORITHM, clock: &K) -> Result<(Header, Claims)> {
+        self.decode_at(token, algorithm, clock.now())
+    }
+
+    // The algorithm comes from the caller, never from the token.
+    pub fn decode_at(&self, token: &str, algorithm: ALGORITHM, now: u64) -> Result<(Header, Claims)> {
+        let key = {
+            let parsed = try!(ParsedToken::parse_with_validation(token, &self.options));
+            let kid = parsed.header().kid.as_ref().map(|kid| kid.as_str());
+            try!(try!(self.keys.find(kid, &algorithm)).to_key())
+        };
+        self.options.decode_at(token, &key, algorithm, now)
+    }
+}
+
+#[cfg(test)]
+struct StaticKeys(::jwk_set::JwkSet);
+
+#[cfg(test)]
+impl JwksSource for StaticKeys {
+    fn fetch(&self) -> Result<::jwk_set::JwkSet> {
+        Ok(self.0.clone())
+    }
+}
+
+#[cfg(test)]
+const METADATA: &'static str = r#"{
+    "issuer": "https://login.example.com",
+    "jwks_uri": "https://login.example.com/keys",
+    "authorization_endpoint": "https://login.example.com/authorize",
+    "id_token_signing_alg_values_supported": ["RS256", "ES256", "none", "XX999"]
+}"#;
+
+#[test]
+fn discovery_documents_are_pinned_to_their_issuer() {
+    assert_eq!(discovery_url("https://login.example.com/"), "https://login.example.com/.well-known/openid-configuration");
+    assert_eq!(discovery_url("https://example.com/tenant"), "https://example.com/tenant/.well-known/openid-configuration");
+
+    let metadata = ProviderMetadata::from_json(METADATA, "https://login.example.com").unwrap();
+    assert_eq!(metadata.jwks_uri, "https://login.example.com/keys");
+    assert_eq!(metadata.algorithms(), vec![ALGORITHM::RS256, ALGORITHM::ES256]);
+
+    assert_err!(ProviderMetadata::from_json(METADATA, "https://attacker.example"), Error::InvalidDiscovery(reason) => assert!(reason.contains("https://attacker.example")));
+    assert!(ProviderMetadata::from_json(r#"{"issuer":"https://login.example.com"}"#, "https://login.example.com").is_err());
+}
+
+#[test]
+fn provider_tokens_verify_against_the_discovered_keys() {
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use jwk::Jwk;
+
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap().private_key_to_pem_pkcs8().unwrap();
+    let mut jwk = Jwk::from_key(&key).unwrap().to_public().unwrap();
+    jwk.kid = Some("k1".to_owned());
+    let keys = CachedJwks::with_sources(StaticKeys(::jwk_set::JwkSet::new(vec![jwk])), ::clock::FixedClock(1000));
+    let verifier = OidcVerifier::with_keys(ProviderMetadata::from_json(METADATA, "https://login.example.com").unwrap(), keys);
+
+    let token = |issuer: &str| {
+        let mut header = Header::new();
+        header.alg = ALGORITHM::ES256;
+        header.kid = Some("k1".to_owned());
+        let mut claims = Claims::new();
+        claims.iss = Some(issuer.to_owned());
+        claims.exp = Some(2000);
+        ::jws::JWS::from_claims(header, claims).encode(&key, ALGORITHM::ES256).unwrap()
+    };
+    assert!(verifier.decode_at(&token("https://login.example.com"), ALGORITHM::ES256, 1000).is_ok());
+    assert_err!(verifier.decode_at(&token("https://login.example.com"), ALGORITHM::ES256, 5000), Error::TokenExpired { exp: 2000, now: 5000 });
+    assert_err!(verifier.decode_at(&token("https://other.example"), ALGORITHM::ES256, 1000), Error::ClaimMismatch { claim: "iss" });
+    assert_err!(verifier.decode_at(&token("https://login.example.com"), ALGORITHM::HS256, 1000), Error::UnknownKey { .. } | Error::AlgorithmNotAllowed(_));
+}
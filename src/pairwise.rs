@@ -0,0 +1,43 @@
+#![allow(dead_code)]
+
+use signing;
+use jws::base64_url_encode_bytes;
+use error::Result;
+
+pub fn sector_identifier(uri: &str) -> Option<String> {
+    let rest = match uri.find("://") {
+        Some(i) => &uri[i + 3..],
+        None => return None
+    };
+    let authority = rest.split(|c| c == '/' || c == '?' || c == '#').next().unwrap_or("");
+    let host = authority.rsplit('@').next().unwrap_or("");
+    let host = host.split(':').next().unwrap_or("");
+    if host.is_empty() {
+        None
+    } else {
+        Some(host.to_lowercase())
+    }
+}
+
+pub fn pairwise_sub(sector_identifier: &str, local_account_id: &str, salt: &[u8]) -> Result<String> {
+    let mut input = Vec::with_capacity(sector_identifier.len() + local_account_id.len());
+    input.extend_from_slice(sector_identifier.as_bytes());
+    input.extend_from_slice(local_account_id.as_bytes());
+    Ok(base64_url_encode_bytes(try!(signing::hmac_256(salt, input.as_slice())).as_slice()))
+}
+
+#[test]
+fn sector_identifier_is_the_host_of_the_uri() {
+    assert_eq!(sector_identifier("https://Client.Example.org:8443/cb?x=1").unwrap(), "client.example.org");
+    assert_eq!(sector_identifier("https://user@example.com/cb").unwrap(), "example.com");
+    assert!(sector_identifier("not a uri").is_none());
+}
+
+#[test]
+fn pairwise_subjects_differ_per_sector_and_are_stable() {
+    let salt = b"issuer-salt";
+    let a = pairwise_sub("client-a.example.org", "248289761001", salt).unwrap();
+    let b = pairwise_sub("client-b.example.org", "248289761001", salt).unwrap();
+    assert!(a != b);
+    assert_eq!(a, pairwise_sub("client-a.example.org", "248289761001", salt).unwrap());
+}
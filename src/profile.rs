@@ -0,0 +1,106 @@
+#![allow(dead_code)]
+
+use clock::{Clock, SystemClock};
+use entropy::{Entropy, SystemEntropy};
+use claims::Claims;
+use jws_header::Header;
+use jws::base64_url_encode_bytes;
+use typ::Typ;
+use error::{Error, Result};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Profile {
+    AccessToken,
+    IdToken,
+    DPoP,
+    SecurityEvent,
+    Logout,
+    License,
+}
+
+impl Profile {
+    pub fn typ(&self) -> Typ {
+        match *self {
+            Profile::AccessToken => Typ::AT_JWT,
+            Profile::IdToken => Typ::JWT,
+            Profile::DPoP => Typ::DPOP_JWT,
+            Profile::SecurityEvent => Typ::SECEVENT_JWT,
+            Profile::Logout => Typ::LOGOUT_JWT,
+            Profile::License => Typ::LICENSE_JWT,
+        }
+    }
+
+    pub fn required_claims(&self) -> &'static [&'static str] {
+        match *self {
+            Profile::AccessToken => &["iss", "exp", "aud", "sub", "client_id", "iat", "jti"],
+            Profile::IdToken => &["iss", "sub", "aud", "exp", "iat"],
+            Profile::DPoP => &["jti", "htm", "htu", "iat"],
+            Profile::SecurityEvent => &["iss", "iat", "jti", "events"],
+            Profile::Logout => &["iss", "aud", "iat", "jti", "events"],
+            // exp is left out so that perpetual licenses can be issued.
+            Profile::License => &["iss", "sub", "iat", "jti"],
+        }
+    }
+
+    pub fn apply(&self, header: &mut Header, claims: &mut Claims) -> Result<()> {
+        self.apply_with(header, claims, &SystemClock, &SystemEntropy)
+    }
+
+    pub fn apply_with<C: Clock, E: Entropy>(&self, header: &mut Header, claims: &mut Claims, clock: &C, entropy: &E) -> Result<()> {
+        header.typ = Some(self.typ().as_str().to_owned());
+        let required = self.required_claims();
+        if claims.iat.is_none() && required.contains(&"iat") {
+            claims.iat = Some(clock.now());
+        }
+        if claims.jti.is_none() && required.contains(&"jti") {
+            claims.jti = Some(base64_url_encode_bytes(entropy.random_bytes(16).as_slice()));
+        }
+        let missing: Vec<String> = required.iter()
+            .filter(|name| !claims.has(name))
+            .map(|name| name.to_string())
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::MissingClaims(missing))
+        }
+    }
+}
+
+#[test]
+fn profiles_set_typ_and_fill_iat_and_jti() {
+    let mut header = Header::new();
+    let mut claims = Claims::new();
+    claims.set("htm", "POST");
+    claims.set("htu", "https://server.example.com/token");
+    Profile::DPoP.apply(&mut header, &mut claims).unwrap();
+    assert_eq!(header.typ, Some("dpop+jwt".to_owned()));
+    assert!(claims.iat.is_some());
+    assert!(claims.jti.is_some());
+}
+
+#[test]
+fn profiles_can_be_applied_deterministically() {
+    use clock::FixedClock;
+    use entropy::SeededEntropy;
+
+    let apply = || {
+        let mut header = Header::new();
+        let mut claims = Claims::new();
+        claims.iss = Some("https://issuer.example.com".to_owned());
+        claims.set("events", "{}");
+        Profile::SecurityEvent.apply_with(&mut header, &mut claims, &FixedClock(1457396280), &SeededEntropy::new(b"golden")).unwrap();
+        claims
+    };
+    let claims = apply();
+    assert_eq!(claims.iat, Some(1457396280));
+    assert_eq!(claims, apply());
+}
+
+#[test]
+fn profiles_report_every_missing_required_claim() {
+    let mut header = Header::new();
+    let mut claims = Claims::new();
+    claims.iss = Some("https://issuer.example.com".to_owned());
+    assert_err!(Profile::AccessToken.apply(&mut header, &mut claims), Error::MissingClaims(missing) => assert_eq!(missing, vec!["exp", "aud", "sub", "client_id"]));
+}
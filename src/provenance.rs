@@ -0,0 +1,101 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use serde::Serialize;
+use serde_json::{self, Value, to_value};
+use claims::Claims;
+use jws::JWS;
+use jws_header::{Header, ALGORITHM};
+use error::Result;
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Provenance {
+    sources: BTreeMap<String, String>,
+}
+
+impl Provenance {
+    pub fn source_of(&self, claim: &str) -> Option<&str> {
+        self.sources.get(claim).map(|source| source.as_str())
+    }
+
+    pub fn iter<'a>(&'a self) -> ::std::collections::btree_map::Iter<'a, String, String> {
+        self.sources.iter()
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(try!(serde_json::to_string(&self.sources)))
+    }
+}
+
+// Later sources win; the provenance always names the source whose value was kept.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ClaimsMerge {
+    values: BTreeMap<String, Value>,
+    provenance: Provenance,
+}
+
+impl ClaimsMerge {
+    pub fn new() -> ClaimsMerge {
+        ClaimsMerge::default()
+    }
+
+    pub fn merge(&mut self, source: &str, claims: &Claims) -> &mut ClaimsMerge {
+        for (name, value) in claims.iter_all() {
+            self.insert(source, name, value);
+        }
+        self
+    }
+
+    pub fn set<T: Serialize>(&mut self, source: &str, claim: &str, value: T) -> &mut ClaimsMerge {
+        self.insert(source, claim.to_owned(), to_value(&value));
+        self
+    }
+
+    fn insert(&mut self, source: &str, claim: String, value: Value) {
+        self.provenance.sources.insert(claim.clone(), source.to_owned());
+        self.values.insert(claim, value);
+    }
+
+    pub fn provenance(&self) -> &Provenance {
+        &self.provenance
+    }
+
+    pub fn claims(&self) -> Result<Claims> {
+        Ok(try!(serde_json::from_value(Value::Object(self.values.clone()))))
+    }
+
+    // The provenance is returned with the token so it can be logged for audit;
+    // it is never part of the token itself.
+    pub fn encode(&self, header: Header, secret: &[u8], alg: ALGORITHM) -> Result<(String, Provenance)> {
+        let token = try!(JWS::from_claims(header, try!(self.claims())).encode(secret, alg));
+        Ok((token, self.provenance.clone()))
+    }
+}
+
+#[test]
+fn merged_claims_remember_their_source() {
+    let mut template = Claims::new();
+    template.iss = Some("https://gateway.example".to_owned());
+    template.set("scope", "read");
+    let mut upstream = Claims::new();
+    upstream.sub = Some("user-1".to_owned());
+    upstream.set("scope", "read write");
+    upstream.set("tenant", "acme");
+
+    let mut merge = ClaimsMerge::new();
+    merge.merge("template", &template).merge("upstream", &upstream).set("request", "exp", 2000u64);
+    let (token, provenance) = merge.encode(Header::new(), ::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap();
+
+    assert_eq!(provenance.source_of("scope"), Some("upstream"));
+    assert_eq!(provenance.source_of("iss"), Some("template"));
+    assert_eq!(provenance.source_of("exp"), Some("request"));
+    assert_eq!(provenance.source_of("jti"), None);
+    assert_eq!(provenance.iter().count(), 5);
+
+    let (_, claims) = JWS::decode_jwt_at(token, ::TEST_HS256_SECRET, ALGORITHM::HS256, &::validation::Validation::new(), 1000).unwrap();
+    assert_eq!(claims.get::<String>("scope"), Some("read write".to_owned()));
+    assert_eq!(claims.sub, Some("user-1".to_owned()));
+    assert_eq!(claims.exp, Some(2000));
+    assert!(claims.get::<Value>("provenance").is_none());
+    assert!(provenance.to_json().unwrap().contains("\"tenant\":\"upstream\""));
+}
@@ -0,0 +1,72 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+pub trait ReplayStore {
+    // Returns false when the jti has been recorded before.
+    fn check_and_record(&mut self, jti: &str, exp: u64) -> bool;
+
+    // Stores that know the time may forget entries once exp has passed; an
+    // expired token is rejected before its jti is consulted.
+    fn check_and_record_at(&mut self, jti: &str, exp: u64, now: u64) -> bool {
+        let _ = now;
+        self.check_and_record(jti, exp)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryReplayStore {
+    seen: HashMap<String, u64>,
+    next_expiry: Option<u64>,
+}
+
+impl MemoryReplayStore {
+    pub fn new() -> MemoryReplayStore {
+        MemoryReplayStore::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    pub fn purge(&mut self, now: u64) {
+        self.seen.retain(|_, exp| *exp > now);
+        self.next_expiry = self.seen.values().cloned().min();
+    }
+}
+
+impl ReplayStore for MemoryReplayStore {
+    fn check_and_record(&mut self, jti: &str, exp: u64) -> bool {
+        if self.seen.contains_key(jti) {
+            return false;
+        }
+        self.seen.insert(jti.to_owned(), exp);
+        self.next_expiry = Some(self.next_expiry.map_or(exp, |next| ::std::cmp::min(next, exp)));
+        true
+    }
+
+    // Purges only when the earliest entry has expired, so most calls cost one lookup.
+    fn check_and_record_at(&mut self, jti: &str, exp: u64, now: u64) -> bool {
+        if self.next_expiry.map_or(false, |next| next <= now) {
+            self.purge(now);
+        }
+        self.check_and_record(jti, exp)
+    }
+}
+
+#[test]
+fn memory_stores_forget_expired_entries() {
+    let mut store = MemoryReplayStore::new();
+    assert!(store.check_and_record_at("a", 1100, 1000));
+    assert!(store.check_and_record_at("b", 2000, 1000));
+    assert!(!store.check_and_record_at("a", 1100, 1050));
+    assert_eq!(store.len(), 2);
+
+    assert!(!store.check_and_record_at("b", 2000, 1100));
+    assert_eq!(store.len(), 1);
+    assert!(store.check_and_record_at("a", 1200, 1100));
+}
@@ -0,0 +1,94 @@
+#![allow(dead_code)]
+
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use serde_json;
+use claims::Claims;
+use entropy::{Entropy, SystemEntropy};
+use error::{Error, Result};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+// Encrypts verified claims with AES-256-GCM before they are put in a shared
+// cache. The sealed form is nonce || ciphertext || tag; the context (a cache
+// key or session id) is authenticated but not stored. Nonces always come from
+// the system random source: a repeated GCM nonce under one key leaks the
+// authentication key, so seeded entropy is not accepted here.
+pub struct SealedClaims {
+    key: Vec<u8>,
+}
+
+impl SealedClaims {
+    pub fn new(key: &[u8]) -> Result<SealedClaims> {
+        if key.len() != KEY_LEN {
+            return Err(Error::InvalidSealKey { len: key.len() });
+        }
+        Ok(SealedClaims { key: key.to_vec() })
+    }
+
+    pub fn seal(&self, claims: &Claims, context: &[u8]) -> Result<Vec<u8>> {
+        let plaintext = try!(serde_json::to_vec(claims));
+        let nonce = SystemEntropy.random_bytes(NONCE_LEN);
+        let mut tag = [0; TAG_LEN];
+        let ciphertext = try!(encrypt_aead(Cipher::aes_256_gcm(), &self.key, Some(&nonce), context, &plaintext, &mut tag));
+        let mut sealed = nonce;
+        sealed.extend_from_slice(&ciphertext);
+        sealed.extend_from_slice(&tag);
+        Ok(sealed)
+    }
+
+    pub fn unseal(&self, sealed: &[u8], context: &[u8]) -> Result<Claims> {
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return Err(Error::UnsealFailed);
+        }
+        let (nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        let plaintext = try!(decrypt_aead(Cipher::aes_256_gcm(), &self.key, Some(nonce), context, ciphertext, tag)
+            .map_err(|_| Error::UnsealFailed));
+        Ok(try!(serde_json::from_slice(&plaintext)))
+    }
+}
+
+#[test]
+fn sealed_claims_round_trip_without_plaintext() {
+    let sealer = SealedClaims::new(&[7; 32]).unwrap();
+    let mut claims = Claims::new();
+    claims.sub = Some("alice@example.com".to_owned());
+    claims.set("role", "admin");
+
+    let sealed = sealer.seal(&claims, b"session:42").unwrap();
+    assert!(!String::from_utf8_lossy(&sealed).contains("alice"));
+    let unsealed = sealer.unseal(&sealed, b"session:42").unwrap();
+    assert_eq!(unsealed.sub, claims.sub);
+    assert_eq!(unsealed.get::<String>("role"), Some("admin".to_owned()));
+}
+
+#[test]
+fn each_seal_draws_a_fresh_nonce() {
+    let sealer = SealedClaims::new(&[7; 32]).unwrap();
+    let first = sealer.seal(&Claims::new(), b"session:42").unwrap();
+    let second = sealer.seal(&Claims::new(), b"session:42").unwrap();
+    assert!(first[..NONCE_LEN] != second[..NONCE_LEN]);
+}
+
+#[test]
+fn tampered_or_moved_entries_do_not_unseal() {
+    let sealer = SealedClaims::new(&[7; 32]).unwrap();
+    let mut sealed = sealer.seal(&Claims::new(), b"session:42").unwrap();
+
+    assert_err!(sealer.unseal(&sealed, b"session:43"), Error::UnsealFailed);
+    assert!(SealedClaims::new(&[8; 32]).unwrap().unseal(&sealed, b"session:42").is_err());
+    sealed[NONCE_LEN] ^= 1;
+    assert!(sealer.unseal(&sealed, b"session:42").is_err());
+    assert!(sealer.unseal(&sealed[..10], b"session:42").is_err());
+}
+
+#[test]
+fn seal_keys_must_be_256_bits() {
+    match SealedClaims::new(b"short") {
+        Err(Error::InvalidSealKey { len: 5 }) => {},
+        Err(other) => panic!("expected InvalidSealKey, got {:?}", other),
+        Ok(_) => panic!("expected InvalidSealKey")
+    }
+}
@@ -0,0 +1,58 @@
+#![allow(dead_code)]
+
+use std::fmt;
+use openssl::hash::{hash, MessageDigest};
+use jws::base64_url_encode_bytes;
+
+#[derive(PartialEq, Eq, Clone, Hash)]
+pub struct SecretToken {
+    value: String,
+}
+
+impl SecretToken {
+    pub fn new(value: String) -> SecretToken {
+        SecretToken { value: value }
+    }
+
+    pub fn expose(&self) -> &str {
+        self.value.as_str()
+    }
+
+    pub fn into_exposed(self) -> String {
+        self.value
+    }
+
+    pub fn fingerprint(&self) -> String {
+        let digest = hash(MessageDigest::sha256(), self.value.as_bytes()).expect("SHA-256 failed");
+        base64_url_encode_bytes(&digest[..8])
+    }
+}
+
+impl From<String> for SecretToken {
+    fn from(value: String) -> SecretToken {
+        SecretToken::new(value)
+    }
+}
+
+impl fmt::Debug for SecretToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretToken({})", self.fingerprint())
+    }
+}
+
+impl fmt::Display for SecretToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[redacted token {}]", self.fingerprint())
+    }
+}
+
+#[test]
+fn secret_tokens_do_not_print_their_value() {
+    let token = SecretToken::new("eyJhbGciOiJIUzI1NiJ9.e30.c2lnbmF0dXJl".to_owned());
+    let debug = format!("{:?}", token);
+    let display = format!("{}", token);
+    assert!(!debug.contains("eyJ") && !display.contains("eyJ"));
+    assert!(display.contains(&token.fingerprint()));
+    assert_eq!(token.fingerprint().len(), 11);
+    assert_eq!(token.expose(), "eyJhbGciOiJIUzI1NiJ9.e30.c2lnbmF0dXJl");
+}
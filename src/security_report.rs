@@ -0,0 +1,177 @@
+#![allow(dead_code)]
+
+use openssl::bn::BigNum;
+use jwk::{Jwk, JwkParams};
+use jwk_set::{JwkSet, fits};
+use jws_header::ALGORITHM;
+use validation::{Validation, MIN_RSA_BITS};
+
+// Configuration that verifies tokens today but falls short of RFC 8725 and
+// NIST SP 800-131A.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Finding {
+    // An algorithm name outside RFC 7518, such as RS1 or HS1. This crate cannot
+    // tell how strong it is.
+    CustomAlgorithm(String),
+    // ES256 is accepted from secp256k1 keys (allow_es256_secp256k1).
+    Es256OnSecp256k1,
+    // HMAC secrets shorter than the hash output are accepted (allow_short_hmac_keys).
+    ShortHmacKeysAllowed,
+    // RSA algorithms are allowed and keys under MIN_RSA_BITS are not refused.
+    // None means min_rsa_bits is unset, so any modulus is accepted.
+    WeakRsaKeysAllowed { min_bits: Option<usize> },
+    // A configured key is too short for an allowed algorithm it fits.
+    WeakKey { kid: Option<String>, alg: ALGORITHM, bits: usize, required: usize },
+    // A configured key's certificate is identified only by its SHA-1 x5t.
+    Sha1Thumbprint { kid: Option<String> },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SecurityReport {
+    pub algorithms: Vec<ALGORITHM>,
+    pub custom_algorithms: Vec<String>,
+    pub findings: Vec<Finding>,
+}
+
+impl SecurityReport {
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+}
+
+fn is_hmac(alg: &ALGORITHM) -> bool {
+    match *alg {
+        ALGORITHM::HS256 | ALGORITHM::HS384 | ALGORITHM::HS512 => true,
+        _ => false
+    }
+}
+
+fn is_rsa(alg: &ALGORITHM) -> bool {
+    match *alg {
+        ALGORITHM::RS256 | ALGORITHM::RS384 | ALGORITHM::RS512 |
+        ALGORITHM::PS256 | ALGORITHM::PS384 | ALGORITHM::PS512 => true,
+        _ => false
+    }
+}
+
+// The key size each algorithm needs; 0 where the curve or key type fixes it.
+fn required_bits(alg: &ALGORITHM) -> usize {
+    match *alg {
+        ALGORITHM::HS256 => 256,
+        ALGORITHM::HS384 => 384,
+        ALGORITHM::HS512 => 512,
+        _ if is_rsa(alg) => MIN_RSA_BITS,
+        _ => 0
+    }
+}
+
+fn key_bits(jwk: &Jwk) -> Option<usize> {
+    match jwk.params {
+        JwkParams::Oct { ref k } => Some(k.len() * 8),
+        JwkParams::Rsa { ref n, .. } => BigNum::from_slice(n).ok().map(|n| n.num_bits() as usize),
+        JwkParams::Ec { .. } => None,
+    }
+}
+
+// The strictest allowed algorithm the key is too short for.
+fn weak_key(jwk: &Jwk, algorithms: &[ALGORITHM]) -> Option<Finding> {
+    let bits = match key_bits(jwk) {
+        Some(bits) => bits,
+        None => return None
+    };
+    algorithms.iter()
+        .filter(|alg| fits(jwk, alg) && bits < required_bits(alg))
+        .max_by_key(|alg| required_bits(alg))
+        .map(|alg| Finding::WeakKey { kid: jwk.kid.clone(), alg: alg.clone(), bits: bits, required: required_bits(alg) })
+}
+
+impl Validation {
+    // Only the configuration is audited; see security_report_for_keys to
+    // include the keys tokens are verified with.
+    pub fn security_report(&self) -> SecurityReport {
+        let mut findings: Vec<Finding> = self.custom_algorithms.iter()
+            .map(|name| Finding::CustomAlgorithm(name.clone()))
+            .collect();
+        let allows = |check: fn(&ALGORITHM) -> bool| self.algorithms.is_empty() || self.algorithms.iter().any(check);
+        if self.allow_es256_secp256k1 && allows(|alg| *alg == ALGORITHM::ES256) {
+            findings.push(Finding::Es256OnSecp256k1);
+        }
+        if self.allow_short_hmac_keys && allows(is_hmac) {
+            findings.push(Finding::ShortHmacKeysAllowed);
+        }
+        let rsa_floor_ok = self.min_rsa_bits.map_or(false, |bits| bits >= MIN_RSA_BITS);
+        if !rsa_floor_ok && allows(is_rsa) {
+            findings.push(Finding::WeakRsaKeysAllowed { min_bits: self.min_rsa_bits });
+        }
+        SecurityReport {
+            algorithms: self.algorithms.clone(),
+            custom_algorithms: self.custom_algorithms.clone(),
+            findings: findings,
+        }
+    }
+
+    // Keys are checked against the allowed algorithms they fit. With an empty
+    // allow-list every algorithm a key fits is considered.
+    pub fn security_report_for_keys(&self, keys: &JwkSet) -> SecurityReport {
+        let mut report = self.security_report();
+        let algorithms = if self.algorithms.is_empty() {
+            vec![ALGORITHM::HS256, ALGORITHM::HS384, ALGORITHM::HS512,
+                 ALGORITHM::RS256, ALGORITHM::RS384, ALGORITHM::RS512,
+                 ALGORITHM::PS256, ALGORITHM::PS384, ALGORITHM::PS512]
+        } else {
+            self.algorithms.clone()
+        };
+        for jwk in keys.keys() {
+            report.findings.extend(weak_key(jwk, &algorithms));
+            if jwk.x5t.is_some() && jwk.x5t_s256.is_none() {
+                report.findings.push(Finding::Sha1Thumbprint { kid: jwk.kid.clone() });
+            }
+        }
+        report
+    }
+}
+
+#[test]
+fn strict_rsa_configurations_report_no_findings() {
+    let mut validation = Validation::rfc8725_strict();
+    validation.algorithms = vec![ALGORITHM::RS256, ALGORITHM::PS256];
+    assert!(validation.security_report().is_clean());
+
+    validation.min_rsa_bits = Some(1024);
+    validation = validation.allow_custom_algorithm("RS1");
+    assert_eq!(validation.security_report().findings, vec![
+        Finding::CustomAlgorithm("RS1".to_owned()),
+        Finding::WeakRsaKeysAllowed { min_bits: Some(1024) },
+    ]);
+}
+
+#[test]
+fn short_secrets_and_small_moduli_are_flagged_per_key() {
+    let rsa = ::openssl::rsa::Rsa::generate(1024).unwrap();
+    let mut small = Jwk::from_key(&rsa.public_key_to_der().unwrap()).unwrap();
+    small.kid = Some("partner-7".to_owned());
+    let mut short = Jwk::oct(::TEST_HS256_SECRET);
+    short.kid = Some("hmac".to_owned());
+    let keys = JwkSet::new(vec![small, short]);
+
+    let mut validation = Validation::new();
+    validation.algorithms = vec![ALGORITHM::HS256, ALGORITHM::HS512];
+    validation.allow_short_hmac_keys = true;
+    assert_eq!(validation.security_report_for_keys(&keys).findings, vec![
+        Finding::ShortHmacKeysAllowed,
+        Finding::WeakKey { kid: Some("hmac".to_owned()), alg: ALGORITHM::HS512, bits: 256, required: 512 },
+    ]);
+
+    validation.algorithms = vec![ALGORITHM::RS256];
+    validation.allow_short_hmac_keys = false;
+    validation.min_rsa_bits = Some(MIN_RSA_BITS);
+    assert_eq!(validation.security_report_for_keys(&keys).findings, vec![
+        Finding::WeakKey { kid: Some("partner-7".to_owned()), alg: ALGORITHM::RS256, bits: 1024, required: MIN_RSA_BITS },
+    ]);
+
+    let mut sha1_only = Jwk::oct(::TEST_HS256_SECRET);
+    sha1_only.x5t = Some(vec![0; 20]);
+    assert_eq!(validation.security_report_for_keys(&JwkSet::new(vec![sha1_only])).findings, vec![
+        Finding::Sha1Thumbprint { kid: None },
+    ]);
+}
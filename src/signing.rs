@@ -1,63 +1,493 @@
 #![allow(dead_code)]
-extern crate openssl;
 
-use self::openssl::crypto::pkey::PKey;
-use self::openssl::crypto::hash;
-use self::openssl::crypto::hmac::hmac;
+use openssl::bn::BigNum;
+use openssl::ecdsa::EcdsaSig;
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::nid::Nid;
+use openssl::pkey::{HasParams, Id, PKey, PKeyRef, Private, Public};
+use openssl::rsa::Padding;
+use openssl::sign::{RsaPssSaltlen, Signer, Verifier};
+use openssl::x509::X509;
 
-enum ALGORITHMS {
-    HS256,
-    HS384,
-    HS512,
-    RS256,
-    RS384,
-    RS512,
-    ES256,
-    ES384,
-    ES512,
+pub fn sign_pk256(key: &PKey<Private>, payload: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    sign(MessageDigest::sha256(), Padding::PKCS1, key, payload)
 }
 
-pub fn sign_pk256(key: PKey, payload: &[u8]) -> Vec<u8> {
-    sign(hash::Type::SHA256, key, payload)
+pub fn sign_pk384(key: &PKey<Private>, payload: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    sign(MessageDigest::sha384(), Padding::PKCS1, key, payload)
 }
 
-pub fn sign_pk384(key: PKey, payload: &[u8]) -> Vec<u8> {
-    sign(hash::Type::SHA384, key, payload)
+pub fn sign_pk512(key: &PKey<Private>, payload: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    sign(MessageDigest::sha512(), Padding::PKCS1, key, payload)
 }
 
-pub fn sign_pk512(key: PKey, payload: &[u8]) -> Vec<u8> {
-    sign(hash::Type::SHA512, key, payload)
+pub fn verify_pk256(key: &PKey<Public>, signature: &[u8], payload: &[u8]) -> Result<bool, ErrorStack> {
+    verify(MessageDigest::sha256(), Padding::PKCS1, key, signature, payload)
 }
 
-pub fn verify_pk256(key: PKey, hash: &[u8], payload: &[u8]) -> bool {
-    verify(hash::Type::SHA256, key, hash, payload)
+pub fn verify_pk384(key: &PKey<Public>, signature: &[u8], payload: &[u8]) -> Result<bool, ErrorStack> {
+    verify(MessageDigest::sha384(), Padding::PKCS1, key, signature, payload)
 }
 
-pub fn verify_pk384(key: PKey, hash: &[u8], payload: &[u8]) -> bool {
-    verify(hash::Type::SHA256, key, hash, payload)
+pub fn verify_pk512(key: &PKey<Public>, signature: &[u8], payload: &[u8]) -> Result<bool, ErrorStack> {
+    verify(MessageDigest::sha512(), Padding::PKCS1, key, signature, payload)
 }
 
-pub fn verify_pk512(key: PKey, hash: &[u8], payload: &[u8]) -> bool {
-    verify(hash::Type::SHA256, key, hash, payload)
+pub fn sign_ps256(key: &PKey<Private>, payload: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    sign(MessageDigest::sha256(), Padding::PKCS1_PSS, key, payload)
 }
 
-fn sign(hash_type: hash::Type, key: PKey, payload: &[u8]) -> Vec<u8> {
-    let digest = hash::hash(hash_type, payload);
-    key.sign_with_hash(digest.as_slice(), hash_type)
+pub fn sign_ps384(key: &PKey<Private>, payload: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    sign(MessageDigest::sha384(), Padding::PKCS1_PSS, key, payload)
 }
 
-fn verify(hash_type: hash::Type, key: PKey, hash: &[u8], payload: &[u8]) -> bool {
-    key.verify_with_hash(hash, payload, hash_type)
+pub fn sign_ps512(key: &PKey<Private>, payload: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    sign(MessageDigest::sha512(), Padding::PKCS1_PSS, key, payload)
 }
 
-pub fn hmac_256(key: &[u8], payload: &[u8]) -> Vec<u8> {
-    hmac(hash::Type::SHA256, key, payload)
+pub fn verify_ps256(key: &PKey<Public>, signature: &[u8], payload: &[u8]) -> Result<bool, ErrorStack> {
+    verify(MessageDigest::sha256(), Padding::PKCS1_PSS, key, signature, payload)
 }
 
-pub fn hmac_384(key: &[u8], payload: &[u8]) -> Vec<u8> {
-    hmac(hash::Type::SHA384, key, payload)
+pub fn verify_ps384(key: &PKey<Public>, signature: &[u8], payload: &[u8]) -> Result<bool, ErrorStack> {
+    verify(MessageDigest::sha384(), Padding::PKCS1_PSS, key, signature, payload)
 }
 
-pub fn hmac_512(key: &[u8], payload: &[u8]) -> Vec<u8> {
-    hmac(hash::Type::SHA512, key, payload)
+pub fn verify_ps512(key: &PKey<Public>, signature: &[u8], payload: &[u8]) -> Result<bool, ErrorStack> {
+    verify(MessageDigest::sha512(), Padding::PKCS1_PSS, key, signature, payload)
+}
+
+// RFC 7518 3.5: PSS uses MGF1 with the same hash and a salt as long as the digest.
+fn sign(digest: MessageDigest, padding: Padding, key: &PKey<Private>, payload: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    let mut signer = try!(Signer::new(digest, key));
+    try!(signer.set_rsa_padding(padding));
+    if padding == Padding::PKCS1_PSS {
+        try!(signer.set_rsa_mgf1_md(digest));
+        try!(signer.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH));
+    }
+    try!(signer.update(payload));
+    signer.sign_to_vec()
+}
+
+fn verify(digest: MessageDigest, padding: Padding, key: &PKey<Public>, signature: &[u8], payload: &[u8]) -> Result<bool, ErrorStack> {
+    let mut verifier = try!(Verifier::new(digest, key));
+    try!(verifier.set_rsa_padding(padding));
+    if padding == Padding::PKCS1_PSS {
+        try!(verifier.set_rsa_mgf1_md(digest));
+        try!(verifier.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH));
+    }
+    try!(verifier.update(payload));
+    Ok(verifier.verify(signature).unwrap_or(false))
+}
+
+const ED25519_KEY_LEN: usize = 32;
+const ED448_KEY_LEN: usize = 57;
+
+const DER_SEQUENCE: u8 = 0x30;
+
+// Keys are PEM unless they start with a DER SEQUENCE tag, which no PEM text can.
+pub fn private_key(key: &[u8]) -> Result<PKey<Private>, ErrorStack> {
+    if key.first() == Some(&DER_SEQUENCE) {
+        PKey::private_key_from_der(key)
+    } else {
+        // An empty password callback keeps OpenSSL from prompting on the terminal
+        // when it meets an encrypted key.
+        PKey::private_key_from_pem_callback(key, |_| Ok(0))
+    }
+}
+
+// PEM covers PKCS#1 and PKCS#8, encrypted or not; encrypted DER is always PKCS#8.
+pub fn private_key_with_passphrase(key: &[u8], passphrase: Option<&[u8]>) -> Result<PKey<Private>, ErrorStack> {
+    match passphrase {
+        None => private_key(key),
+        Some(passphrase) if key.first() == Some(&DER_SEQUENCE) => PKey::private_key_from_pkcs8_passphrase(key, passphrase),
+        Some(passphrase) => PKey::private_key_from_pem_passphrase(key, passphrase)
+    }
+}
+
+// The curve is taken from the key itself: raw keys by length, PEM keys by their OID.
+pub fn eddsa_key(key: &[u8]) -> Result<PKey<Private>, ErrorStack> {
+    match key.len() {
+        ED25519_KEY_LEN => PKey::private_key_from_raw_bytes(key, Id::ED25519),
+        ED448_KEY_LEN => PKey::private_key_from_raw_bytes(key, Id::ED448),
+        _ => private_key(key)
+    }
+}
+
+fn eddsa_signature_len<T>(key: &PKeyRef<T>) -> Option<usize> {
+    match key.id() {
+        Id::ED25519 => Some(64),
+        Id::ED448 => Some(114),
+        _ => None
+    }
+}
+
+// Verification accepts an SPKI public key, an X.509 certificate or the raw 32 or 57
+// public key bytes, or falls back to the public half of an encoded private key so
+// existing callers holding only the private key keep working. Raw bytes are never
+// read as an EdDSA seed here; that is eddsa_key, on the signing side. Like
+// private_key, PEM is read with an empty passphrase callback so an encrypted key
+// fails instead of OpenSSL prompting on the terminal.
+pub fn public_key(key: &[u8]) -> Result<PKey<Public>, ErrorStack> {
+    let public = if key.first() == Some(&DER_SEQUENCE) {
+        PKey::public_key_from_der(key)
+    } else {
+        PKey::public_key_from_pem_callback(key, |_| Ok(0))
+    };
+    if let Ok(public) = public {
+        return Ok(public);
+    }
+    if let Ok(public) = certificate_key(key) {
+        return Ok(public);
+    }
+    match key.len() {
+        ED25519_KEY_LEN => return PKey::public_key_from_raw_bytes(key, Id::ED25519),
+        ED448_KEY_LEN => return PKey::public_key_from_raw_bytes(key, Id::ED448),
+        _ => {}
+    }
+    let private = try!(private_key(key));
+    PKey::public_key_from_der(&try!(private.public_key_to_der()))
+}
+
+// True for PEM text and for DER SPKI, certificates and private keys. Raw EdDSA
+// bytes are not an encoding: any 32 or 57 bytes load as a key.
+pub fn is_encoded_key(key: &[u8]) -> bool {
+    if key.starts_with(b"-----BEGIN") {
+        return true;
+    }
+    key.first() == Some(&DER_SEQUENCE) &&
+        (PKey::public_key_from_der(key).is_ok() || X509::from_der(key).is_ok() || PKey::private_key_from_der(key).is_ok())
+}
+
+pub fn certificate_key(cert: &[u8]) -> Result<PKey<Public>, ErrorStack> {
+    let cert = try!(if cert.first() == Some(&DER_SEQUENCE) {
+        X509::from_der(cert)
+    } else {
+        X509::from_pem(cert)
+    });
+    cert.public_key()
+}
+
+pub fn sign_eddsa(key: &PKey<Private>, payload: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    let mut signer = try!(Signer::new_without_digest(key));
+    signer.sign_oneshot_to_vec(payload)
+}
+
+pub fn verify_eddsa(key: &PKey<Public>, signature: &[u8], payload: &[u8]) -> Result<bool, ErrorStack> {
+    if eddsa_signature_len(key) != Some(signature.len()) {
+        return Ok(false);
+    }
+    let mut verifier = try!(Verifier::new_without_digest(key));
+    Ok(verifier.verify_oneshot(signature, payload).unwrap_or(false))
+}
+
+const SECP256K1_COORDINATE_LEN: usize = 32;
+
+pub fn curve<T: HasParams>(key: &PKeyRef<T>) -> Option<Nid> {
+    key.ec_key().ok().and_then(|ec_key| ec_key.group().curve_name())
+}
+
+pub fn curve_name(nid: Nid) -> String {
+    if nid == Nid::X9_62_PRIME256V1 {
+        "P-256".to_owned()
+    } else if nid == Nid::SECP384R1 {
+        "P-384".to_owned()
+    } else if nid == Nid::SECP521R1 {
+        "P-521".to_owned()
+    } else {
+        nid.short_name().unwrap_or("unknown").to_owned()
+    }
+}
+
+pub fn is_secp256k1<T: HasParams>(key: &PKeyRef<T>) -> bool {
+    curve(key) == Some(Nid::SECP256K1)
+}
+
+// OpenSSL emits and expects DER `SEQUENCE { r, s }`; RFC 7518 3.4 puts the two
+// integers on the wire as fixed-length big-endian values concatenated.
+pub fn ecdsa_der_to_raw(der: &[u8], coordinate_len: usize) -> Result<Vec<u8>, ErrorStack> {
+    let signature = try!(EcdsaSig::from_der(der));
+    let mut raw = try!(signature.r().to_vec_padded(coordinate_len as i32));
+    raw.extend(try!(signature.s().to_vec_padded(coordinate_len as i32)));
+    Ok(raw)
+}
+
+pub fn ecdsa_raw_to_der(raw: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    let (r, s) = raw.split_at(raw.len() / 2);
+    let signature = try!(EcdsaSig::from_private_components(try!(BigNum::from_slice(r)), try!(BigNum::from_slice(s))));
+    signature.to_der()
+}
+
+pub fn sign_ecdsa(digest: MessageDigest, coordinate_len: usize, key: &PKey<Private>, payload: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    let mut signer = try!(Signer::new(digest, key));
+    try!(signer.update(payload));
+    ecdsa_der_to_raw(&try!(signer.sign_to_vec()), coordinate_len)
+}
+
+pub fn verify_ecdsa(digest: MessageDigest, coordinate_len: usize, key: &PKey<Public>, signature: &[u8], payload: &[u8]) -> Result<bool, ErrorStack> {
+    if signature.len() != 2 * coordinate_len {
+        return Ok(false);
+    }
+    let der = try!(ecdsa_raw_to_der(signature));
+    let mut verifier = try!(Verifier::new(digest, key));
+    try!(verifier.update(payload));
+    Ok(verifier.verify(&der).unwrap_or(false))
+}
+
+pub fn sign_es256k(key: &PKey<Private>, payload: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    sign_ecdsa(MessageDigest::sha256(), SECP256K1_COORDINATE_LEN, key, payload)
+}
+
+pub fn verify_es256k(key: &PKey<Public>, signature: &[u8], payload: &[u8]) -> Result<bool, ErrorStack> {
+    verify_ecdsa(MessageDigest::sha256(), SECP256K1_COORDINATE_LEN, key, signature, payload)
+}
+
+pub fn hmac_256(key: &[u8], payload: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    hmac(MessageDigest::sha256(), key, payload)
+}
+
+pub fn hmac_384(key: &[u8], payload: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    hmac(MessageDigest::sha384(), key, payload)
+}
+
+pub fn hmac_512(key: &[u8], payload: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    hmac(MessageDigest::sha512(), key, payload)
+}
+
+// Masks are 0xff when c lies in [low, high] and 0 otherwise, computed without
+// branches or table lookups so decoding time does not depend on the input.
+fn in_range(c: i32, low: i32, high: i32) -> i32 {
+    (((low - 1 - c) & (c - high - 1)) >> 8) & 0xff
+}
+
+fn decode_char(c: u8) -> (u32, u32) {
+    let c = c as i32;
+    let upper = in_range(c, 'A' as i32, 'Z' as i32);
+    let lower = in_range(c, 'a' as i32, 'z' as i32);
+    let digit = in_range(c, '0' as i32, '9' as i32);
+    let dash = in_range(c, '-' as i32, '-' as i32);
+    let underscore = in_range(c, '_' as i32, '_' as i32);
+    let value = (upper & (c - 65)) | (lower & (c - 71)) | (digit & (c + 4)) | (dash & 62) | (underscore & 63);
+    (value as u32 & 0x3f, (upper | lower | digit | dash | underscore) as u32 & 1)
+}
+
+// Unpadded base64url for signature material. Only the input length may affect
+// the time taken; non-canonical encodings (stray trailing bits) are rejected so
+// each signature has exactly one accepted spelling.
+pub fn base64url_decode_ct(input: &str) -> Option<Vec<u8>> {
+    let input = input.as_bytes();
+    if input.len() % 4 == 1 {
+        return None;
+    }
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    let mut valid = 1u32;
+    for &c in input {
+        let (value, ok) = decode_char(c);
+        valid &= ok;
+        buffer = (buffer << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    let canonical = ((buffer | buffer.wrapping_neg()) >> 31) ^ 1;
+    if valid & canonical == 1 {
+        Some(output)
+    } else {
+        None
+    }
+}
+
+// Compares the decoded MAC rather than its encoding, in constant time.
+pub fn verify_hmac(digest: MessageDigest, key: &[u8], payload: &[u8], signature: &str) -> Result<bool, ErrorStack> {
+    let expected = try!(hmac(digest, key, payload));
+    Ok(match base64url_decode_ct(signature) {
+        Some(ref signature) if signature.len() == expected.len() => memcmp::eq(signature, &expected),
+        _ => false
+    })
+}
+
+// OpenSSL refuses some keys, an empty one among them; that is an error, not a panic.
+fn hmac(digest: MessageDigest, key: &[u8], payload: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    let key = try!(PKey::hmac(key));
+    let mut signer = try!(Signer::new(digest, &key));
+    try!(signer.update(payload));
+    signer.sign_to_vec()
+}
+
+#[test]
+fn pss_signatures_are_randomized_but_verify() {
+    use openssl::rsa::Rsa;
+
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let public = public_key(&key.public_key_to_pem().unwrap()).unwrap();
+    let first = sign_ps256(&key, b"payload").unwrap();
+    let second = sign_ps256(&key, b"payload").unwrap();
+    assert!(first != second);
+    assert!(verify_ps256(&public, first.as_slice(), b"payload").unwrap());
+    assert!(!verify_ps256(&public, first.as_slice(), b"tampered").unwrap());
+    assert!(!verify_pk256(&public, first.as_slice(), b"payload").unwrap());
+}
+
+#[test]
+fn ed25519_keys_load_from_raw_bytes_or_pem() {
+    let raw = [7u8; 32];
+    let key = eddsa_key(&raw).unwrap();
+    let pem = key.private_key_to_pem_pkcs8().unwrap();
+    let from_pem = eddsa_key(pem.as_slice()).unwrap();
+    let signature = sign_eddsa(&key, b"payload").unwrap();
+    assert_eq!(signature.len(), 64);
+    assert_eq!(signature, sign_eddsa(&from_pem, b"payload").unwrap());
+    let public = public_key(&from_pem.public_key_to_pem().unwrap()).unwrap();
+    assert!(verify_eddsa(&public, signature.as_slice(), b"payload").unwrap());
+    assert!(!verify_eddsa(&public, signature.as_slice(), b"tampered").unwrap());
+}
+
+#[test]
+fn ed448_keys_are_detected_and_sized() {
+    let key = eddsa_key(&[9u8; 57]).unwrap();
+    assert_eq!(key.id(), Id::ED448);
+    let signature = sign_eddsa(&key, b"payload").unwrap();
+    assert_eq!(signature.len(), 114);
+    let public = public_key(&key.raw_public_key().unwrap()).unwrap();
+    assert!(verify_eddsa(&public, signature.as_slice(), b"payload").unwrap());
+    assert!(!verify_eddsa(&public, &signature[..64], b"payload").unwrap());
+
+    let pem = key.private_key_to_pem_pkcs8().unwrap();
+    assert_eq!(eddsa_key(pem.as_slice()).unwrap().id(), Id::ED448);
+}
+
+#[test]
+fn es256k_signatures_are_raw_and_pinned_to_secp256k1() {
+    use openssl::ec::{EcGroup, EcKey};
+
+    let group = EcGroup::from_curve_name(Nid::SECP256K1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+    assert!(is_secp256k1(&key));
+    let signature = sign_es256k(&key, b"payload").unwrap();
+    assert_eq!(signature.len(), 64);
+    let public = public_key(&key.public_key_to_pem().unwrap()).unwrap();
+    assert!(is_secp256k1(&public));
+    assert!(verify_es256k(&public, signature.as_slice(), b"payload").unwrap());
+    assert!(!verify_es256k(&public, signature.as_slice(), b"tampered").unwrap());
+
+    let p256 = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    assert!(!is_secp256k1(&PKey::from_ec_key(EcKey::generate(&p256).unwrap()).unwrap()));
+}
+
+#[test]
+fn der_keys_load_like_pem_keys() {
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::rsa::Rsa;
+
+    let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let ec = PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::SECP256K1).unwrap()).unwrap()).unwrap();
+    for key in vec![rsa, ec] {
+        let pkcs8 = key.private_key_to_pkcs8().unwrap();
+        let loaded = private_key(&pkcs8).unwrap();
+        assert!(loaded.public_eq(&key));
+        let public = public_key(&key.public_key_to_der().unwrap()).unwrap();
+        assert!(public.public_eq(&key));
+        assert!(public_key(&pkcs8).unwrap().public_eq(&key));
+    }
+    let traditional = Rsa::generate(2048).unwrap().private_key_to_der().unwrap();
+    assert!(private_key(&traditional).is_ok());
+}
+
+#[test]
+fn encrypted_pkcs1_and_pkcs8_keys_load_with_their_passphrase() {
+    use openssl::rsa::Rsa;
+    use openssl::symm::Cipher;
+
+    let rsa = Rsa::generate(2048).unwrap();
+    let pkey = PKey::from_rsa(rsa.clone()).unwrap();
+    let expected = pkey.private_key_to_der().unwrap();
+    let encodings = vec![
+        rsa.private_key_to_pem_passphrase(Cipher::aes_256_cbc(), b"hunter2").unwrap(),
+        pkey.private_key_to_pem_pkcs8_passphrase(Cipher::aes_256_cbc(), b"hunter2").unwrap(),
+        pkey.private_key_to_pkcs8_passphrase(Cipher::aes_256_cbc(), b"hunter2").unwrap(),
+    ];
+    for encoded in encodings {
+        let loaded = private_key_with_passphrase(&encoded, Some(b"hunter2")).unwrap();
+        assert_eq!(loaded.private_key_to_der().unwrap(), expected);
+        assert!(private_key_with_passphrase(&encoded, Some(b"wrong")).is_err());
+        assert!(public_key(&encoded).is_err());
+    }
+
+    assert!(private_key_with_passphrase(&rsa.private_key_to_pem().unwrap(), None).is_ok());
+    assert!(private_key_with_passphrase(&pkey.private_key_to_pem_pkcs8().unwrap(), None).is_ok());
+}
+
+#[test]
+fn ecdsa_signatures_convert_between_der_and_raw() {
+    use openssl::ec::{EcGroup, EcKey};
+    use rustc_serialize::base64::FromBase64;
+
+    // RFC 7515 A.3: an ES256 JWS produced outside OpenSSL, verified through the conversion.
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let x = BigNum::from_slice(&"f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU".from_base64().unwrap()).unwrap();
+    let y = BigNum::from_slice(&"x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0".from_base64().unwrap()).unwrap();
+    let key = PKey::from_ec_key(EcKey::from_public_key_affine_coordinates(&group, &x, &y).unwrap()).unwrap();
+    let signing_input = "eyJhbGciOiJFUzI1NiJ9.eyJpc3MiOiJqb2UiLA0KICJleHAiOjEzMDA4MTkzODAsDQogImh0dHA6Ly9leGFtcGxlLmNvbS9pc19yb290Ijp0cnVlfQ";
+    let raw = "DtEhU3ljbEg8L38VWAfUAqOyKAM6-Xx-F4GawxaepmXFCgfTjDxw5djxLa8ISlSApmWQxfKTUJqPP3-Kg6NU1Q".from_base64().unwrap();
+
+    let der = ecdsa_raw_to_der(&raw).unwrap();
+    let mut verifier = Verifier::new(MessageDigest::sha256(), &key).unwrap();
+    verifier.update(signing_input.as_bytes()).unwrap();
+    assert!(verifier.verify(&der).unwrap());
+    assert_eq!(ecdsa_der_to_raw(&der, 32).unwrap(), raw);
+
+    // Short integers are left-padded back to the fixed coordinate length.
+    let mut small = vec![0; 31];
+    small.push(1);
+    small.extend(vec![0x7f; 32]);
+    assert_eq!(ecdsa_der_to_raw(&ecdsa_raw_to_der(&small).unwrap(), 32).unwrap(), small);
+}
+
+#[test]
+fn constant_time_decoding_matches_the_general_decoder() {
+    use rustc_serialize::base64::FromBase64;
+
+    for len in 0..66 {
+        let bytes: Vec<u8> = (0..len).map(|i| (i * 37 + 11) as u8).collect();
+        let encoded = ::jws::base64_url_encode_bytes(&bytes);
+        assert_eq!(base64url_decode_ct(&encoded), Some(bytes.clone()));
+        assert_eq!(encoded.from_base64().unwrap(), bytes);
+    }
+    assert_eq!(base64url_decode_ct("-_-_"), Some(vec![0xfb, 0xff, 0xbf]));
+    assert!(base64url_decode_ct("AB").is_none());
+    assert!(base64url_decode_ct("AAA").is_some());
+    assert!(base64url_decode_ct("AAB").is_none());
+    assert!(base64url_decode_ct("A").is_none());
+    assert!(base64url_decode_ct("AA+/").is_none());
+    assert!(base64url_decode_ct("AA==").is_none());
+    assert!(base64url_decode_ct("AA.A").is_none());
+}
+
+#[test]
+fn hmac_verification_compares_the_decoded_mac() {
+    let mac = hmac_256(b"secret", b"payload").unwrap();
+    let encoded = ::jws::base64_url_encode_bytes(&mac);
+    assert!(verify_hmac(MessageDigest::sha256(), b"secret", b"payload", &encoded).unwrap());
+    assert!(!verify_hmac(MessageDigest::sha256(), b"other", b"payload", &encoded).unwrap());
+    assert!(!verify_hmac(MessageDigest::sha256(), b"secret", b"payload", &encoded[..40]).unwrap());
+    assert!(!verify_hmac(MessageDigest::sha384(), b"secret", b"payload", &encoded).unwrap());
+}
+
+#[test]
+fn raw_eddsa_bytes_verify_as_the_public_key_not_a_seed() {
+    let private = eddsa_key(&[6u8; 32]).unwrap();
+    let raw_public = private.raw_public_key().unwrap();
+    let signature = sign_eddsa(&private, b"payload").unwrap();
+
+    let public = public_key(&raw_public).unwrap();
+    assert_eq!(public.raw_public_key().unwrap(), raw_public);
+    assert!(verify_eddsa(&public, &signature, b"payload").unwrap());
+    assert_ne!(public_key(&[6u8; 32]).unwrap().raw_public_key().unwrap(), raw_public);
 }
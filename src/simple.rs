@@ -0,0 +1,438 @@
+#![allow(dead_code)]
+
+use openssl::error::ErrorStack;
+use openssl::pkey::{Id, PKey, Public};
+use std::fmt;
+use claims::Claims;
+use clock::{Clock, SystemClock};
+use jws::JWS;
+use jws_header::{Header, ALGORITHM};
+use signing;
+use validation::{Validation, MIN_RSA_BITS};
+use error::{Error, Result};
+
+// Key material typed by algorithm family. A key can only be bound to the
+// algorithms of its own family, so an RSA public key can never be used as an
+// HMAC secret (the RS256/HS256 confusion attack). The trait is sealed: a key
+// family defined outside this module could claim any algorithm it liked.
+pub trait KeyFamily: sealed::Sealed {
+    fn supports(&self, algorithm: &ALGORITHM) -> bool;
+    fn as_bytes(&self) -> &[u8];
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::HmacSecret {}
+    impl Sealed for super::RsaKey {}
+    impl Sealed for super::EcKey {}
+    impl Sealed for super::EdKey {}
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct HmacSecret(Vec<u8>);
+
+impl HmacSecret {
+    // Rejects PEM and DER keys, which are almost certainly a public key passed by mistake.
+    pub fn new(secret: &[u8]) -> Result<HmacSecret> {
+        if signing::is_encoded_key(secret) {
+            return Err(Error::KeyAlgorithmMismatch { alg: ALGORITHM::HS256 });
+        }
+        Ok(HmacSecret(secret.to_vec()))
+    }
+}
+
+impl KeyFamily for HmacSecret {
+    fn supports(&self, algorithm: &ALGORITHM) -> bool {
+        match *algorithm {
+            ALGORITHM::HS256 | ALGORITHM::HS384 | ALGORITHM::HS512 => true,
+            _ => false
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// Error::private_key_parse when the key is bound to sign, Error::public_key_parse
+// when it is bound to verify, so the hint matches what the caller was loading.
+type ParseError = fn(&[u8], ErrorStack) -> Error;
+
+fn key_of_type(key: &[u8], ids: &[Id], alg: ALGORITHM, parse_error: ParseError) -> Result<Vec<u8>> {
+    let public = try!(signing::public_key(key).map_err(|err| parse_error(key, err)));
+    if !ids.contains(&public.id()) {
+        return Err(Error::KeyAlgorithmMismatch { alg: alg });
+    }
+    Ok(key.to_vec())
+}
+
+// A PEM or DER RSA key: private to sign, public, certificate or private to verify.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RsaKey(Vec<u8>);
+
+impl RsaKey {
+    pub fn new(key: &[u8]) -> Result<RsaKey> {
+        RsaKey::parse(key, Error::public_key_parse)
+    }
+
+    fn parse(key: &[u8], parse_error: ParseError) -> Result<RsaKey> {
+        key_of_type(key, &[Id::RSA, Id::RSA_PSS], ALGORITHM::RS256, parse_error).map(RsaKey)
+    }
+}
+
+impl KeyFamily for RsaKey {
+    fn supports(&self, algorithm: &ALGORITHM) -> bool {
+        match *algorithm {
+            ALGORITHM::RS256 | ALGORITHM::RS384 | ALGORITHM::RS512 |
+            ALGORITHM::PS256 | ALGORITHM::PS384 | ALGORITHM::PS512 => true,
+            _ => false
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// A PEM or DER EC key; the curve is still checked against the algorithm when used.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EcKey(Vec<u8>);
+
+impl EcKey {
+    pub fn new(key: &[u8]) -> Result<EcKey> {
+        EcKey::parse(key, Error::public_key_parse)
+    }
+
+    fn parse(key: &[u8], parse_error: ParseError) -> Result<EcKey> {
+        key_of_type(key, &[Id::EC], ALGORITHM::ES256, parse_error).map(EcKey)
+    }
+}
+
+impl KeyFamily for EcKey {
+    fn supports(&self, algorithm: &ALGORITHM) -> bool {
+        match *algorithm {
+            ALGORITHM::ES256 | ALGORITHM::ES384 | ALGORITHM::ES512 | ALGORITHM::ES256K => true,
+            _ => false
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// A PEM or DER Ed25519 or Ed448 key, or raw 32 or 57 bytes: the private key to
+// sign, the public key to verify.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EdKey(Vec<u8>);
+
+impl EdKey {
+    pub fn new(key: &[u8]) -> Result<EdKey> {
+        EdKey::parse(key, Error::public_key_parse)
+    }
+
+    fn parse(key: &[u8], parse_error: ParseError) -> Result<EdKey> {
+        key_of_type(key, &[Id::ED25519, Id::ED448], ALGORITHM::EdDSA, parse_error).map(EdKey)
+    }
+}
+
+impl KeyFamily for EdKey {
+    fn supports(&self, algorithm: &ALGORITHM) -> bool {
+        *algorithm == ALGORITHM::EdDSA
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+fn bind<K: KeyFamily>(key: &K, algorithm: ALGORITHM) -> Result<(Vec<u8>, ALGORITHM)> {
+    if !key.supports(&algorithm) {
+        return Err(Error::KeyAlgorithmMismatch { alg: algorithm });
+    }
+    Ok((key.as_bytes().to_vec(), algorithm))
+}
+
+// Raw key bytes are typed by the family of the algorithm they are used with,
+// so an RSA public key offered as an HS256 secret fails here too.
+fn bind_raw(key: &[u8], algorithm: ALGORITHM, parse_error: ParseError) -> Result<(Vec<u8>, ALGORITHM)> {
+    let typed: Box<dyn KeyFamily> = match algorithm {
+        ALGORITHM::HS256 | ALGORITHM::HS384 | ALGORITHM::HS512 => Box::new(try!(HmacSecret::new(key))),
+        ALGORITHM::RS256 | ALGORITHM::RS384 | ALGORITHM::RS512 |
+        ALGORITHM::PS256 | ALGORITHM::PS384 | ALGORITHM::PS512 => Box::new(try!(RsaKey::parse(key, parse_error))),
+        ALGORITHM::ES256 | ALGORITHM::ES384 | ALGORITHM::ES512 | ALGORITHM::ES256K => Box::new(try!(EcKey::parse(key, parse_error))),
+        ALGORITHM::EdDSA => Box::new(try!(EdKey::parse(key, parse_error))),
+        _ => return Err(Error::UnsupportedAlgorithm(algorithm.name().to_owned()))
+    };
+    if !typed.supports(&algorithm) {
+        return Err(Error::KeyAlgorithmMismatch { alg: algorithm });
+    }
+    Ok((key.to_vec(), algorithm))
+}
+
+// What JWS::encode signs with.
+pub fn encoding_key(key: &[u8], algorithm: ALGORITHM) -> Result<EncodingKey> {
+    let (key, algorithm) = try!(bind_raw(key, algorithm, Error::private_key_parse));
+    Ok(EncodingKey::bound(key, algorithm))
+}
+
+// What JWS::decode_jwt and the other raw-bytes decoders verify with.
+pub fn decoding_key(key: &[u8], algorithm: ALGORITHM) -> Result<DecodingKey> {
+    let (key, algorithm) = try!(bind_raw(key, algorithm, Error::public_key_parse));
+    DecodingKey::staged(key, algorithm)
+}
+
+// A signing key bound to the one algorithm it is used with. It can only be
+// built from a key of the algorithm's family.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EncodingKey {
+    key: Vec<u8>,
+    algorithm: ALGORITHM,
+    min_rsa_bits: usize,
+    allow_short_hmac_key: bool,
+}
+
+impl EncodingKey {
+    fn bound(key: Vec<u8>, algorithm: ALGORITHM) -> EncodingKey {
+        EncodingKey {
+            key: key,
+            algorithm: algorithm,
+            min_rsa_bits: MIN_RSA_BITS,
+            allow_short_hmac_key: false,
+        }
+    }
+
+    pub fn from_key<K: KeyFamily>(key: &K, algorithm: ALGORITHM) -> Result<EncodingKey> {
+        let (key, algorithm) = try!(bind(key, algorithm));
+        Ok(EncodingKey::bound(key, algorithm))
+    }
+
+    pub fn algorithm(&self) -> &ALGORITHM {
+        &self.algorithm
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.key
+    }
+
+    // RSA keys under 2048 bits are refused when signing; this lowers the bar,
+    // only for a legacy key that a peer cannot yet replace.
+    pub fn with_min_rsa_bits(mut self, bits: usize) -> EncodingKey {
+        self.min_rsa_bits = bits;
+        self
+    }
+
+    pub fn min_rsa_bits(&self) -> usize {
+        self.min_rsa_bits
+    }
+
+    // Signs with an HMAC secret shorter than the hash output, which is
+    // otherwise rejected. Only for peers that cannot yet rotate their secret.
+    pub fn allow_short_hmac_key(mut self) -> EncodingKey {
+        self.allow_short_hmac_key = true;
+        self
+    }
+
+    pub fn allows_short_hmac_key(&self) -> bool {
+        self.allow_short_hmac_key
+    }
+}
+
+// A verification key bound to the one algorithm it accepts, so the token's
+// header never chooses how it is checked. Asymmetric keys are parsed once here
+// and the parsed key is reused by every verification.
+#[derive(Clone)]
+pub struct DecodingKey {
+    key: Vec<u8>,
+    algorithm: ALGORITHM,
+    public: Option<PKey<Public>>,
+}
+
+impl DecodingKey {
+    fn staged(key: Vec<u8>, algorithm: ALGORITHM) -> Result<DecodingKey> {
+        let public = match algorithm {
+            ALGORITHM::HS256 | ALGORITHM::HS384 | ALGORITHM::HS512 => None,
+            _ => Some(try!(signing::public_key(&key).map_err(|err| Error::public_key_parse(&key, err))))
+        };
+        Ok(DecodingKey { key: key, algorithm: algorithm, public: public })
+    }
+
+    pub fn from_key<K: KeyFamily>(key: &K, algorithm: ALGORITHM) -> Result<DecodingKey> {
+        let (key, algorithm) = try!(bind(key, algorithm));
+        DecodingKey::staged(key, algorithm)
+    }
+
+    pub fn algorithm(&self) -> &ALGORITHM {
+        &self.algorithm
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.key
+    }
+
+    // The parsed public key; None for HMAC secrets.
+    pub fn public_key(&self) -> Option<&PKey<Public>> {
+        self.public.as_ref()
+    }
+}
+
+impl PartialEq for DecodingKey {
+    fn eq(&self, other: &DecodingKey) -> bool {
+        self.key == other.key && self.algorithm == other.algorithm
+    }
+}
+
+impl fmt::Debug for DecodingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DecodingKey")
+            .field("key", &self.key)
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+/// Signs `claims` as a compact JWT with a default header naming the key's algorithm.
+/// HMAC secrets shorter than the hash output and RSA keys under 2048 bits are rejected.
+pub fn sign(claims: Claims, key: &EncodingKey) -> Result<String> {
+    let mut header = Header::new();
+    header.alg = key.algorithm.clone();
+    JWS::from_claims(header, claims).encode_with_key(key)
+}
+
+/// Verifies `token` with `key` and checks it against `validation`.
+pub fn verify(token: &str, key: &DecodingKey, validation: &Validation) -> Result<Claims> {
+    let (_, claims) = try!(validation.decode_with_key_at(token, key, SystemClock.now()));
+    Ok(claims)
+}
+
+#[test]
+fn claims_round_trip_through_sign_and_verify() {
+    let mut claims = Claims::new();
+    claims.iss = Some("https://issuer.example".to_owned());
+    claims.set("role", "admin");
+    let secret = HmacSecret::new(b"a 64-byte secret, long enough for the HS384 and HS512 tests, too").unwrap();
+    let token = sign(claims.clone(), &EncodingKey::from_key(&secret, ALGORITHM::HS256).unwrap()).unwrap();
+
+    let mut validation = Validation::new();
+    validation.issuer = Some("https://issuer.example".to_owned());
+    assert_eq!(verify(&token, &DecodingKey::from_key(&secret, ALGORITHM::HS256).unwrap(), &validation).unwrap(), claims);
+    let wrong = HmacSecret::new(b"another 32-byte secret for HS256").unwrap();
+    assert!(verify(&token, &DecodingKey::from_key(&wrong, ALGORITHM::HS256).unwrap(), &validation).is_err());
+    assert!(verify(&token, &DecodingKey::from_key(&secret, ALGORITHM::HS512).unwrap(), &validation).is_err());
+}
+
+#[test]
+fn verify_rejects_expired_and_premature_tokens() {
+    let secret = HmacSecret::new(::TEST_HS256_SECRET).unwrap();
+    let encoding = EncodingKey::from_key(&secret, ALGORITHM::HS256).unwrap();
+    let decoding = DecodingKey::from_key(&secret, ALGORITHM::HS256).unwrap();
+    let mut expired = Claims::new();
+    expired.exp = Some(1000);
+    let token = sign(expired, &encoding).unwrap();
+    assert_err!(verify(&token, &decoding, &Validation::new()), Error::TokenExpired { exp: 1000, .. });
+
+    let mut premature = Claims::new();
+    premature.nbf = Some(u64::max_value() / 2);
+    let token = sign(premature, &encoding).unwrap();
+    assert_err!(verify(&token, &decoding, &Validation::new()), Error::TokenNotYetValid { .. });
+}
+
+#[test]
+fn asymmetric_keys_sign_with_their_algorithm() {
+    use openssl::ec::EcGroup;
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(::openssl::ec::EcKey::generate(&group).unwrap()).unwrap();
+    let private = EcKey::new(&key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+    let public = EcKey::new(&key.public_key_to_pem().unwrap()).unwrap();
+    let token = sign(Claims::new(), &EncodingKey::from_key(&private, ALGORITHM::ES256).unwrap()).unwrap();
+    assert!(verify(&token, &DecodingKey::from_key(&public, ALGORITHM::ES256).unwrap(), &Validation::new()).is_ok());
+
+    let ed25519 = PKey::generate_ed25519().unwrap();
+    let private = EdKey::new(&ed25519.private_key_to_pem_pkcs8().unwrap()).unwrap();
+    let public = EdKey::new(&ed25519.public_key_to_pem().unwrap()).unwrap();
+    assert!(EncodingKey::from_key(&private, ALGORITHM::ES256).is_err());
+    let token = sign(Claims::new(), &EncodingKey::from_key(&private, ALGORITHM::EdDSA).unwrap()).unwrap();
+    assert!(verify(&token, &DecodingKey::from_key(&public, ALGORITHM::EdDSA).unwrap(), &Validation::new()).is_ok());
+}
+
+#[test]
+fn typed_keys_only_bind_to_their_own_family() {
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let private = RsaKey::new(&key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+    let public_pem = key.public_key_to_pem().unwrap();
+    let public = RsaKey::new(&public_pem).unwrap();
+
+    assert_err!(DecodingKey::from_key(&public, ALGORITHM::HS256), Error::KeyAlgorithmMismatch { alg: ALGORITHM::HS256 });
+    assert!(HmacSecret::new(&public_pem).is_err());
+    assert!(HmacSecret::new(&key.public_key_to_der().unwrap()).is_err());
+    assert!(EcKey::new(&public_pem).is_err());
+    assert!(HmacSecret::new(&key.private_key_to_der().unwrap()).is_err());
+
+    let token = sign(Claims::new(), &EncodingKey::from_key(&private, ALGORITHM::PS256).unwrap()).unwrap();
+    assert!(verify(&token, &DecodingKey::from_key(&public, ALGORITHM::PS256).unwrap(), &Validation::new()).is_ok());
+
+    let secret = HmacSecret::new(b"a long random shared secret for HS384 signatures").unwrap();
+    assert!(EncodingKey::from_key(&secret, ALGORITHM::RS256).is_err());
+    let mut header = Header::new();
+    header.alg = ALGORITHM::HS384;
+    let token = JWS::from_claims(header, Claims::new()).encode_with_key(&EncodingKey::from_key(&secret, ALGORITHM::HS384).unwrap()).unwrap();
+    assert!(verify(&token, &DecodingKey::from_key(&secret, ALGORITHM::HS384).unwrap(), &Validation::new()).is_ok());
+    assert!(JWS::decode_jwt_with_key(token, &DecodingKey::from_key(&secret, ALGORITHM::HS384).unwrap(), &Validation::new()).is_ok());
+
+    // The raw-bytes entry points bind the key to the algorithm's family the same way.
+    assert_err!(JWS::decode_jwt(JWS::from_claims(Header::new(), Claims::new()).attach_signature(b"forged").unwrap(), &public_pem, ALGORITHM::HS256), Error::KeyAlgorithmMismatch { alg: ALGORITHM::HS256 });
+    assert!(JWS::from_claims(Header::new(), Claims::new()).encode(&public_pem, ALGORITHM::HS256).is_err());
+}
+
+#[test]
+fn short_rsa_keys_only_sign_when_allowed() {
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+
+    let key = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+    let private = RsaKey::new(&key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+    let public = RsaKey::new(&key.public_key_to_pem().unwrap()).unwrap();
+    assert_err!(sign(Claims::new(), &EncodingKey::from_key(&private, ALGORITHM::RS256).unwrap()), Error::WeakKey { bits: 1024, required: 2048, .. });
+    assert_err!(JWS::from_claims(Header::new(), Claims::new()).encode(&key.private_key_to_pem_pkcs8().unwrap(), ALGORITHM::RS256), Error::WeakKey { bits: 1024, required: 2048, .. });
+    let token = sign(Claims::new(), &EncodingKey::from_key(&private, ALGORITHM::RS256).unwrap().with_min_rsa_bits(1024)).unwrap();
+
+    let decoding = DecodingKey::from_key(&public, ALGORITHM::RS256).unwrap();
+    assert!(verify(&token, &decoding, &Validation::new()).is_ok());
+    let mut strict = Validation::rfc8725_strict();
+    strict.algorithms = vec![ALGORITHM::RS256];
+    assert_err!(verify(&token, &decoding, &strict), Error::WeakKey { bits: 1024, required: 2048, .. });
+}
+
+#[test]
+fn random_32_byte_secrets_are_hmac_secrets() {
+    let mut secret = [0u8; 32];
+    ::openssl::rand::rand_bytes(&mut secret).unwrap();
+    assert!(HmacSecret::new(&secret).is_ok());
+    assert!(HmacSecret::new(&[7u8; 32]).is_ok());
+}
+
+#[test]
+fn the_strict_preset_runs_validators_and_reports_every_failure() {
+    let key = HmacSecret::new(::TEST_HS256_SECRET).unwrap();
+    let mut claims = Claims::new();
+    claims.iat = Some(1);
+    let token = sign(claims, &EncodingKey::from_key(&key, ALGORITHM::HS256).unwrap()).unwrap();
+
+    let mut validation = Validation::rfc8725_strict()
+        .with_validator(|_: &Claims| Err(Error::ClaimRejected("tenant suspended".to_owned())));
+    validation.algorithms = vec![ALGORITHM::HS256];
+    validation.require_typ = false;
+    validation.max_age = Some(60);
+    validation.report_all = true;
+    assert_err!(verify(&token, &DecodingKey::from_key(&key, ALGORITHM::HS256).unwrap(), &validation), Error::ValidationFailed(err) => {
+        let reasons: Vec<&str> = err.failures().iter().map(|failure| failure.reject_reason().as_str()).collect();
+        assert_eq!(reasons, vec!["missing_claims", "expired", "invalid_claims"]);
+    });
+}
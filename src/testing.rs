@@ -0,0 +1,311 @@
+#![allow(dead_code)]
+
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use claims::Claims;
+use clock::{Clock, SystemClock};
+use entropy::{Entropy, SystemEntropy};
+use jwk::Jwk;
+use jwk_set::JwkSet;
+use jws::{JWS, base64_url_encode_bytes};
+use jws_header::{Header, ALGORITHM};
+use error::{Error, Result};
+use signing;
+
+fn signs_deterministically(alg: &ALGORITHM) -> bool {
+    match *alg {
+        ALGORITHM::ES256 | ALGORITHM::ES384 | ALGORITHM::ES512 | ALGORITHM::ES256K |
+        ALGORITHM::PS256 | ALGORITHM::PS384 | ALGORITHM::PS512 => false,
+        _ => true
+    }
+}
+
+// Mints RS256 tokens from a freshly generated key by default; `with_key`
+// takes an RSA or EC key so tests can pin the key or pick ES* instead.
+pub struct FakeIssuer<C = SystemClock, E = SystemEntropy> {
+    pub issuer: String,
+    pub audience: String,
+    pub kid: String,
+    pub alg: ALGORITHM,
+    key: Vec<u8>,
+    jwk: Jwk,
+    clock: C,
+    entropy: E,
+}
+
+impl FakeIssuer {
+    pub fn new(issuer: &str, audience: &str) -> Result<FakeIssuer> {
+        FakeIssuer::with_sources(issuer, audience, SystemClock, SystemEntropy)
+    }
+}
+
+impl<C: Clock, E: Entropy> FakeIssuer<C, E> {
+    pub fn with_sources(issuer: &str, audience: &str, clock: C, entropy: E) -> Result<FakeIssuer<C, E>> {
+        let key = try!(try!(PKey::from_rsa(try!(Rsa::generate(2048)))).private_key_to_pkcs8());
+        FakeIssuer::with_key(issuer, audience, &key, ALGORITHM::RS256, clock, entropy)
+    }
+
+    // Seeded entropy promises golden tokens, which ES* and PS* signatures cannot give.
+    pub fn with_key(issuer: &str, audience: &str, key: &[u8], alg: ALGORITHM, clock: C, entropy: E) -> Result<FakeIssuer<C, E>> {
+        if entropy.is_seeded() && !signs_deterministically(&alg) {
+            return Err(Error::InvalidConfig(format!("{} signatures are randomized, so seeded entropy cannot repeat them", alg.name())));
+        }
+        let kid = base64_url_encode_bytes(entropy.random_bytes(8).as_slice());
+        let mut jwk = match try!(Jwk::from_key(key)).to_public() {
+            Some(jwk) => jwk,
+            None => return Err(Error::KeyAlgorithmMismatch { alg: alg })
+        };
+        jwk.kid = Some(kid.clone());
+        jwk.key_use = Some("sig".to_owned());
+        jwk.alg = Some(alg.name().to_owned());
+        Ok(FakeIssuer {
+            issuer: issuer.to_owned(),
+            audience: audience.to_owned(),
+            kid: kid,
+            alg: alg,
+            key: key.to_vec(),
+            jwk: jwk,
+            clock: clock,
+            entropy: entropy,
+        })
+    }
+
+    // The public key as SPKI DER, ready for `decode_jwt`.
+    pub fn public_key(&self) -> Result<Vec<u8>> {
+        self.jwk.to_key()
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet::new(vec![self.jwk.clone()])
+    }
+
+    pub fn claims(&self) -> Claims {
+        let now = self.clock.now();
+        let mut claims = Claims::new();
+        claims.iss = Some(self.issuer.clone());
+        claims.sub = Some("fake-subject".to_owned());
+        claims.aud = Some(self.audience.as_str().into());
+        claims.iat = Some(now);
+        claims.nbf = Some(now);
+        claims.exp = Some(now + 300);
+        claims.jti = Some(base64_url_encode_bytes(self.entropy.random_bytes(16).as_slice()));
+        claims
+    }
+
+    pub fn mint(&self, claims: Claims) -> Result<String> {
+        self.mint_with_kid(&self.kid, claims)
+    }
+
+    fn mint_with_kid(&self, kid: &str, claims: Claims) -> Result<String> {
+        let mut header = Header::new();
+        header.alg = self.alg.clone();
+        header.kid = Some(kid.to_owned());
+        JWS::from_claims(header, claims).encode(self.key.as_slice(), self.alg.clone())
+    }
+
+    pub fn valid(&self) -> Result<String> {
+        self.mint(self.claims())
+    }
+
+    pub fn expired(&self) -> Result<String> {
+        let now = self.clock.now();
+        let mut claims = self.claims();
+        claims.iat = Some(now - 600);
+        claims.nbf = claims.iat;
+        claims.exp = Some(now - 300);
+        self.mint(claims)
+    }
+
+    pub fn not_yet_valid(&self) -> Result<String> {
+        let now = self.clock.now();
+        let mut claims = self.claims();
+        claims.nbf = Some(now + 300);
+        claims.exp = Some(now + 600);
+        self.mint(claims)
+    }
+
+    pub fn wrong_audience(&self) -> Result<String> {
+        let mut claims = self.claims();
+        claims.aud = Some(format!("not-{}", self.audience).into());
+        self.mint(claims)
+    }
+
+    pub fn unknown_kid(&self) -> Result<String> {
+        self.mint_with_kid("unknown-kid", self.claims())
+    }
+
+    // A well-formed token whose signature is random bytes of the right length.
+    pub fn bad_signature(&self) -> Result<String> {
+        let token = try!(self.valid());
+        let dot = token.rfind('.').unwrap_or(token.len());
+        let len = signing::base64url_decode_ct(&token[dot + 1..]).map(|signature| signature.len()).unwrap_or(256);
+        Ok(format!("{}.{}", &token[..dot], base64_url_encode_bytes(self.entropy.random_bytes(len).as_slice())))
+    }
+}
+
+// Fixtures shared by the crate's own tests.
+
+#[cfg(test)]
+pub struct StepClock(pub ::std::sync::atomic::AtomicUsize);
+
+#[cfg(test)]
+impl<'a> Clock for &'a StepClock {
+    fn now(&self) -> u64 {
+        self.0.load(::std::sync::atomic::Ordering::SeqCst) as u64
+    }
+}
+
+#[cfg(test)]
+pub fn hs256_decoding_key() -> ::simple::DecodingKey {
+    ::simple::decoding_key(::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap()
+}
+
+#[cfg(test)]
+pub fn token_expiring_at(exp: Option<u64>) -> String {
+    let mut claims = Claims::new();
+    claims.exp = exp;
+    JWS::from_claims(Header::new(), claims).encode(::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap()
+}
+
+#[cfg(test)]
+pub fn issued_by(issuer: &str, key: &[u8], alg: ALGORITHM) -> String {
+    let mut header = Header::new();
+    header.alg = alg.clone();
+    let mut claims = Claims::new();
+    claims.iss = Some(issuer.to_owned());
+    claims.aud = Some("api".into());
+    JWS::from_claims(header, claims).encode(key, alg).unwrap()
+}
+
+#[cfg(test)]
+pub fn issuer_registry() -> ::issuer_registry::IssuerRegistry {
+    use issuer_registry::{IssuerPolicy, IssuerRegistry};
+
+    let mut defaults = ::validation::Validation::new();
+    defaults.algorithms = vec![ALGORITHM::RS256, ALGORITHM::ES256];
+    defaults.audience = Some("api".to_owned());
+    let mut registry = IssuerRegistry::new(defaults);
+    registry.register("legacy", IssuerPolicy { algorithms: Some(vec![ALGORITHM::HS256]), ..IssuerPolicy::new() });
+    registry.register("modern", IssuerPolicy { algorithms: Some(vec![ALGORITHM::EdDSA]), ..IssuerPolicy::new() });
+    registry.register("partner", IssuerPolicy { min_rsa_bits: Some(3072), ..IssuerPolicy::new() });
+    registry
+}
+
+// An HS256 token over any JSON body, so malformed ID tokens can be built too.
+#[cfg(test)]
+pub fn id_token(claims: ::serde_json::Value) -> String {
+    let mut header = Header::new();
+    header.alg = ALGORITHM::HS256;
+    JWS::from_custom(header, ::serde_json::to_vec(&claims).unwrap()).encode(::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap()
+}
+
+#[cfg(test)]
+pub fn id_token_claims(aud: ::serde_json::Value) -> ::serde_json::Value {
+    use serde_json::Value;
+
+    let mut claims = ::std::collections::BTreeMap::new();
+    claims.insert("iss".to_owned(), Value::String("https://login.example.com".to_owned()));
+    claims.insert("sub".to_owned(), Value::String("user-1".to_owned()));
+    claims.insert("aud".to_owned(), aud);
+    claims.insert("exp".to_owned(), Value::U64(2000));
+    claims.insert("iat".to_owned(), Value::U64(1000));
+    claims.insert("nonce".to_owned(), Value::String("n-0S6".to_owned()));
+    Value::Object(claims)
+}
+
+#[cfg(test)]
+pub fn id_token_validator() -> ::id_token::IdTokenValidator {
+    let mut validator = ::id_token::IdTokenValidator::new("https://login.example.com", "client-a");
+    validator.validation.algorithms = vec![ALGORITHM::HS256];
+    validator
+}
+
+#[test]
+fn fake_issuer_mints_tokens_with_the_requested_defects() {
+    let issuer = FakeIssuer::new("https://issuer.test", "api").unwrap();
+    let now = SystemClock.now();
+    let key = issuer.public_key().unwrap();
+    let valid = JWS::decode_jwt(issuer.valid().unwrap(), &key, ALGORITHM::RS256).unwrap();
+    assert_eq!(valid.0.kid, Some(issuer.kid.clone()));
+    assert_eq!(valid.1.aud, Some("api".into()));
+
+    let expired = JWS::decode_jwt_with_validation(issuer.expired().unwrap(), &key, ALGORITHM::RS256, &::validation::Validation::signature_only()).unwrap();
+    assert!(expired.1.exp.unwrap() < now);
+    assert!(JWS::decode_jwt(issuer.expired().unwrap(), &key, ALGORITHM::RS256).is_err());
+
+    let wrong_aud = JWS::decode_jwt(issuer.wrong_audience().unwrap(), &key, ALGORITHM::RS256).unwrap();
+    assert_eq!(wrong_aud.1.aud, Some("not-api".into()));
+
+    let unknown = JWS::decode_jwt(issuer.unknown_kid().unwrap(), &key, ALGORITHM::RS256).unwrap();
+    assert_eq!(unknown.0.kid, Some("unknown-kid".to_owned()));
+
+    assert!(JWS::decode_jwt(issuer.bad_signature().unwrap(), &key, ALGORITHM::RS256).is_err());
+}
+
+#[test]
+fn the_key_set_verifies_tokens_by_kid() {
+    let issuer = FakeIssuer::new("https://issuer.test", "api").unwrap();
+    let jwks = issuer.jwks();
+    assert!(!jwks.keys()[0].is_private());
+    assert_eq!(jwks.keys()[0].alg, Some("RS256".to_owned()));
+    assert!(jwks.decode_jwt(issuer.valid().unwrap(), ALGORITHM::RS256).is_ok());
+    assert_err!(jwks.decode_jwt(issuer.unknown_kid().unwrap(), ALGORITHM::RS256), Error::UnknownKey { .. });
+}
+
+#[test]
+fn ec_keys_and_symmetric_secrets() {
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+
+    let ec = PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap()).unwrap();
+    let issuer = FakeIssuer::with_key("https://issuer.test", "api", &ec.private_key_to_pkcs8().unwrap(), ALGORITHM::ES256, SystemClock, SystemEntropy).unwrap();
+    assert!(issuer.jwks().decode_jwt(issuer.valid().unwrap(), ALGORITHM::ES256).is_ok());
+    assert!(issuer.jwks().decode_jwt(issuer.bad_signature().unwrap(), ALGORITHM::ES256).is_err());
+
+    assert!(FakeIssuer::with_key("https://issuer.test", "api", b"a shared secret", ALGORITHM::HS256, SystemClock, SystemEntropy).is_err());
+}
+
+#[test]
+fn fixed_sources_and_a_pinned_key_produce_byte_identical_tokens() {
+    use clock::FixedClock;
+    use entropy::SeededEntropy;
+
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap().private_key_to_pkcs8().unwrap();
+    let first = FakeIssuer::with_key("https://issuer.test", "api", &key, ALGORITHM::RS256, FixedClock(1457396280), SeededEntropy::new(b"golden")).unwrap();
+    let second = FakeIssuer::with_key("https://issuer.test", "api", &key, ALGORITHM::RS256, FixedClock(1457396280), SeededEntropy::new(b"golden")).unwrap();
+    assert_eq!(first.valid().unwrap(), second.valid().unwrap());
+    assert_eq!(first.expired().unwrap(), second.expired().unwrap());
+}
+
+#[test]
+fn only_deterministic_algorithms_give_golden_tokens() {
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use clock::FixedClock;
+    use entropy::SeededEntropy;
+
+    let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap().private_key_to_pkcs8().unwrap();
+    let ec = PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap()).unwrap().private_key_to_pkcs8().unwrap();
+    let ed = PKey::generate_ed25519().unwrap().private_key_to_pkcs8().unwrap();
+    let mint = |key: &[u8], alg: ALGORITHM| {
+        let issuer = FakeIssuer::with_key("https://issuer.test", "api", key, alg, FixedClock(1457396280), SeededEntropy::new(b"golden")).unwrap();
+        let token = issuer.valid().unwrap();
+        assert!(JWS::decode_jwt_with_validation(token.clone(), &issuer.public_key().unwrap(), issuer.alg.clone(), &::validation::Validation::signature_only()).is_ok());
+        token
+    };
+    assert_eq!(mint(&rsa, ALGORITHM::RS256), mint(&rsa, ALGORITHM::RS256));
+    for &(ref key, ref alg) in &[(&rsa, ALGORITHM::PS256), (&ec, ALGORITHM::ES256)] {
+        match FakeIssuer::with_key("https://issuer.test", "api", key, alg.clone(), FixedClock(1457396280), SeededEntropy::new(b"golden")) {
+            Err(Error::InvalidConfig(_)) => {},
+            Err(other) => panic!("expected InvalidConfig, got {:?}", other),
+            Ok(_) => panic!("expected InvalidConfig for {:?}", alg)
+        }
+    }
+    assert!(FakeIssuer::with_key("https://issuer.test", "api", &ec, ALGORITHM::ES256, FixedClock(1457396280), SystemEntropy).is_ok());
+
+    // Ed25519 keys have no JWK form here, so the EdDSA case signs directly.
+    let mut header = Header::new();
+    header.alg = ALGORITHM::EdDSA;
+    let jws = JWS::from_claims(header, Claims::new());
+    assert_eq!(jws.encode(&ed, ALGORITHM::EdDSA).unwrap(), jws.encode(&ed, ALGORITHM::EdDSA).unwrap());
+}
@@ -0,0 +1,255 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use openssl::sha::{sha256, Sha256};
+use claims::Claims;
+use clock::{Clock, SystemClock};
+use jws::JWS;
+use jws_header::{Header, ALGORITHM};
+use validation::Validation;
+use error::Result;
+#[cfg(test)]
+use testing::{StepClock, token_expiring_at};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    // Lookups that waited for another thread to verify the same token.
+    pub coalesced: u64,
+    pub evictions: u64,
+}
+
+#[derive(Debug)]
+enum Slot {
+    Pending,
+    Ready { verified: Box<(Header, Claims)>, expires: u64 },
+}
+
+#[derive(Debug, Default)]
+struct State {
+    slots: HashMap<[u8; 32], Slot>,
+    stats: CacheStats,
+}
+
+// Entries are dropped at exp, so an expired token is never served. Nothing is
+// cached while the validation has a replay store, since every use of a token
+// must reach the store.
+#[derive(Debug)]
+pub struct VerifiedTokenCache<C = SystemClock> {
+    pub max_ttl: u64,
+    pub validation: Validation,
+    pub max_entries: usize,
+    clock: C,
+    state: Mutex<State>,
+    verified: Condvar,
+}
+
+// Clears a Pending slot and wakes its waiters when dropped, so a panic while
+// verifying does not leave other lookups of the token waiting forever.
+struct PendingSlot<'a, C: Clock + 'a> {
+    cache: &'a VerifiedTokenCache<C>,
+    key: [u8; 32],
+}
+
+impl<'a, C: Clock + 'a> Drop for PendingSlot<'a, C> {
+    fn drop(&mut self) {
+        let mut state = self.cache.lock();
+        if let Some(&Slot::Pending) = state.slots.get(&self.key) {
+            state.slots.remove(&self.key);
+        }
+        drop(state);
+        self.cache.verified.notify_all();
+    }
+}
+
+// The key and algorithm are part of the fingerprint, so a token verified
+// under one key is never served to a caller checking it against another.
+fn fingerprint(token: &str, secret: &[u8], algorithm: &ALGORITHM) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(algorithm.name().as_bytes());
+    hasher.update(&[0]);
+    hasher.update(&sha256(secret));
+    hasher.update(token.as_bytes());
+    hasher.finish()
+}
+
+impl VerifiedTokenCache {
+    pub fn new() -> VerifiedTokenCache {
+        VerifiedTokenCache::with_clock(SystemClock)
+    }
+}
+
+impl<C: Clock> VerifiedTokenCache<C> {
+    pub fn with_clock(clock: C) -> VerifiedTokenCache<C> {
+        VerifiedTokenCache {
+            max_ttl: 300,
+            validation: Validation::new(),
+            max_entries: 10000,
+            clock: clock,
+            state: Mutex::new(State::default()),
+            verified: Condvar::new(),
+        }
+    }
+
+    fn lock<'a>(&'a self) -> MutexGuard<'a, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.lock().stats
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().slots.values().filter(|slot| match **slot { Slot::Ready { .. } => true, _ => false }).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Concurrent lookups of the same uncached token wait for a single verification.
+    pub fn decode_jwt(&self, token: &str, secret: &[u8], algorithm: ALGORITHM) -> Result<(Header, Claims)> {
+        if self.validation.has_replay_store() {
+            self.lock().stats.misses += 1;
+            return JWS::decode_jwt_at(token.to_owned(), secret, algorithm, &self.validation, self.clock.now());
+        }
+        let key = fingerprint(token, secret, &algorithm);
+        let mut state = self.lock();
+        let mut waited = false;
+        loop {
+            let now = self.clock.now();
+            let expired = match state.slots.get(&key) {
+                Some(&Slot::Ready { ref verified, expires }) if now < expires => {
+                    let found = (**verified).clone();
+                    state.stats.hits += 1;
+                    return Ok(found);
+                },
+                Some(&Slot::Ready { .. }) => true,
+                Some(&Slot::Pending) => {
+                    if !waited {
+                        state.stats.coalesced += 1;
+                        waited = true;
+                    }
+                    state = self.verified.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+                    continue;
+                },
+                None => false
+            };
+            if expired {
+                state.slots.remove(&key);
+                state.stats.evictions += 1;
+            }
+            break;
+        }
+        state.stats.misses += 1;
+        state.slots.insert(key, Slot::Pending);
+        drop(state);
+        let pending = PendingSlot { cache: self, key: key };
+
+        let result = JWS::decode_jwt_at(token.to_owned(), secret, algorithm, &self.validation, self.clock.now());
+
+        let mut state = self.lock();
+        state.slots.remove(&key);
+        if let Ok((ref header, ref claims)) = result {
+            let now = self.clock.now();
+            let expires = claims.exp.map_or(now.saturating_add(self.max_ttl), |exp| ::std::cmp::min(exp, now.saturating_add(self.max_ttl)));
+            if expires > now {
+                if state.slots.len() >= self.max_entries {
+                    let before = state.slots.len();
+                    state.slots.retain(|_, slot| match *slot { Slot::Ready { expires, .. } => expires > now, Slot::Pending => true });
+                    state.stats.evictions += (before - state.slots.len()) as u64;
+                }
+                if state.slots.len() < self.max_entries {
+                    state.slots.insert(key, Slot::Ready { verified: Box::new((header.clone(), claims.clone())), expires: expires });
+                }
+            }
+        }
+        drop(state);
+        drop(pending);
+        result
+    }
+}
+
+#[test]
+fn entries_live_until_exp_or_the_max_ttl() {
+    let clock = StepClock(::std::sync::atomic::AtomicUsize::new(1000));
+    let mut cache = VerifiedTokenCache::with_clock(&clock);
+    cache.max_ttl = 60;
+    let short = token_expiring_at(Some(1010));
+    let long = token_expiring_at(None);
+
+    assert!(cache.decode_jwt(&short, ::TEST_HS256_SECRET, ALGORITHM::HS256).is_ok());
+    assert!(cache.decode_jwt(&long, ::TEST_HS256_SECRET, ALGORITHM::HS256).is_ok());
+    assert!(cache.decode_jwt(&short, ::TEST_HS256_SECRET, ALGORITHM::HS256).is_ok());
+    assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 2, coalesced: 0, evictions: 0 });
+
+    clock.0.store(1010, ::std::sync::atomic::Ordering::SeqCst);
+    assert!(cache.decode_jwt(&short, ::TEST_HS256_SECRET, ALGORITHM::HS256).is_ok());
+    assert!(cache.decode_jwt(&long, ::TEST_HS256_SECRET, ALGORITHM::HS256).is_ok());
+    assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 3, coalesced: 0, evictions: 1 });
+
+    clock.0.store(1060, ::std::sync::atomic::Ordering::SeqCst);
+    assert!(cache.decode_jwt(&long, ::TEST_HS256_SECRET, ALGORITHM::HS256).is_ok());
+    assert_eq!(cache.stats().misses, 4);
+}
+
+#[test]
+fn failures_are_not_cached_and_keys_are_kept_apart() {
+    let cache = VerifiedTokenCache::with_clock(::clock::FixedClock(1000));
+    let token = token_expiring_at(None);
+    assert!(cache.decode_jwt(&token, b"wrong", ALGORITHM::HS256).is_err());
+    assert!(cache.decode_jwt(&token, ::TEST_HS256_SECRET, ALGORITHM::HS256).is_ok());
+    assert!(cache.decode_jwt(&token, b"wrong", ALGORITHM::HS256).is_err());
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.stats().hits, 0);
+}
+
+#[test]
+fn concurrent_lookups_share_one_verification() {
+    let cache = VerifiedTokenCache::with_clock(::clock::FixedClock(1000));
+    let token = token_expiring_at(None);
+    ::std::thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| assert!(cache.decode_jwt(&token, ::TEST_HS256_SECRET, ALGORITHM::HS256).is_ok()));
+        }
+    });
+    let stats = cache.stats();
+    assert_eq!(stats.hits + stats.misses, 8);
+    assert_eq!(stats.misses, 1);
+}
+
+#[test]
+fn replayed_tokens_are_not_served_from_the_cache() {
+    use std::sync::{Arc, Mutex};
+    use error::Error;
+    use replay::MemoryReplayStore;
+
+    let mut cache = VerifiedTokenCache::with_clock(::clock::FixedClock(1000));
+    cache.validation = Validation::new().with_replay_store(Arc::new(Mutex::new(MemoryReplayStore::new())));
+    let mut claims = Claims::new();
+    claims.exp = Some(2000);
+    claims.jti = Some("assertion-1".to_owned());
+    let token = JWS::from_claims(Header::new(), claims).encode(::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap();
+
+    assert!(cache.decode_jwt(&token, ::TEST_HS256_SECRET, ALGORITHM::HS256).is_ok());
+    assert_err!(cache.decode_jwt(&token, ::TEST_HS256_SECRET, ALGORITHM::HS256), Error::TokenReplayed);
+    assert_eq!(cache.len(), 0);
+    assert_eq!(cache.stats().hits, 0);
+}
+
+#[test]
+fn a_panicking_verification_does_not_block_later_lookups() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let mut cache = VerifiedTokenCache::with_clock(::clock::FixedClock(1000));
+    cache.validation = Validation::new().with_validator(|_: &Claims| -> Result<()> { panic!("validator bug") });
+    let token = token_expiring_at(None);
+    for _ in 0..2 {
+        let lookup = catch_unwind(AssertUnwindSafe(|| cache.decode_jwt(&token, ::TEST_HS256_SECRET, ALGORITHM::HS256)));
+        assert!(lookup.is_err());
+    }
+    assert!(cache.is_empty());
+    assert_eq!(cache.stats().misses, 2);
+}
@@ -0,0 +1,60 @@
+#![allow(dead_code)]
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub parent_id: String,
+    pub flags: u8,
+    pub tracestate: Option<String>,
+}
+
+fn is_lower_hex(value: &str, len: usize) -> bool {
+    value.len() == len && value.bytes().all(|b| (b'0'..=b'9').contains(&b) || (b'a'..=b'f').contains(&b))
+}
+
+fn is_zero(value: &str) -> bool {
+    value.bytes().all(|b| b == b'0')
+}
+
+impl TraceContext {
+    pub fn parse(traceparent: &str, tracestate: Option<&str>) -> Option<TraceContext> {
+        let parts: Vec<&str> = traceparent.split('-').collect();
+        if parts.len() != 4 || parts[0] != "00" {
+            return None;
+        }
+        if !is_lower_hex(parts[1], 32) || is_zero(parts[1]) || !is_lower_hex(parts[2], 16) || is_zero(parts[2]) || !is_lower_hex(parts[3], 2) {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id: parts[1].to_owned(),
+            parent_id: parts[2].to_owned(),
+            flags: u8::from_str_radix(parts[3], 16).unwrap_or(0),
+            tracestate: tracestate.map(|s| s.to_owned()),
+        })
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", self.trace_id, self.parent_id, self.flags)
+    }
+
+    pub fn sampled(&self) -> bool {
+        self.flags & 0x01 == 0x01
+    }
+}
+
+#[test]
+fn traceparent_round_trips() {
+    let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    let ctx = TraceContext::parse(header, Some("rojo=00f067aa0ba902b7")).unwrap();
+    assert_eq!(ctx.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert!(ctx.sampled());
+    assert_eq!(ctx.traceparent(), header);
+}
+
+#[test]
+fn invalid_traceparents_are_rejected() {
+    assert!(TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01", None).is_none());
+    assert!(TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01", None).is_none());
+    assert!(TraceContext::parse("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01", None).is_none());
+    assert!(TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7", None).is_none());
+}
@@ -0,0 +1,137 @@
+#![allow(dead_code)]
+
+use std::result;
+use serde;
+use serde::Serialize;
+use serde_json;
+use openssl::hash::{hash, MessageDigest};
+use claims::Claims;
+use clock::Clock;
+use jws::{JWS, base64_url_encode_bytes};
+use jws_header::{Header, ALGORITHM};
+use validation::Validation;
+use error::{Error, RejectReason, Result};
+use signing;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct VerificationTranscript {
+    pub token_sha256: String,
+    pub key_thumbprint: Option<String>,
+    pub alg: ALGORITHM,
+    pub verified_at: u64,
+    pub exp: Option<u64>,
+    pub nbf: Option<u64>,
+    pub iat: Option<u64>,
+    pub outcome: Option<RejectReason>,
+}
+
+fn sha256_b64(bytes: &[u8]) -> Option<String> {
+    hash(MessageDigest::sha256(), bytes).ok().map(|digest| base64_url_encode_bytes(&digest))
+}
+
+// Asymmetric keys are identified by their public half so the archive never holds private material.
+fn key_thumbprint(secret: &[u8], alg: &ALGORITHM) -> Option<String> {
+    let public = match *alg {
+        ALGORITHM::HS256 | ALGORITHM::HS384 | ALGORITHM::HS512 => return sha256_b64(secret),
+        _ => signing::public_key(secret).ok()
+    };
+    public.and_then(|key| key.public_key_to_der().ok()).and_then(|der| sha256_b64(&der))
+}
+
+impl VerificationTranscript {
+    pub fn outcome_str(&self) -> &'static str {
+        self.outcome.map_or("accepted", |reason| reason.as_str())
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(try!(serde_json::to_string(self)))
+    }
+}
+
+impl Serialize for VerificationTranscript {
+    fn serialize<S>(&self, serializer: &mut S) -> result::Result<(), S::Error>
+        where S: serde::Serializer
+    {
+        let mut state = try!(serializer.serialize_map(None));
+        try!(serializer.serialize_map_key(&mut state, "token_sha256"));
+        try!(serializer.serialize_map_value(&mut state, self.token_sha256.as_str()));
+        if let Some(ref key_thumbprint) = self.key_thumbprint {
+            try!(serializer.serialize_map_key(&mut state, "key_thumbprint"));
+            try!(serializer.serialize_map_value(&mut state, key_thumbprint.as_str()));
+        }
+        try!(serializer.serialize_map_key(&mut state, "alg"));
+        try!(serializer.serialize_map_value(&mut state, self.alg.clone()));
+        try!(serializer.serialize_map_key(&mut state, "verified_at"));
+        try!(serializer.serialize_map_value(&mut state, self.verified_at));
+        if let Some(exp) = self.exp {
+            try!(serializer.serialize_map_key(&mut state, "exp"));
+            try!(serializer.serialize_map_value(&mut state, exp));
+        }
+        if let Some(nbf) = self.nbf {
+            try!(serializer.serialize_map_key(&mut state, "nbf"));
+            try!(serializer.serialize_map_value(&mut state, nbf));
+        }
+        if let Some(iat) = self.iat {
+            try!(serializer.serialize_map_key(&mut state, "iat"));
+            try!(serializer.serialize_map_value(&mut state, iat));
+        }
+        try!(serializer.serialize_map_key(&mut state, "outcome"));
+        try!(serializer.serialize_map_value(&mut state, self.outcome_str()));
+        serializer.serialize_map_end(state)
+    }
+}
+
+pub fn decode_jwt_with_transcript<C: Clock>(value: String, secret: &[u8], algorithm: ALGORITHM, clock: &C)
+    -> (Result<(Header, Claims)>, VerificationTranscript)
+{
+    let mut transcript = VerificationTranscript {
+        token_sha256: sha256_b64(value.as_bytes()).unwrap_or_default(),
+        key_thumbprint: key_thumbprint(secret, &algorithm),
+        alg: algorithm.clone(),
+        verified_at: clock.now(),
+        exp: None,
+        nbf: None,
+        iat: None,
+        outcome: None,
+    };
+    let result = JWS::decode_jwt_at(value, secret, algorithm, &Validation::new(), clock.now());
+    match result {
+        Ok((_, ref claims)) => {
+            transcript.exp = claims.exp;
+            transcript.nbf = claims.nbf;
+            transcript.iat = claims.iat;
+        },
+        Err(ref err) => transcript.outcome = Some(Error::reject_reason(err))
+    }
+    (result, transcript)
+}
+
+#[test]
+fn transcripts_record_accepted_and_rejected_tokens() {
+    use clock::FixedClock;
+
+    let mut claims = Claims::new();
+    claims.exp = Some(2000);
+    let token = JWS::from_claims(Header::new(), claims).encode(::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap();
+
+    let (result, transcript) = decode_jwt_with_transcript(token.clone(), ::TEST_HS256_SECRET, ALGORITHM::HS256, &FixedClock(1000));
+    assert!(result.is_ok());
+    assert_eq!(transcript.outcome_str(), "accepted");
+    assert_eq!(transcript.exp, Some(2000));
+    let json = transcript.to_json().unwrap();
+    assert!(json.contains(r#""verified_at":1000"#));
+    assert!(!json.contains("secret"));
+
+    let (result, transcript) = decode_jwt_with_transcript(token, b"another 32-byte secret for HS256", ALGORITHM::HS256, &FixedClock(1000));
+    assert!(result.is_err());
+    assert_eq!(transcript.outcome, Some(RejectReason::InvalidSignature));
+    assert_eq!(transcript.exp, None);
+}
+
+#[test]
+fn asymmetric_keys_are_identified_by_their_public_half() {
+    let private = signing::eddsa_key(&[5u8; 32]).unwrap();
+    let thumbprint = key_thumbprint(&private.raw_public_key().unwrap(), &ALGORITHM::EdDSA).unwrap();
+    let public = private.public_key_to_der().unwrap();
+    assert_eq!(Some(thumbprint), sha256_b64(&public));
+}
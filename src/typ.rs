@@ -0,0 +1,96 @@
+#![allow(dead_code)]
+
+use std::borrow::Cow;
+use std::fmt;
+use std::result;
+use serde;
+use serde::Serialize;
+
+#[derive(Debug, Clone)]
+pub struct Typ {
+    value: Cow<'static, str>,
+}
+
+impl Typ {
+    pub const JWT: Typ = Typ { value: Cow::Borrowed("JWT") };
+    pub const AT_JWT: Typ = Typ { value: Cow::Borrowed("at+jwt") };
+    pub const DPOP_JWT: Typ = Typ { value: Cow::Borrowed("dpop+jwt") };
+    pub const SECEVENT_JWT: Typ = Typ { value: Cow::Borrowed("secevent+jwt") };
+    pub const LOGOUT_JWT: Typ = Typ { value: Cow::Borrowed("logout+jwt") };
+    pub const LICENSE_JWT: Typ = Typ { value: Cow::Borrowed("license+jwt") };
+
+    pub fn new<S: Into<String>>(value: S) -> Typ {
+        Typ { value: Cow::Owned(value.into()) }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.value
+    }
+
+    pub fn matches(&self, other: &str) -> bool {
+        canonical(&self.value) == canonical(other)
+    }
+}
+
+// Media types compare case-insensitively, and "application/" may be omitted
+// when the remainder contains no other "/" (RFC 7515, section 4.1.9).
+fn canonical(value: &str) -> String {
+    let lower = value.to_ascii_lowercase();
+    if lower.starts_with("application/") && !lower["application/".len()..].contains('/') {
+        lower["application/".len()..].to_owned()
+    } else {
+        lower
+    }
+}
+
+impl PartialEq for Typ {
+    fn eq(&self, other: &Typ) -> bool {
+        self.matches(&other.value)
+    }
+}
+
+impl<'a> PartialEq<&'a str> for Typ {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.matches(other)
+    }
+}
+
+impl fmt::Display for Typ {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+impl Serialize for Typ {
+    fn serialize<S>(&self, serializer: &mut S) -> result::Result<(), S::Error>
+        where S: serde::Serializer
+    {
+        serializer.serialize_str(&self.value)
+    }
+}
+
+impl serde::Deserialize for Typ {
+    fn deserialize<D>(deserializer: &mut D) -> result::Result<Typ, D::Error>
+        where D: serde::Deserializer
+    {
+        let value: String = try!(serde::Deserialize::deserialize(deserializer));
+        Ok(Typ::new(value))
+    }
+}
+
+#[test]
+fn typ_comparison_ignores_case_and_the_application_prefix() {
+    assert_eq!(Typ::JWT, "jwt");
+    assert_eq!(Typ::AT_JWT, "application/AT+JWT");
+    assert_eq!(Typ::new("application/dpop+jwt"), Typ::DPOP_JWT);
+    assert!(Typ::JWT != Typ::LOGOUT_JWT);
+    assert!(!Typ::new("application/vnd/jwt").matches("vnd/jwt"));
+}
+
+#[test]
+fn typ_serializes_as_its_string_value() {
+    use serde_json;
+    assert_eq!(serde_json::to_string(&Typ::SECEVENT_JWT).unwrap(), r#""secevent+jwt""#);
+    let typ: Typ = serde_json::from_str(r#""application/at+jwt""#).unwrap();
+    assert_eq!(typ, Typ::AT_JWT);
+}
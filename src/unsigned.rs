@@ -0,0 +1,108 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::result;
+use std::str;
+use serde;
+use serde::Serialize;
+use serde_json;
+use rustc_serialize::base64::FromBase64;
+
+use jws::base64_url_encode_bytes;
+use jws_header::{Header, ALGORITHM};
+use error::{Error, Result};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct UnsignedJws {
+    alg: ALGORITHM,
+    signing_input: String,
+}
+
+fn signature_lens(alg: &ALGORITHM) -> &'static [usize] {
+    match *alg {
+        ALGORITHM::HS256 => &[32],
+        ALGORITHM::HS384 => &[48],
+        ALGORITHM::HS512 => &[64],
+        ALGORITHM::EdDSA => &[64, 114],
+        ALGORITHM::ES256 => &[64],
+        ALGORITHM::ES384 => &[96],
+        ALGORITHM::ES512 => &[132],
+        ALGORITHM::ES256K => &[64],
+        _ => &[]
+    }
+}
+
+impl UnsignedJws {
+    pub fn from_signing_input(signing_input: String) -> Result<UnsignedJws> {
+        let alg = {
+            let parts: Vec<&str> = signing_input.split('.').collect();
+            if parts.len() != 2 {
+                return Err(Error::InvalidSigningInput);
+            }
+            let header = try!(parts[0].from_base64());
+            let header: Header = try!(serde_json::from_str(try!(str::from_utf8(header.as_slice()))));
+            header.alg
+        };
+        Ok(UnsignedJws {
+            alg: alg,
+            signing_input: signing_input,
+        })
+    }
+
+    pub fn alg(&self) -> &ALGORITHM {
+        &self.alg
+    }
+
+    pub fn signing_input(&self) -> &str {
+        self.signing_input.as_str()
+    }
+
+    pub fn complete(&self, signature: &[u8]) -> Result<String> {
+        let expected = signature_lens(&self.alg);
+        let matches = if expected.is_empty() {
+            !signature.is_empty()
+        } else {
+            expected.contains(&signature.len())
+        };
+        if !matches {
+            return Err(Error::SignatureLengthMismatch { found: signature.len() });
+        }
+        Ok(format!("{}.{}", self.signing_input, base64_url_encode_bytes(signature)))
+    }
+}
+
+impl Serialize for UnsignedJws {
+    fn serialize<S>(&self, serializer: &mut S) -> result::Result<(), S::Error>
+        where S: serde::Serializer
+    {
+        let mut state = try!(serializer.serialize_map(Some(2)));
+        try!(serializer.serialize_map_key(&mut state, "alg"));
+        try!(serializer.serialize_map_value(&mut state, self.alg.clone()));
+        try!(serializer.serialize_map_key(&mut state, "signing_input"));
+        try!(serializer.serialize_map_value(&mut state, self.signing_input.as_str()));
+        serializer.serialize_map_end(state)
+    }
+}
+
+impl serde::Deserialize for UnsignedJws {
+    fn deserialize<D>(deserializer: &mut D) -> result::Result<UnsignedJws, D::Error>
+        where D: serde::Deserializer
+    {
+        let mut fields: BTreeMap<String, String> = try!(serde::Deserialize::deserialize(deserializer));
+        let signing_input = match fields.remove("signing_input") {
+            Some(signing_input) => signing_input,
+            None => return Err(serde::de::Error::missing_field("signing_input"))
+        };
+        let alg: ALGORITHM = match fields.remove("alg") {
+            Some(alg) => try!(serde_json::from_value(serde_json::Value::String(alg))
+                .map_err(|_| serde::de::Error::invalid_value("unknown alg"))),
+            None => return Err(serde::de::Error::missing_field("alg"))
+        };
+        let unsigned = try!(UnsignedJws::from_signing_input(signing_input)
+            .map_err(|e| serde::de::Error::invalid_value(&e.to_string())));
+        if unsigned.alg != alg {
+            return Err(serde::de::Error::invalid_value("alg does not match the protected header"));
+        }
+        Ok(unsigned)
+    }
+}
@@ -0,0 +1,765 @@
+#![allow(dead_code)]
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use clock::{Clock, SystemClock};
+use claims::Claims;
+use jws::{JWS, ParsedToken};
+use jws_header::{Header, ALGORITHM};
+use limits::Limits;
+use replay::ReplayStore;
+use signing;
+use simple::DecodingKey;
+use typ::Typ;
+use error::{Error, Result};
+
+// NIST SP 800-131A; smaller moduli are only for interoperating with legacy keys.
+pub const MIN_RSA_BITS: usize = 2048;
+
+// Header parameters that carry or point at a key. Trusting them would let the
+// token choose the key it is verified with (RFC 8725, section 3.10).
+const EMBEDDED_KEY_HEADERS: [&'static str; 4] = ["jwk", "jku", "x5c", "x5u"];
+
+// Picks the token's algorithm out of an allow-list. Shared secrets and public
+// keys must not be accepted side by side: a verifier holding an RSA public key
+// would otherwise check an HS256 token using the PEM bytes as the HMAC secret.
+pub fn allowed_algorithm(header: &Header, algorithms: &[ALGORITHM]) -> Result<ALGORITHM> {
+    let is_hmac = |alg: &ALGORITHM| match *alg {
+        ALGORITHM::HS256 | ALGORITHM::HS384 | ALGORITHM::HS512 => true,
+        _ => false
+    };
+    if algorithms.iter().any(is_hmac) && algorithms.iter().any(|alg| !is_hmac(alg)) {
+        return Err(Error::AlgorithmNotAllowed("HMAC algorithms cannot share an allow-list with public-key algorithms".to_owned()));
+    }
+    if header.alg == ALGORITHM::None || !algorithms.contains(&header.alg) {
+        return Err(Error::AlgorithmNotAllowed(header.alg.name().to_owned()));
+    }
+    Ok(header.alg.clone())
+}
+
+// RFC 7518, section 3.2: an HMAC key must be at least as long as the hash output.
+pub fn check_hmac_key(key: &[u8], algorithm: &ALGORITHM) -> Result<()> {
+    let required = match *algorithm {
+        ALGORITHM::HS256 => 256,
+        ALGORITHM::HS384 => 384,
+        ALGORITHM::HS512 => 512,
+        _ => return Ok(())
+    };
+    let bits = key.len() * 8;
+    if bits < required {
+        return Err(Error::WeakKey { alg: algorithm.clone(), bits: bits, required: required });
+    }
+    Ok(())
+}
+
+pub fn check_rsa_key(key: &[u8], algorithm: &ALGORITHM, required: usize) -> Result<()> {
+    match *algorithm {
+        ALGORITHM::RS256 | ALGORITHM::RS384 | ALGORITHM::RS512 |
+        ALGORITHM::PS256 | ALGORITHM::PS384 | ALGORITHM::PS512 => {},
+        _ => return Ok(())
+    }
+    let bits = try!(signing::public_key(key).map_err(|err| Error::public_key_parse(key, err))).bits() as usize;
+    if bits < required {
+        return Err(Error::WeakKey { alg: algorithm.clone(), bits: bits, required: required });
+    }
+    Ok(())
+}
+
+// Every missing claim is reported, not just the first.
+fn check_required(claims: &Claims, required: &[String]) -> Result<()> {
+    let missing: Vec<String> = required.iter()
+        .filter(|name| !claims.has(name))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::MissingClaims(missing));
+    }
+    Ok(())
+}
+
+// A token meant for several recipients names them all in aud; it is accepted
+// when the configured audience is one of them.
+fn check_audience(claims: &Claims, audience: &Option<String>) -> Result<()> {
+    match (audience, &claims.aud) {
+        (&Some(_), &None) => Err(Error::MissingClaims(vec!["aud".to_owned()])),
+        (&Some(ref expected), &Some(ref aud)) if !aud.contains(expected) => Err(Error::ClaimMismatch { claim: "aud" }),
+        _ => Ok(())
+    }
+}
+
+fn check_exp(claims: &Claims, leeway: u64, now: u64) -> Result<()> {
+    match claims.exp {
+        Some(exp) if exp.saturating_add(leeway) <= now => Err(Error::TokenExpired { exp: exp, now: now }),
+        _ => Ok(())
+    }
+}
+
+fn check_nbf(claims: &Claims, leeway: u64, now: u64) -> Result<()> {
+    match claims.nbf {
+        Some(nbf) if nbf > now.saturating_add(leeway) => Err(Error::TokenNotYetValid { nbf: nbf, now: now }),
+        _ => Ok(())
+    }
+}
+
+// Some profiles bound how long ago a token may have been issued, whatever its
+// exp says. A token without iat cannot show its age and is rejected.
+fn check_max_age(claims: &Claims, max_age: Option<u64>, leeway: u64, now: u64) -> Result<()> {
+    let max_age = match max_age {
+        Some(max_age) => max_age,
+        None => return Ok(())
+    };
+    match claims.iat {
+        None => Err(Error::MissingClaims(vec!["iat".to_owned()])),
+        Some(iat) if iat.saturating_add(max_age).saturating_add(leeway) < now => Err(Error::TokenTooOld { iat: iat, max_age: max_age, now: now }),
+        Some(_) => Ok(())
+    }
+}
+
+// Application rules (tenant, scopes, ...) that run after the built-in checks.
+pub trait ClaimValidator: Send + Sync {
+    fn validate(&self, claims: &Claims) -> Result<()>;
+}
+
+impl<F: Fn(&Claims) -> Result<()> + Send + Sync> ClaimValidator for F {
+    fn validate(&self, claims: &Claims) -> Result<()> {
+        self(claims)
+    }
+}
+
+// Every check that failed, in the order the checks ran.
+#[derive(Debug)]
+pub struct ValidationError {
+    failures: Vec<Error>,
+}
+
+impl ValidationError {
+    pub fn failures(&self) -> &[Error] {
+        &self.failures
+    }
+
+    pub fn into_failures(self) -> Vec<Error> {
+        self.failures
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{} checks failed", self.failures.len()));
+        for (index, failure) in self.failures.iter().enumerate() {
+            try!(write!(f, "{} {}", if index == 0 { ":" } else { ";" }, failure));
+        }
+        Ok(())
+    }
+}
+
+// What JWS::decode_jwt and its siblings check: the algorithm, the key and the
+// header before the signature, then the claims.
+#[derive(Clone)]
+pub struct Validation {
+    pub algorithms: Vec<ALGORITHM>,
+    // Algorithm names outside RFC 7518 that a token's header may carry. A header
+    // naming any other unknown algorithm is rejected as unsupported.
+    pub custom_algorithms: Vec<String>,
+    pub require_algorithms: bool,
+    pub typ: Option<Typ>,
+    pub require_typ: bool,
+    pub reject_embedded_keys: bool,
+    pub allow_es256_secp256k1: bool,
+    // Accept HMAC secrets shorter than the hash output, for legacy issuers only.
+    pub allow_short_hmac_keys: bool,
+    pub min_rsa_bits: Option<usize>,
+    // Clock skew allowed when checking exp, nbf and max_age.
+    pub leeway: u64,
+    // Clock skew allowed for iat in the future, kept apart from `leeway` so a
+    // generous expiry grace does not also admit tokens minted ahead of time.
+    pub iat_leeway: u64,
+    pub validate_exp: bool,
+    pub validate_nbf: bool,
+    pub validate_iat: bool,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    pub required_claims: Vec<String>,
+    pub max_age: Option<u64>,
+    // Run every claim check and report all failures as one ValidationFailed
+    // error, instead of stopping at the first.
+    pub report_all: bool,
+    // Oversized tokens are rejected before any base64 or JSON work.
+    pub limits: Limits,
+    validators: Vec<Arc<dyn ClaimValidator>>,
+    replay_store: Option<Arc<Mutex<dyn ReplayStore + Send>>>,
+}
+
+impl fmt::Debug for Validation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Validation")
+            .field("algorithms", &self.algorithms)
+            .field("custom_algorithms", &self.custom_algorithms)
+            .field("require_algorithms", &self.require_algorithms)
+            .field("typ", &self.typ)
+            .field("require_typ", &self.require_typ)
+            .field("reject_embedded_keys", &self.reject_embedded_keys)
+            .field("allow_es256_secp256k1", &self.allow_es256_secp256k1)
+            .field("allow_short_hmac_keys", &self.allow_short_hmac_keys)
+            .field("min_rsa_bits", &self.min_rsa_bits)
+            .field("leeway", &self.leeway)
+            .field("iat_leeway", &self.iat_leeway)
+            .field("validate_exp", &self.validate_exp)
+            .field("validate_nbf", &self.validate_nbf)
+            .field("validate_iat", &self.validate_iat)
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .field("required_claims", &self.required_claims)
+            .field("max_age", &self.max_age)
+            .field("report_all", &self.report_all)
+            .field("limits", &self.limits)
+            .field("validators", &self.validators.len())
+            .field("replay_store", &self.replay_store.is_some())
+            .finish()
+    }
+}
+
+// Validators compare by identity; a clone shares its validators with the original.
+impl PartialEq for Validation {
+    fn eq(&self, other: &Validation) -> bool {
+        self.algorithms == other.algorithms &&
+            self.custom_algorithms == other.custom_algorithms &&
+            self.require_algorithms == other.require_algorithms &&
+            self.typ == other.typ &&
+            self.require_typ == other.require_typ &&
+            self.reject_embedded_keys == other.reject_embedded_keys &&
+            self.allow_es256_secp256k1 == other.allow_es256_secp256k1 &&
+            self.allow_short_hmac_keys == other.allow_short_hmac_keys &&
+            self.min_rsa_bits == other.min_rsa_bits &&
+            self.leeway == other.leeway &&
+            self.iat_leeway == other.iat_leeway &&
+            self.validate_exp == other.validate_exp &&
+            self.validate_nbf == other.validate_nbf &&
+            self.validate_iat == other.validate_iat &&
+            self.issuer == other.issuer &&
+            self.audience == other.audience &&
+            self.required_claims == other.required_claims &&
+            self.max_age == other.max_age &&
+            self.report_all == other.report_all &&
+            self.limits == other.limits &&
+            self.validators.len() == other.validators.len() &&
+            self.validators.iter().zip(other.validators.iter()).all(|(a, b)| Arc::ptr_eq(a, b)) &&
+            match (&self.replay_store, &other.replay_store) {
+                (&Some(ref a), &Some(ref b)) => Arc::ptr_eq(a, b),
+                (&None, &None) => true,
+                _ => false
+            }
+    }
+}
+
+impl Validation {
+    pub fn new() -> Validation {
+        Validation {
+            algorithms: Vec::new(),
+            custom_algorithms: Vec::new(),
+            require_algorithms: false,
+            typ: None,
+            require_typ: false,
+            reject_embedded_keys: false,
+            allow_es256_secp256k1: false,
+            allow_short_hmac_keys: false,
+            min_rsa_bits: None,
+            leeway: 60,
+            iat_leeway: 60,
+            validate_exp: true,
+            validate_nbf: true,
+            validate_iat: false,
+            issuer: None,
+            audience: None,
+            required_claims: Vec::new(),
+            max_age: None,
+            report_all: false,
+            limits: Limits::new(),
+            validators: Vec::new(),
+            replay_store: None,
+        }
+    }
+
+    // For callers that check the registered claims themselves, against their own clock.
+    pub fn signature_only() -> Validation {
+        Validation {
+            leeway: 0,
+            iat_leeway: 0,
+            validate_exp: false,
+            validate_nbf: false,
+            ..Validation::new()
+        }
+    }
+
+    // Every RFC 8725 check this crate supports. The allow-list starts empty, so
+    // nothing verifies until the caller names the algorithms it accepts.
+    pub fn rfc8725_strict() -> Validation {
+        Validation {
+            require_algorithms: true,
+            require_typ: true,
+            reject_embedded_keys: true,
+            min_rsa_bits: Some(MIN_RSA_BITS),
+            validate_iat: true,
+            required_claims: vec!["iss".to_owned(), "aud".to_owned()],
+            ..Validation::new()
+        }
+    }
+
+    pub fn allow_custom_algorithm(mut self, name: &str) -> Validation {
+        self.custom_algorithms.push(name.to_owned());
+        self
+    }
+
+    pub fn require(mut self, claims: &[&str]) -> Validation {
+        self.required_claims.extend(claims.iter().map(|claim| claim.to_string()));
+        self
+    }
+
+    pub fn with_validator<V: ClaimValidator + 'static>(mut self, validator: V) -> Validation {
+        self.validators.push(Arc::new(validator));
+        self
+    }
+
+    // Tokens must then carry jti and exp. The jti is recorded only once every
+    // other check has passed, so a rejected token does not use it up.
+    pub fn with_replay_store(mut self, store: Arc<Mutex<dyn ReplayStore + Send>>) -> Validation {
+        self.replay_store = Some(store);
+        self
+    }
+
+    pub fn has_replay_store(&self) -> bool {
+        self.replay_store.is_some()
+    }
+
+    pub fn decode(&self, token: &str, secret: &[u8], algorithm: ALGORITHM) -> Result<(Header, Claims)> {
+        self.decode_with_clock(token, secret, algorithm, &SystemClock)
+    }
+
+    // The header's alg chooses among the allow-list in `algorithms`, which must not be empty.
+    pub fn decode_allowed(&self, token: &str, key: &[u8]) -> Result<(Header, Claims)> {
+        self.decode_allowed_at(token, key, SystemClock.now())
+    }
+
+    pub fn decode_allowed_at(&self, token: &str, key: &[u8], now: u64) -> Result<(Header, Claims)> {
+        let algorithm = {
+            let parsed = try!(ParsedToken::parse_with_validation(token, self));
+            try!(allowed_algorithm(parsed.header(), &self.algorithms))
+        };
+        self.decode_at(token, key, algorithm, now)
+    }
+
+    pub fn decode_with_clock<C: Clock>(&self, token: &str, secret: &[u8], algorithm: ALGORITHM, clock: &C) -> Result<(Header, Claims)> {
+        self.decode_at(token, secret, algorithm, clock.now())
+    }
+
+    pub fn decode_at(&self, token: &str, secret: &[u8], algorithm: ALGORITHM, now: u64) -> Result<(Header, Claims)> {
+        let parsed = try!(ParsedToken::parse_with_validation(token, self));
+        self.claims_of(try!(parsed.verify_with_validation_at(secret, algorithm, self, now)))
+    }
+
+    pub fn decode_with_key_at(&self, token: &str, key: &DecodingKey, now: u64) -> Result<(Header, Claims)> {
+        let parsed = try!(ParsedToken::parse_with_validation(token, self));
+        self.claims_of(try!(parsed.verify_with_key_at(key, self, now)))
+    }
+
+    fn claims_of(&self, jws: JWS) -> Result<(Header, Claims)> {
+        let claims = match jws.claims() {
+            Some(claims) => claims.clone(),
+            None => return Err(Error::MalformedToken)
+        };
+        Ok((jws.header().clone(), claims))
+    }
+
+    pub fn check_with_clock<C: Clock>(&self, claims: &Claims, clock: &C) -> Result<()> {
+        self.check(claims, clock.now())
+    }
+
+    fn record(&self, failures: &mut Vec<Error>, result: Result<()>) -> Result<()> {
+        match result {
+            Err(err) if self.report_all => {
+                failures.push(err);
+                Ok(())
+            },
+            result => result
+        }
+    }
+
+    fn check_issuer(&self, claims: &Claims) -> Result<()> {
+        if self.issuer.is_some() && claims.iss != self.issuer {
+            return Err(Error::ClaimMismatch { claim: "iss" });
+        }
+        Ok(())
+    }
+
+    pub fn check(&self, claims: &Claims, now: u64) -> Result<()> {
+        let mut failures = Vec::new();
+        try!(self.record(&mut failures, check_required(claims, &self.required_claims)));
+        if self.validate_exp {
+            try!(self.record(&mut failures, check_exp(claims, self.leeway, now)));
+        }
+        if self.validate_nbf {
+            try!(self.record(&mut failures, check_nbf(claims, self.leeway, now)));
+        }
+        try!(self.record(&mut failures, self.check_issuer(claims)));
+        if self.validate_iat {
+            try!(self.record(&mut failures, claims.check_issued_at(now, self.iat_leeway)));
+        }
+        try!(self.record(&mut failures, check_max_age(claims, self.max_age, self.leeway, now)));
+        try!(self.record(&mut failures, check_audience(claims, &self.audience)));
+        for validator in self.validators.iter() {
+            try!(self.record(&mut failures, validator.validate(claims)));
+        }
+        if !failures.is_empty() {
+            return Err(Error::ValidationFailed(ValidationError { failures: failures }));
+        }
+        if let Some(ref store) = self.replay_store {
+            let (jti, exp) = match (&claims.jti, claims.exp) {
+                (&Some(ref jti), Some(exp)) => (jti, exp),
+                _ => return Err(Error::MissingClaims(["jti", "exp"].iter().filter(|name| !claims.has(name)).map(|name| name.to_string()).collect()))
+            };
+            let mut store = store.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if !store.check_and_record_at(jti, exp, now) {
+                return Err(Error::TokenReplayed);
+            }
+        }
+        Ok(())
+    }
+
+    // HMAC secret lengths are checked where the signature is, honouring allow_short_hmac_keys.
+    fn check_key(&self, key: &[u8], algorithm: &ALGORITHM) -> Result<()> {
+        match self.min_rsa_bits {
+            Some(required) => check_rsa_key(key, algorithm, required),
+            None => Ok(())
+        }
+    }
+
+    fn check_header(&self, header: &Header) -> Result<()> {
+        if self.reject_embedded_keys {
+            for name in EMBEDDED_KEY_HEADERS.iter() {
+                let present = match *name {
+                    "jku" => header.jku.is_some(),
+                    "x5u" => header.x5u.is_some(),
+                    _ => header.get::<::serde_json::Value>(name).is_some()
+                };
+                if present {
+                    return Err(Error::ForbiddenHeaderParameter(name.to_string()));
+                }
+            }
+        }
+        let typ_ok = match (&self.typ, &header.typ) {
+            (&Some(ref expected), &Some(ref found)) => expected.matches(found),
+            (&Some(_), &None) => false,
+            // Explicit typing means a specific media type; the generic "JWT" that
+            // every token carries cannot tell one kind of token from another (RFC 8725, 3.11).
+            (&None, &Some(ref found)) => !self.require_typ || !Typ::JWT.matches(found),
+            (&None, &None) => !self.require_typ,
+        };
+        if !typ_ok {
+            return Err(Error::UnexpectedTyp { found: header.typ.clone() });
+        }
+        Ok(())
+    }
+}
+
+// Everything checked before the signature: the algorithm against the
+// allow-list, the key's strength and the header.
+pub fn check_before_signature(validation: &Validation, header: &Header, key: &[u8], algorithm: &ALGORITHM) -> Result<()> {
+    if (validation.require_algorithms || !validation.algorithms.is_empty()) && !validation.algorithms.contains(algorithm) {
+        return Err(Error::AlgorithmNotAllowed(algorithm.name().to_owned()));
+    }
+    try!(validation.check_key(key, algorithm));
+    validation.check_header(header)
+}
+
+#[cfg(test)]
+fn timed_claims(exp: Option<u64>, nbf: Option<u64>, iat: Option<u64>) -> Claims {
+    let mut claims = Claims::new();
+    claims.exp = exp;
+    claims.nbf = nbf;
+    claims.iat = iat;
+    claims
+}
+
+#[test]
+fn temporal_checks_allow_for_leeway() {
+    let validation = Validation::new();
+    assert!(validation.check(&timed_claims(Some(1000), None, None), 1059).is_ok());
+    assert_err!(validation.check(&timed_claims(Some(1000), None, None), 1060), Error::TokenExpired { exp: 1000, now: 1060 });
+    assert!(validation.check(&timed_claims(None, Some(1060), None), 1000).is_ok());
+    assert_err!(validation.check(&timed_claims(None, Some(1061), None), 1000), Error::TokenNotYetValid { nbf: 1061, now: 1000 });
+    assert!(validation.check(&timed_claims(None, None, Some(5000)), 1000).is_ok());
+    let mut with_iat = Validation::new();
+    with_iat.validate_iat = true;
+    assert_err!(with_iat.check(&timed_claims(None, None, Some(5000)), 1000), Error::IssuedInFuture { skew: 4000 });
+    with_iat.leeway = 5000;
+    assert!(with_iat.check(&timed_claims(None, None, Some(5000)), 1000).is_err());
+    with_iat.iat_leeway = 4000;
+    assert!(with_iat.check(&timed_claims(None, None, Some(5000)), 1000).is_ok());
+    assert!(Validation::signature_only().check(&timed_claims(Some(1), Some(5000), Some(5000)), 1000).is_ok());
+}
+
+#[test]
+fn temporal_checks_read_an_injected_clock() {
+    use clock::{FixedClock, OffsetClock};
+
+    let token = strict_token(Header::new(), timed_claims(Some(2000), Some(1500), None));
+    let decode = |now: u64| ::jws::JWS::decode_jwt_with_clock(token.clone(), ::TEST_HS256_SECRET, ALGORITHM::HS256, &Validation::new(), &OffsetClock { clock: FixedClock(1000), offset: now as i64 - 1000 });
+    assert_err!(decode(1000), Error::TokenNotYetValid { nbf: 1500, now: 1000 });
+    assert!(decode(1700).is_ok());
+    assert_err!(decode(2100), Error::TokenExpired { exp: 2000, now: 2100 });
+    let options = Validation::new();
+    assert_err!(options.decode_with_clock(&token, ::TEST_HS256_SECRET, ALGORITHM::HS256, &FixedClock(1000)), Error::TokenNotYetValid { nbf: 1500, now: 1000 });
+    assert!(options.decode_with_clock(&token, ::TEST_HS256_SECRET, ALGORITHM::HS256, &FixedClock(1700)).is_ok());
+    assert_err!(options.decode_with_clock(&token, ::TEST_HS256_SECRET, ALGORITHM::HS256, &FixedClock(5000)), Error::TokenExpired { exp: 2000, now: 5000 });
+    assert!(Validation::new().check_with_clock(&timed_claims(Some(2000), None, None), &FixedClock(1000)).is_ok());
+}
+
+#[test]
+fn tokens_older_than_the_max_age_are_rejected_before_exp() {
+    let mut validation = Validation::new();
+    validation.max_age = Some(300);
+    let fresh = timed_claims(Some(10000), None, Some(1000));
+    assert!(validation.check(&fresh, 1360).is_ok());
+    assert_err!(validation.check(&fresh, 1361), Error::TokenTooOld { iat: 1000, max_age: 300, now: 1361 });
+    assert_err!(validation.check(&timed_claims(Some(10000), None, None), 1000), Error::MissingClaims(missing) => assert_eq!(missing, vec!["iat".to_owned()]));
+
+    let token = strict_token(Header::new(), fresh);
+    assert!(validation.decode_at(&token, ::TEST_HS256_SECRET, ALGORITHM::HS256, 1360).is_ok());
+    assert_err!(validation.decode_at(&token, ::TEST_HS256_SECRET, ALGORITHM::HS256, 1361), Error::TokenTooOld { .. });
+}
+
+#[test]
+fn one_time_tokens_are_rejected_when_replayed() {
+    use replay::MemoryReplayStore;
+
+    let store = Arc::new(Mutex::new(MemoryReplayStore::new()));
+    let validation = Validation::new().with_replay_store(store.clone());
+    let mut claims = timed_claims(Some(2000), None, None);
+    claims.jti = Some("assertion-1".to_owned());
+    let token = strict_token(Header::new(), claims);
+
+    assert!(::jws::JWS::decode_jwt_at(token.clone(), ::TEST_HS256_SECRET, ALGORITHM::HS256, &validation, 1000).is_ok());
+    assert_err!(::jws::JWS::decode_jwt_at(token.clone(), ::TEST_HS256_SECRET, ALGORITHM::HS256, &validation.clone(), 1001), Error::TokenReplayed);
+    assert!(::jws::JWS::decode_jwt_at(token, b"wrong", ALGORITHM::HS256, &validation, 1001).is_err());
+    assert_eq!(store.lock().unwrap().len(), 1);
+
+    assert_err!(validation.check(&timed_claims(None, None, None), 1000), Error::MissingClaims(missing) => assert_eq!(missing, vec!["jti".to_owned(), "exp".to_owned()]));
+}
+
+#[test]
+fn report_all_collects_every_failed_check() {
+    let mut validation = Validation::new()
+        .require(&["sub"])
+        .with_validator(|_: &Claims| Err(Error::ClaimRejected("tenant suspended".to_owned())));
+    validation.audience = Some("api".to_owned());
+    validation.report_all = true;
+    let mut claims = timed_claims(Some(900), None, None);
+    claims.aud = Some("billing".into());
+
+    let err = assert_err!(validation.check(&claims, 1000), Error::ValidationFailed(err) => err);
+    let reasons: Vec<&str> = err.failures().iter().map(|failure| failure.reject_reason().as_str()).collect();
+    assert_eq!(reasons, vec!["missing_claims", "expired", "invalid_claims", "invalid_claims"]);
+    assert!(err.to_string().starts_with("4 checks failed: "));
+    assert!(err.to_string().contains("tenant suspended"));
+
+    validation.report_all = false;
+    assert_err!(validation.check(&claims, 1000), Error::MissingClaims(_));
+}
+
+#[test]
+fn an_allow_list_accepts_any_of_its_algorithms() {
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let private = key.private_key_to_pem_pkcs8().unwrap();
+    let public = key.public_key_to_pem().unwrap();
+    let token = |alg: ALGORITHM, key: &[u8]| {
+        let mut header = Header::new();
+        header.alg = alg.clone();
+        let jws = ::jws::JWS::from_claims(header, Claims::new());
+        match alg {
+            // The forgery: the public key used as an HMAC secret.
+            ALGORITHM::HS256 => jws.attach_signature(&signing::hmac_256(key, jws.signing_input().unwrap().as_bytes()).unwrap()).unwrap(),
+            _ => jws.encode(key, alg).unwrap()
+        }
+    };
+
+    let mut options = Validation::new();
+    options.algorithms = vec![ALGORITHM::RS256, ALGORITHM::PS256];
+    assert!(options.decode_allowed_at(&token(ALGORITHM::RS256, &private), &public, 1000).is_ok());
+    assert!(options.decode_allowed_at(&token(ALGORITHM::PS256, &private), &public, 1000).is_ok());
+    assert_err!(options.decode_allowed_at(&token(ALGORITHM::HS256, &public), &public, 1000), Error::AlgorithmNotAllowed(alg) => assert_eq!(alg, "HS256"));
+    assert!(Validation::new().decode_allowed_at(&token(ALGORITHM::RS256, &private), &public, 1000).is_err());
+
+    options.algorithms.push(ALGORITHM::HS256);
+    assert_err!(options.decode_allowed_at(&token(ALGORITHM::HS256, &public), &public, 1000), Error::AlgorithmNotAllowed(reason) => assert!(reason.contains("HMAC")));
+    let validation = Validation::signature_only();
+    assert!(::jws::JWS::decode_jwt_with_algorithms(token(ALGORITHM::PS256, &private), &public, &[ALGORITHM::RS256, ALGORITHM::PS256], &validation).is_ok());
+}
+
+#[test]
+fn hmac_secrets_must_be_as_long_as_the_hash() {
+    let token = strict_token(Header::new(), Claims::new());
+    let short = &::TEST_HS256_SECRET[..31];
+    let unsigned = ::jws::JWS::from_claims(Header::new(), Claims::new());
+    assert_err!(unsigned.encode(short, ALGORITHM::HS256), Error::WeakKey { alg: ALGORITHM::HS256, bits: 248, required: 256 });
+    let short_key = ::simple::EncodingKey::from_key(&::simple::HmacSecret::new(short).unwrap(), ALGORITHM::HS256).unwrap();
+    let short_token = unsigned.encode_with_key(&short_key.allow_short_hmac_key()).unwrap();
+    assert_err!(Validation::new().decode_at(&short_token, short, ALGORITHM::HS256, 1000), Error::WeakKey { alg: ALGORITHM::HS256, bits: 248, required: 256 });
+    assert_err!(::jws::JWS::decode_jwt_with_limits(short_token.clone(), short, ALGORITHM::HS256, &Limits::new()), Error::WeakKey { .. });
+    assert!(Validation::new().decode_at(&token, ::TEST_HS256_SECRET, ALGORITHM::HS256, 1000).is_ok());
+    assert!(check_hmac_key(::TEST_HS256_SECRET, &ALGORITHM::HS384).is_err());
+
+    let mut legacy = Validation::new();
+    legacy.allow_short_hmac_keys = true;
+    assert!(legacy.decode_at(&short_token, short, ALGORITHM::HS256, 1000).is_ok());
+}
+
+#[test]
+fn oversized_tokens_are_rejected_before_decoding() {
+    let mut validation = Validation::new();
+    validation.limits.max_token_size = 64;
+    let mut claims = Claims::new();
+    claims.set("padding", "x".repeat(64));
+    let token = strict_token(Header::new(), claims);
+    assert_err!(::jws::JWS::decode_jwt_at(token.clone(), ::TEST_HS256_SECRET, ALGORITHM::HS256, &validation, 1000), Error::TokenTooLarge { limit: 64, .. });
+    assert!(::jws::JWS::decode_jwt_at(token, ::TEST_HS256_SECRET, ALGORITHM::HS256, &Validation::new(), 1000).is_ok());
+    let garbage = "!".repeat(65);
+    assert_err!(::jws::JWS::decode_jwt_at(garbage, ::TEST_HS256_SECRET, ALGORITHM::HS256, &validation, 1000), Error::TokenTooLarge { size: 65, limit: 64 });
+}
+
+#[test]
+fn every_missing_required_claim_is_reported() {
+    let validation = Validation::new().require(&["sub", "tenant", "exp"]);
+    let mut claims = timed_claims(Some(2000), None, None);
+    assert_err!(validation.check(&claims, 1000), Error::MissingClaims(missing) => assert_eq!(missing, vec!["sub".to_owned(), "tenant".to_owned()]));
+    claims.sub = Some("user".to_owned());
+    claims.set("tenant", "acme");
+    assert!(validation.check(&claims, 1000).is_ok());
+
+    let token = strict_token(Header::new(), timed_claims(None, None, None));
+    assert_err!(::jws::JWS::decode_jwt_at(token, ::TEST_HS256_SECRET, ALGORITHM::HS256, &validation, 1000), Error::MissingClaims(missing) => assert_eq!(missing.len(), 3));
+}
+
+#[cfg(test)]
+struct RequiredScope(&'static str);
+
+#[cfg(test)]
+impl ClaimValidator for RequiredScope {
+    fn validate(&self, claims: &Claims) -> Result<()> {
+        let scopes: String = claims.get("scope").unwrap_or_default();
+        if scopes.split(' ').any(|scope| scope == self.0) {
+            Ok(())
+        } else {
+            Err(Error::ClaimRejected(format!("the {} scope is missing", self.0)))
+        }
+    }
+}
+
+#[test]
+fn custom_validators_run_after_the_built_in_checks() {
+    let validation = Validation::new()
+        .with_validator(|claims: &Claims| match claims.get::<String>("tenant") {
+            Some(ref tenant) if tenant == "acme" => Ok(()),
+            _ => Err(Error::ClaimMismatch { claim: "tenant" })
+        })
+        .with_validator(RequiredScope("write"));
+    let mut claims = timed_claims(Some(2000), None, None);
+    claims.set("tenant", "acme");
+    claims.set("scope", "read write");
+    let token = |claims: Claims| strict_token(Header::new(), claims);
+    assert!(::jws::JWS::decode_jwt_at(token(claims.clone()), ::TEST_HS256_SECRET, ALGORITHM::HS256, &validation, 1000).is_ok());
+
+    claims.set("scope", "read");
+    assert_err!(::jws::JWS::decode_jwt_at(token(claims.clone()), ::TEST_HS256_SECRET, ALGORITHM::HS256, &validation, 1000), Error::ClaimRejected(reason) => assert!(reason.contains("write")));
+    claims.set("tenant", "other");
+    assert_err!(::jws::JWS::decode_jwt_at(token(claims.clone()), ::TEST_HS256_SECRET, ALGORITHM::HS256, &validation, 1000), Error::ClaimMismatch { claim: "tenant" });
+    assert_err!(::jws::JWS::decode_jwt_at(token(claims), ::TEST_HS256_SECRET, ALGORITHM::HS256, &validation, 3000), Error::TokenExpired { .. });
+    assert_eq!(validation.clone(), validation);
+    assert!(validation != Validation::new());
+}
+
+#[test]
+fn a_configured_audience_must_be_among_the_token_audiences() {
+    let mut validation = Validation::new();
+    validation.audience = Some("api".to_owned());
+    let mut claims = timed_claims(None, None, None);
+    assert_err!(validation.check(&claims, 1000), Error::MissingClaims(missing) => assert_eq!(missing, vec!["aud".to_owned()]));
+    claims.aud = Some(vec!["billing".to_owned(), "api".to_owned()].into());
+    assert!(validation.check(&claims, 1000).is_ok());
+    claims.aud = Some("billing".into());
+    assert_err!(validation.check(&claims, 1000), Error::ClaimMismatch { claim: "aud" });
+    assert!(Validation::new().check(&claims, 1000).is_ok());
+
+    let mut options = Validation::new();
+    options.audience = Some("api".to_owned());
+    claims.aud = Some(vec!["api".to_owned(), "billing".to_owned()].into());
+    assert!(options.decode_at(&strict_token(Header::new(), claims), ::TEST_HS256_SECRET, ALGORITHM::HS256, 1000).is_ok());
+}
+
+#[cfg(test)]
+fn strict_token(header: Header, claims: Claims) -> String {
+    ::jws::JWS::from_claims(header, claims).encode(::TEST_HS256_SECRET, ALGORITHM::HS256).unwrap()
+}
+
+#[cfg(test)]
+fn strict_header() -> Header {
+    let mut header = Header::new();
+    header.typ = Some("at+jwt".to_owned());
+    header
+}
+
+#[cfg(test)]
+fn strict_claims() -> Claims {
+    let mut claims = Claims::new();
+    claims.iss = Some("https://issuer.example".to_owned());
+    claims.aud = Some("api".into());
+    claims.iat = Some(1000);
+    claims
+}
+
+#[test]
+fn strict_mode_accepts_a_well_formed_token() {
+    let mut options = Validation::rfc8725_strict();
+    options.algorithms = vec![ALGORITHM::HS256];
+    options.typ = Some(Typ::AT_JWT);
+    options.issuer = Some("https://issuer.example".to_owned());
+    options.audience = Some("api".to_owned());
+    let (header, claims) = options.decode_at(&strict_token(strict_header(), strict_claims()), ::TEST_HS256_SECRET, ALGORITHM::HS256, 1000).unwrap();
+    assert_eq!(header.typ, Some("at+jwt".to_owned()));
+    assert_eq!(claims.aud, Some("api".into()));
+}
+
+#[test]
+fn strict_mode_requires_an_algorithm_allow_list() {
+    let token = strict_token(strict_header(), strict_claims());
+    assert!(Validation::new().decode_at(&token, ::TEST_HS256_SECRET, ALGORITHM::HS256, 1000).is_ok());
+    assert_err!(Validation::rfc8725_strict().decode_at(&token, ::TEST_HS256_SECRET, ALGORITHM::HS256, 1000), Error::AlgorithmNotAllowed(alg) => assert_eq!(alg, "HS256"));
+}
+
+#[test]
+fn strict_mode_rejects_each_best_practice_violation() {
+    let mut options = Validation::rfc8725_strict();
+    options.algorithms = vec![ALGORITHM::HS256];
+
+    let mut generic = strict_header();
+    generic.typ = None;
+    assert_err!(options.decode_at(&strict_token(generic, strict_claims()), ::TEST_HS256_SECRET, ALGORITHM::HS256, 1000), Error::UnexpectedTyp { found: Some(typ) } => assert_eq!(typ, "JWT"));
+
+    let mut pinned = options.clone();
+    pinned.typ = Some(Typ::DPOP_JWT);
+    assert_err!(pinned.decode_at(&strict_token(strict_header(), strict_claims()), ::TEST_HS256_SECRET, ALGORITHM::HS256, 1000), Error::UnexpectedTyp { found: Some(typ) } => assert_eq!(typ, "at+jwt"));
+
+    let mut embedded = strict_header();
+    embedded.jku = Some("https://attacker.example/jwks".to_owned());
+    assert_err!(options.decode_at(&strict_token(embedded, strict_claims()), ::TEST_HS256_SECRET, ALGORITHM::HS256, 1000), Error::ForbiddenHeaderParameter(name) => assert_eq!(name, "jku"));
+
+    let mut anonymous = strict_claims();
+    anonymous.aud = None;
+    assert_err!(options.decode_at(&strict_token(strict_header(), anonymous), ::TEST_HS256_SECRET, ALGORITHM::HS256, 1000), Error::MissingClaims(claims) => assert_eq!(claims, vec!["aud".to_owned()]));
+
+    assert_err!(options.decode_at(&strict_token(strict_header(), strict_claims()), ::TEST_HS256_SECRET, ALGORITHM::HS256, 900), Error::IssuedInFuture { skew: 100 });
+
+    options.limits.max_token_size = 16;
+    assert_err!(options.decode_at(&strict_token(strict_header(), strict_claims()), ::TEST_HS256_SECRET, ALGORITHM::HS256, 1000), Error::TokenTooLarge { .. });
+}
@@ -0,0 +1,133 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+use std::slice;
+use serde_json::Value;
+use smallvec::SmallVec;
+
+const INLINE_ENTRIES: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct ValueMap {
+    entries: SmallVec<[(String, Value); INLINE_ENTRIES]>,
+    preserve_order: bool,
+}
+
+impl ValueMap {
+    pub fn new() -> ValueMap {
+        ValueMap {
+            entries: SmallVec::new(),
+            preserve_order: false,
+        }
+    }
+
+    pub fn with_insertion_order() -> ValueMap {
+        ValueMap {
+            entries: SmallVec::new(),
+            preserve_order: true,
+        }
+    }
+
+    pub fn preserves_order(&self) -> bool {
+        self.preserve_order
+    }
+
+    fn position(&self, key: &str) -> Result<usize, usize> {
+        if self.preserve_order {
+            return self.entries.iter().position(|&(ref k, _)| k == key).ok_or(self.entries.len());
+        }
+        match self.entries.last() {
+            Some(&(ref last, _)) if last.as_str() < key => Err(self.entries.len()),
+            _ => self.entries.binary_search_by(|&(ref k, _)| k.as_str().cmp(key))
+        }
+    }
+
+    pub fn insert(&mut self, key: String, value: Value) -> Option<Value> {
+        match self.position(&key) {
+            Ok(i) => Some(::std::mem::replace(&mut self.entries[i].1, value)),
+            Err(i) => {
+                self.entries.insert(i, (key, value));
+                None
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref value)| value)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.position(key).ok().map(|i| self.entries.remove(i).1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter {
+        Iter { inner: self.entries.iter() }
+    }
+
+    pub fn to_object(&self) -> BTreeMap<String, Value> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+impl PartialEq for ValueMap {
+    fn eq(&self, other: &ValueMap) -> bool {
+        self.len() == other.len() && self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl FromIterator<(String, Value)> for ValueMap {
+    fn from_iter<I: IntoIterator<Item = (String, Value)>>(iter: I) -> ValueMap {
+        let mut map = ValueMap::new();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+pub struct Iter<'a> {
+    inner: slice::Iter<'a, (String, Value)>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a String, &'a Value);
+
+    fn next(&mut self) -> Option<(&'a String, &'a Value)> {
+        self.inner.next().map(|&(ref key, ref value)| (key, value))
+    }
+}
+
+#[test]
+fn entries_are_kept_sorted_and_replaced_by_key() {
+    let mut map = ValueMap::new();
+    assert_eq!(map.insert("b".to_owned(), Value::U64(1)), None);
+    assert_eq!(map.insert("a".to_owned(), Value::U64(2)), None);
+    assert_eq!(map.insert("b".to_owned(), Value::U64(3)), Some(Value::U64(1)));
+    let keys: Vec<&String> = map.iter().map(|(key, _)| key).collect();
+    assert_eq!(keys, vec!["a", "b"]);
+    assert_eq!(map.get("b"), Some(&Value::U64(3)));
+    assert_eq!(map.remove("a"), Some(Value::U64(2)));
+    assert_eq!(map.len(), 1);
+}
+
+#[test]
+fn insertion_order_can_be_preserved() {
+    let mut map = ValueMap::with_insertion_order();
+    map.insert("b".to_owned(), Value::U64(1));
+    map.insert("a".to_owned(), Value::U64(2));
+    map.insert("b".to_owned(), Value::U64(3));
+    let keys: Vec<&String> = map.iter().map(|(key, _)| key).collect();
+    assert_eq!(keys, vec!["b", "a"]);
+
+    let sorted: ValueMap = vec![("a".to_owned(), Value::U64(2)), ("b".to_owned(), Value::U64(3))].into_iter().collect();
+    assert_eq!(map, sorted);
+}
@@ -0,0 +1,96 @@
+#![allow(dead_code)]
+
+use clock::{Clock, SystemClock};
+use jws::{JWS, ParsedToken, base64_url_encode_bytes};
+use jws_header::{Header, ALGORITHM};
+use error::{Error, Result};
+
+const TIMESTAMP_PARAM: &'static str = "t";
+
+fn signed_payload(timestamp: u64, body: &[u8]) -> Vec<u8> {
+    let mut payload = format!("{}.", timestamp).into_bytes();
+    payload.extend_from_slice(body);
+    payload
+}
+
+pub fn sign_webhook(body: &[u8], secret: &[u8], timestamp: u64) -> Result<String> {
+    let mut header = Header::new();
+    header.alg = ALGORITHM::HS256;
+    header.set(TIMESTAMP_PARAM, timestamp);
+    let token = try!(JWS::from_custom(header, signed_payload(timestamp, body)).encode(secret, ALGORITHM::HS256));
+    let parts: Vec<&str> = token.split('.').collect();
+    Ok(format!("{}..{}", parts[0], parts[2]))
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct WebhookVerifier {
+    secrets: Vec<Vec<u8>>,
+    pub tolerance: u64,
+}
+
+impl WebhookVerifier {
+    pub fn new(secret: &[u8]) -> WebhookVerifier {
+        WebhookVerifier {
+            secrets: vec![secret.to_vec()],
+            tolerance: 300,
+        }
+    }
+
+    pub fn add_secret(&mut self, secret: &[u8]) {
+        self.secrets.push(secret.to_vec());
+    }
+
+    pub fn verify(&self, signature: &str, body: &[u8]) -> Result<u64> {
+        self.verify_with_clock(signature, body, &SystemClock)
+    }
+
+    pub fn verify_with_clock<C: Clock>(&self, signature: &str, body: &[u8], clock: &C) -> Result<u64> {
+        let parts: Vec<&str> = signature.split('.').collect();
+        if parts.len() != 3 || !parts[1].is_empty() {
+            return Err(Error::MalformedToken);
+        }
+        let header = try!(ParsedToken::parse(signature)).header().clone();
+        let timestamp: u64 = match header.get(TIMESTAMP_PARAM) {
+            Some(timestamp) => timestamp,
+            None => return Err(Error::MalformedToken)
+        };
+        let now = clock.now();
+        let skew = if now > timestamp { now - timestamp } else { timestamp - now };
+        if skew > self.tolerance {
+            return Err(Error::StaleTimestamp { timestamp: timestamp, now: now });
+        }
+        let payload = base64_url_encode_bytes(signed_payload(timestamp, body).as_slice());
+        let parsed = try!(ParsedToken::parse(&format!("{}.{}.{}", parts[0], payload, parts[2])));
+        for secret in self.secrets.iter() {
+            if parsed.verify_raw(secret.as_slice(), ALGORITHM::HS256).is_ok() {
+                return Ok(timestamp);
+            }
+        }
+        Err(Error::JWSInvalidSignature)
+    }
+}
+
+#[test]
+fn webhook_signatures_verify_within_tolerance() {
+    use clock::FixedClock;
+
+    let body = br#"{"event":"invoice.paid"}"#;
+    let signature = sign_webhook(body, b"whsec_new_0123456789abcdef0123456789", 1000).unwrap();
+    assert!(signature.contains(".."));
+
+    let mut verifier = WebhookVerifier::new(b"whsec_old_0123456789abcdef0123456789");
+    verifier.add_secret(b"whsec_new_0123456789abcdef0123456789");
+    assert_eq!(verifier.verify_with_clock(&signature, body, &FixedClock(1200)).unwrap(), 1000);
+
+    assert_err!(verifier.verify_with_clock(&signature, body, &FixedClock(1400)), Error::StaleTimestamp { timestamp: 1000, now: 1400 });
+}
+
+#[test]
+fn webhook_signatures_reject_modified_bodies_and_unknown_secrets() {
+    use clock::FixedClock;
+
+    let signature = sign_webhook(b"original", b"whsec_0123456789abcdef0123456789abcdef", 1000).unwrap();
+    let verifier = WebhookVerifier::new(b"whsec_0123456789abcdef0123456789abcdef");
+    assert!(verifier.verify_with_clock(&signature, b"tampered", &FixedClock(1000)).is_err());
+    assert!(WebhookVerifier::new(b"whsec_other_0123456789abcdef01234567").verify_with_clock(&signature, b"original", &FixedClock(1000)).is_err());
+}